@@ -0,0 +1,161 @@
+use crate::error::Result;
+use crate::one_game::Tags;
+use crate::output::UnresolvedEntry;
+use crate::search::filter_games;
+use crate::{models::Game, scraper::Scraper};
+use std::fs;
+use std::path::Path;
+
+/// How a line of a list file was matched against the catalog.
+#[derive(Debug, Clone)]
+pub enum Resolution {
+    /// Exactly one game matched
+    Found(Game),
+    /// Several games matched; the line must be made more specific
+    Ambiguous(Vec<Game>),
+    /// Nothing matched
+    Missing,
+    /// The line is a serial, but looking it up on Redump failed (network or page error)
+    LookupFailed(String),
+}
+
+/// A non-empty, non-comment line of a list file and its resolution.
+#[derive(Debug, Clone)]
+pub struct BatchEntry {
+    /// 1-based line number in the list file
+    pub line: usize,
+    /// The line as written (trimmed)
+    pub text: String,
+    /// Result of matching the line against the catalog
+    pub resolution: Resolution,
+}
+
+impl BatchEntry {
+    /// Describes an entry that did not resolve to exactly one game.
+    pub fn unresolved(&self) -> Option<UnresolvedEntry> {
+        let (candidates, error) = match &self.resolution {
+            Resolution::Found(_) => return None,
+            Resolution::Ambiguous(games) => (games.iter().map(|game| game.clean_title()).collect(), None),
+            Resolution::Missing => (Vec::new(), None),
+            Resolution::LookupFailed(error) => (Vec::new(), Some(error.clone())),
+        };
+        Some(UnresolvedEntry {
+            line: self.line,
+            text: self.text.clone(),
+            candidates,
+            error,
+        })
+    }
+}
+
+/// Reads a list file: one title, Redump serial or search query per line.
+/// Empty lines and lines starting with '#' are skipped.
+pub fn read_list(path: &Path) -> Result<Vec<(usize, String)>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+/// Resolves list lines against the catalog.
+///
+/// Each line is tried, in order, as an exact title (with or without '.zip'),
+/// as a Redump serial looked up on redump.org, and as a search query.
+pub async fn resolve(scraper: &Scraper, games: &[Game], lines: Vec<(usize, String)>) -> Vec<BatchEntry> {
+    let mut entries = Vec::with_capacity(lines.len());
+
+    for (line, text) in lines {
+        let resolution = match find_exact(games, &text) {
+            Some(game) => Resolution::Found(game.clone()),
+            None => match normalize_serial(&text) {
+                Some(serial) => resolve_serial(scraper, games, &serial).await,
+                None => from_matches(filter_games(games, &text)),
+            },
+        };
+        entries.push(BatchEntry {
+            line,
+            text,
+            resolution,
+        });
+    }
+
+    entries
+}
+
+/// Finds a game whose file name or clean title equals `text` (case-insensitive).
+fn find_exact<'a>(games: &'a [Game], text: &str) -> Option<&'a Game> {
+    games.iter().find(|game| {
+        game.title.eq_ignore_ascii_case(text) || game.clean_title().eq_ignore_ascii_case(text)
+    })
+}
+
+/// Turns a list of matches into a resolution.
+fn from_matches(matches: Vec<&Game>) -> Resolution {
+    match matches.as_slice() {
+        [] => Resolution::Missing,
+        [game] => Resolution::Found((*game).clone()),
+        _ => Resolution::Ambiguous(matches.into_iter().cloned().collect()),
+    }
+}
+
+/// Looks a serial up on Redump and matches the returned titles against the catalog by base title.
+/// Redump lists titles without their region, so releases sharing a base title are narrowed down to
+/// the region of the serial's prefix.
+async fn resolve_serial(scraper: &Scraper, games: &[Game], serial: &str) -> Resolution {
+    let titles = match scraper.lookup_serial(serial).await {
+        Ok(titles) => titles,
+        Err(e) => return Resolution::LookupFailed(e.to_string()),
+    };
+    let wanted: Vec<String> = titles.iter().map(|title| normalize_title(title)).collect();
+    let matches: Vec<&Game> = games
+        .iter()
+        .filter(|game| wanted.contains(&normalize_title(&game.base_title())))
+        .collect();
+    let in_region: Vec<&Game> = match serial_region(serial) {
+        Some(region) => matches
+            .iter()
+            .copied()
+            .filter(|game| Tags::parse(&game.clean_title()).regions.iter().any(|tag| tag == region))
+            .collect(),
+        None => Vec::new(),
+    };
+    // A release missing from the region's catalog entries still shows up among the candidates
+    from_matches(if in_region.is_empty() { matches } else { in_region })
+}
+
+/// Region of the releases a serial prefix is used for, e.g. 'USA' for 'SLUS-20946'.
+fn serial_region(serial: &str) -> Option<&'static str> {
+    match serial.get(..4)? {
+        "SLUS" | "SCUS" => Some("USA"),
+        "SLES" | "SCES" => Some("Europe"),
+        "SLPS" | "SLPM" | "SCPS" => Some("Japan"),
+        "SLKA" | "SCKA" => Some("Korea"),
+        _ => None,
+    }
+}
+
+/// Normalizes a title for comparison: Redump display names use ':' where file names use ' -'.
+fn normalize_title(title: &str) -> String {
+    title.replace(": ", " - ").to_lowercase()
+}
+
+/// Recognizes PS2 disc serials such as 'SLUS-20946', 'SLUS_209.46' or 'sces 50490'
+/// and returns them in Redump's 'SLUS-20946' form.
+pub fn normalize_serial(text: &str) -> Option<String> {
+    let compact: String = text
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_' | '.' | ' '))
+        .collect::<String>()
+        .to_ascii_uppercase();
+    if compact.len() != 9 {
+        return None;
+    }
+    let (prefix, number) = compact.split_at(4);
+    if !prefix.chars().all(|c| c.is_ascii_alphabetic()) || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{}-{}", prefix, number))
+}
@@ -0,0 +1,1145 @@
+use crate::cue::{self, CueTrack, TrackKind, SECTOR_SIZE};
+use crate::error::{Error, Result};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use sha1::{Digest, Sha1};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+/// Size of the CHD v5 header.
+const HEADER_SIZE: u64 = 124;
+/// Size of a CD frame in a CHD: a raw sector followed by its subcode.
+const FRAME_SIZE: usize = 2448;
+/// Subcode bytes per frame (always zero, BIN images carry none).
+const SUBCODE_SIZE: usize = 96;
+/// CD frames per hunk.
+const FRAMES_PER_HUNK: usize = 8;
+/// CD tracks are padded to a multiple of this many frames.
+const TRACK_PADDING: u64 = 4;
+/// Hunk size of DVD images (two 2048-byte sectors).
+const DVD_HUNK_SIZE: usize = 4096;
+/// Sector size of DVD images.
+const DVD_SECTOR_SIZE: u32 = 2048;
+/// Hunks read and compressed per batch.
+const BATCH_HUNKS: usize = 256;
+/// Size of a map entry once decoded.
+const MAP_ENTRY_SIZE: usize = 12;
+/// Size of the compressed map header.
+const MAP_HEADER_SIZE: usize = 16;
+/// Map entry type: hunk compressed with the first codec.
+const COMPRESSION_TYPE_0: u8 = 0;
+/// Map entry type: hunk stored uncompressed.
+const COMPRESSION_NONE: u8 = 4;
+/// Map entry types used to run-length encode the types.
+const COMPRESSION_RLE_SMALL: u8 = 7;
+const COMPRESSION_RLE_LARGE: u8 = 8;
+/// Metadata flag: the entry is part of the overall SHA-1.
+const METADATA_CHECKSUM: u8 = 0x01;
+/// Size of a metadata entry header.
+const METADATA_HEADER_SIZE: usize = 16;
+
+const CODEC_ZLIB: u32 = u32::from_be_bytes(*b"zlib");
+const CODEC_CD_ZLIB: u32 = u32::from_be_bytes(*b"cdzl");
+const TAG_DVD: u32 = u32::from_be_bytes(*b"DVD ");
+const TAG_CD_TRACK: u32 = u32::from_be_bytes(*b"CHT2");
+
+/// Sizes and checksums of a written CHD.
+#[derive(Debug, Clone)]
+pub struct Converted {
+    /// Size of the uncompressed CHD data in bytes
+    pub logical_size: u64,
+    /// Size of the CHD file in bytes
+    pub size: u64,
+    /// CRC32 of the uncompressed CHD data (the ISO itself for DVD images)
+    pub crc32: u32,
+    /// SHA-1 of the data and metadata, as shown by `chdman info`
+    pub sha1: String,
+}
+
+/// Path of the CHD written next to an ISO or cue sheet.
+pub fn output_path(source: &Path) -> PathBuf {
+    source.with_extension("chd")
+}
+
+/// Whether `source` is a cue sheet rather than an ISO.
+fn is_cue(source: &Path) -> bool {
+    source
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("cue"))
+}
+
+/// Files making up a source image: the ISO, or the cue sheet and its BIN files.
+pub fn source_files(source: &Path) -> Result<Vec<PathBuf>> {
+    if !is_cue(source) {
+        return Ok(vec![source.to_path_buf()]);
+    }
+    let mut files = vec![source.to_path_buf()];
+    files.extend(cue::files(&cue::parse(source)?));
+    Ok(files)
+}
+
+/// Bytes of image data in a source, used as the total for `convert` progress.
+pub fn source_size(source: &Path) -> Result<u64> {
+    if !is_cue(source) {
+        return Ok(fs::metadata(source)?.len());
+    }
+    Ok(cue::parse(source)?.iter().map(|track| track.frames * SECTOR_SIZE).sum())
+}
+
+/// Converts an ISO (DVD layout) or a BIN/CUE set (CD layout) into a CHD v5 file,
+/// using `threads` worker threads (0 uses every core). Hunks are zlib ('zlib' or 'cdzl')
+/// compressed; the lzma and flac codecs of chdman are not written.
+/// `on_progress` receives the number of source bytes processed so far.
+/// Cancelling removes the partial output.
+pub fn convert(
+    source: &Path,
+    output: &Path,
+    threads: usize,
+    cancel: &CancellationToken,
+    on_progress: impl FnMut(u64),
+) -> Result<Converted> {
+    let result = write_chd(source, output, threads, cancel, on_progress);
+    if result.is_err() {
+        let _ = fs::remove_file(output);
+    }
+    result
+}
+
+/// Hunk layout of the image being written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// 2048-byte sectors, 'zlib' codec
+    Dvd,
+    /// 2448-byte frames, 'cdzl' codec
+    Cd,
+}
+
+impl Layout {
+    fn hunk_size(self) -> usize {
+        match self {
+            Layout::Dvd => DVD_HUNK_SIZE,
+            Layout::Cd => FRAME_SIZE * FRAMES_PER_HUNK,
+        }
+    }
+
+    fn unit_size(self) -> u32 {
+        match self {
+            Layout::Dvd => DVD_SECTOR_SIZE,
+            Layout::Cd => FRAME_SIZE as u32,
+        }
+    }
+
+    fn codec(self) -> u32 {
+        match self {
+            Layout::Dvd => CODEC_ZLIB,
+            Layout::Cd => CODEC_CD_ZLIB,
+        }
+    }
+}
+
+/// Image data fed to the writer.
+enum Source {
+    Dvd(BufReader<File>),
+    Cd(CdFrames),
+}
+
+impl Source {
+    /// Fills `buffer` with data; returns the bytes read (0 at the end).
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        match self {
+            Source::Dvd(reader) => Ok(read_full(reader, buffer)?),
+            Source::Cd(frames) => {
+                let mut filled = 0;
+                while filled < buffer.len() && frames.next_frame(&mut buffer[filled..filled + FRAME_SIZE])? {
+                    filled += FRAME_SIZE;
+                }
+                Ok(filled)
+            }
+        }
+    }
+}
+
+/// Produces the CHD frames of a BIN/CUE set: each raw sector followed by empty subcode,
+/// audio swapped to big-endian and every track padded to a multiple of four frames.
+struct CdFrames {
+    tracks: Vec<CueTrack>,
+    track: usize,
+    frame: u64,
+    file: Option<(PathBuf, BufReader<File>)>,
+    /// Source bytes read so far
+    consumed: u64,
+}
+
+impl CdFrames {
+    fn next_frame(&mut self, frame: &mut [u8]) -> Result<bool> {
+        loop {
+            let Some(track) = self.tracks.get(self.track) else {
+                return Ok(false);
+            };
+            if self.frame >= track.frames.next_multiple_of(TRACK_PADDING) {
+                self.track += 1;
+                self.frame = 0;
+                continue;
+            }
+            frame.fill(0);
+            if self.frame < track.frames {
+                if self.frame == 0 {
+                    if self.file.as_ref().is_none_or(|(path, _)| *path != track.file) {
+                        self.file = Some((track.file.clone(), BufReader::new(File::open(&track.file)?)));
+                    }
+                    let (_, reader) = self.file.as_mut().unwrap();
+                    reader.seek(SeekFrom::Start(track.start * SECTOR_SIZE))?;
+                }
+                let (_, reader) = self.file.as_mut().unwrap();
+                reader.read_exact(&mut frame[..SECTOR_SIZE as usize])?;
+                if track.kind == TrackKind::Audio {
+                    for sample in frame[..SECTOR_SIZE as usize].chunks_exact_mut(2) {
+                        sample.swap(0, 1);
+                    }
+                }
+                self.consumed += SECTOR_SIZE;
+            }
+            self.frame += 1;
+            return Ok(true);
+        }
+    }
+}
+
+/// A decoded map entry.
+#[derive(Debug, Clone, Copy)]
+struct MapEntry {
+    kind: u8,
+    length: u32,
+    offset: u64,
+    crc: u16,
+}
+
+fn write_chd(
+    source: &Path,
+    output: &Path,
+    threads: usize,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(u64),
+) -> Result<Converted> {
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let (layout, logical_size, metadata, mut input) = if is_cue(source) {
+        let tracks = cue::parse(source)?;
+        let frames: u64 = tracks.iter().map(|track| track.frames.next_multiple_of(TRACK_PADDING)).sum();
+        let metadata = tracks.iter().map(|track| (TAG_CD_TRACK, track_metadata(track))).collect();
+        let frames_source = CdFrames {
+            tracks,
+            track: 0,
+            frame: 0,
+            file: None,
+            consumed: 0,
+        };
+        (Layout::Cd, frames * FRAME_SIZE as u64, metadata, Source::Cd(frames_source))
+    } else {
+        let size = fs::metadata(source)?.len();
+        if size % DVD_SECTOR_SIZE as u64 != 0 {
+            return Err(Error::ImageInvalid(format!("{} is not a whole number of sectors", source.display())));
+        }
+        let metadata = vec![(TAG_DVD, b"\0".to_vec())];
+        (Layout::Dvd, size, metadata, Source::Dvd(BufReader::new(File::open(source)?)))
+    };
+
+    let hunk_size = layout.hunk_size();
+    let mut writer = BufWriter::new(File::create(output)?);
+    // The header is written once the map and checksums are known
+    writer.write_all(&[0u8; HEADER_SIZE as usize])?;
+
+    let mut map = Vec::with_capacity(logical_size.div_ceil(hunk_size as u64) as usize);
+    let mut position = HEADER_SIZE;
+    let mut raw_sha1 = Sha1::new();
+    let mut crc32 = crc32fast::Hasher::new();
+    let mut remaining = logical_size;
+    let mut buffer = vec![0u8; BATCH_HUNKS * hunk_size];
+
+    loop {
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        // Only the logical bytes count towards the checksums; the last hunk is zero padded
+        let logical = (read as u64).min(remaining) as usize;
+        raw_sha1.update(&buffer[..logical]);
+        crc32.update(&buffer[..logical]);
+        remaining -= logical as u64;
+        let filled = read.next_multiple_of(hunk_size);
+        buffer[read..filled].fill(0);
+
+        let hunks: Vec<&[u8]> = buffer[..filled].chunks(hunk_size).collect();
+        let compressed = compress_hunks(layout, &hunks, threads)?;
+        for (hunk, compressed) in hunks.iter().zip(compressed) {
+            let crc = crc16(hunk);
+            let (kind, data) = match &compressed {
+                Some(data) => (COMPRESSION_TYPE_0, data.as_slice()),
+                None => (COMPRESSION_NONE, *hunk),
+            };
+            writer.write_all(data)?;
+            map.push(MapEntry {
+                kind,
+                length: data.len() as u32,
+                offset: position,
+                crc,
+            });
+            position += data.len() as u64;
+        }
+        on_progress(match &input {
+            Source::Dvd(_) => logical_size - remaining,
+            Source::Cd(frames) => frames.consumed,
+        });
+    }
+
+    let map_offset = position;
+    let map_data = encode_map(&map, HEADER_SIZE);
+    writer.write_all(&map_data)?;
+    position += map_data.len() as u64;
+
+    let meta_offset = position;
+    for (index, (tag, data)) in metadata.iter().enumerate() {
+        let next = match index + 1 < metadata.len() {
+            true => position + (METADATA_HEADER_SIZE + data.len()) as u64,
+            false => 0,
+        };
+        writer.write_all(&tag.to_be_bytes())?;
+        writer.write_all(&[METADATA_CHECKSUM])?;
+        writer.write_all(&(data.len() as u32).to_be_bytes()[1..])?;
+        writer.write_all(&next.to_be_bytes())?;
+        writer.write_all(data)?;
+        position += (METADATA_HEADER_SIZE + data.len()) as u64;
+    }
+
+    let raw_sha1: [u8; 20] = raw_sha1.finalize().into();
+    let sha1 = overall_sha1(&raw_sha1, &metadata);
+    let header = Header {
+        compressors: [layout.codec(), 0, 0, 0],
+        logical_size,
+        map_offset,
+        meta_offset,
+        hunk_size: hunk_size as u32,
+        unit_size: layout.unit_size(),
+        raw_sha1,
+        sha1,
+    };
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&header.encode())?;
+    writer.flush()?;
+
+    Ok(Converted {
+        logical_size,
+        size: position,
+        crc32: crc32.finalize(),
+        sha1: hex(&sha1),
+    })
+}
+
+/// CD track metadata in chdman's CHT2 format.
+fn track_metadata(track: &CueTrack) -> Vec<u8> {
+    let pregap_type = match track.pregap {
+        0 => "MODE1".to_string(),
+        _ => format!("V{}", track.kind.chd_name()),
+    };
+    format!(
+        "TRACK:{} TYPE:{} SUBTYPE:NONE FRAMES:{} PREGAP:{} PGTYPE:{} PGSUB:RW POSTGAP:0\0",
+        track.number,
+        track.kind.chd_name(),
+        track.frames,
+        track.pregap,
+        pregap_type
+    )
+    .into_bytes()
+}
+
+/// SHA-1 over the data SHA-1 and the sorted hashes of the checksummed metadata.
+fn overall_sha1(raw_sha1: &[u8; 20], metadata: &[(u32, Vec<u8>)]) -> [u8; 20] {
+    let mut hashes: Vec<[u8; 24]> = metadata
+        .iter()
+        .map(|(tag, data)| {
+            let mut hash = [0u8; 24];
+            hash[..4].copy_from_slice(&tag.to_be_bytes());
+            hash[4..].copy_from_slice(&Sha1::digest(data));
+            hash
+        })
+        .collect();
+    hashes.sort();
+    let mut sha1 = Sha1::new();
+    sha1.update(raw_sha1);
+    for hash in &hashes {
+        sha1.update(hash);
+    }
+    sha1.finalize().into()
+}
+
+/// Compresses a batch of hunks, spreading them over `threads` threads.
+fn compress_hunks(layout: Layout, hunks: &[&[u8]], threads: usize) -> Result<Vec<Option<Vec<u8>>>> {
+    let per_thread = hunks.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = hunks
+            .chunks(per_thread)
+            .map(|group| {
+                scope.spawn(move || {
+                    group
+                        .iter()
+                        .map(|hunk| compress_hunk(layout, hunk))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        let mut compressed = Vec::with_capacity(hunks.len());
+        for handle in handles {
+            let group = handle
+                .join()
+                .map_err(|_| Error::Io(io::Error::other("compression thread panicked")))??;
+            compressed.extend(group);
+        }
+        Ok(compressed)
+    })
+}
+
+/// Compresses one hunk; returns None when compression does not save space.
+fn compress_hunk(layout: Layout, hunk: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    let compressed = match layout {
+        Layout::Dvd => deflate(hunk)?,
+        Layout::Cd => {
+            // cdzl: ECC bitmap (no sector has its ECC stripped), length of the sector
+            // stream, then the deflated sectors and the deflated subcode
+            let frames = hunk.len() / FRAME_SIZE;
+            let sectors: Vec<u8> = hunk
+                .chunks(FRAME_SIZE)
+                .flat_map(|frame| &frame[..SECTOR_SIZE as usize])
+                .copied()
+                .collect();
+            let subcode: Vec<u8> = hunk
+                .chunks(FRAME_SIZE)
+                .flat_map(|frame| &frame[SECTOR_SIZE as usize..])
+                .copied()
+                .collect();
+            let sectors = deflate(&sectors)?;
+            let Ok(sectors_length) = u16::try_from(sectors.len()) else {
+                return Ok(None);
+            };
+            let mut compressed = vec![0u8; frames.div_ceil(8)];
+            compressed.extend_from_slice(&sectors_length.to_be_bytes());
+            compressed.extend_from_slice(&sectors);
+            compressed.extend_from_slice(&deflate(&subcode)?);
+            compressed
+        }
+    };
+    Ok((compressed.len() < hunk.len()).then_some(compressed))
+}
+
+fn deflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len()), flate2::Compression::best());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(size);
+    DeflateDecoder::new(data).take(size as u64).read_to_end(&mut output)?;
+    if output.len() != size {
+        return Err(Error::ImageInvalid("truncated hunk data".to_string()));
+    }
+    Ok(output)
+}
+
+/// Decompresses one hunk written with `codec`.
+fn decompress_hunk(codec: u32, data: &[u8], hunk_size: usize) -> Result<Vec<u8>> {
+    match codec {
+        CODEC_ZLIB => inflate(data, hunk_size),
+        CODEC_CD_ZLIB => {
+            let frames = hunk_size / FRAME_SIZE;
+            let ecc_size = frames.div_ceil(8);
+            let length_size = if hunk_size < 65536 { 2 } else { 3 };
+            let header_size = ecc_size + length_size;
+            if data.len() < header_size {
+                return Err(Error::ImageInvalid("truncated cdzl hunk".to_string()));
+            }
+            if data[..ecc_size].iter().any(|&byte| byte != 0) {
+                return Err(Error::ImageInvalid("cdzl hunks with stripped ECC are not supported".to_string()));
+            }
+            let sectors_length = data[ecc_size..header_size]
+                .iter()
+                .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+            let sectors_end = (header_size + sectors_length).min(data.len());
+            let sectors = inflate(&data[header_size..sectors_end], frames * SECTOR_SIZE as usize)?;
+            let subcode = inflate(&data[sectors_end..], frames * SUBCODE_SIZE)?;
+            let mut hunk = Vec::with_capacity(hunk_size);
+            for (sector, subcode) in sectors.chunks(SECTOR_SIZE as usize).zip(subcode.chunks(SUBCODE_SIZE)) {
+                hunk.extend_from_slice(sector);
+                hunk.extend_from_slice(subcode);
+            }
+            Ok(hunk)
+        }
+        other => Err(Error::ImageInvalid(format!(
+            "unsupported CHD codec '{}'",
+            String::from_utf8_lossy(&other.to_be_bytes())
+        ))),
+    }
+}
+
+/// Encodes the map in the compressed v5 form: a 16-byte header, then a bitstream with
+/// a fixed 4-bit Huffman tree, the hunk types, and the length and CRC of each hunk.
+fn encode_map(map: &[MapEntry], first_offset: u64) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(map.len() * MAP_ENTRY_SIZE);
+    for entry in map {
+        raw.extend_from_slice(&entry.encode());
+    }
+    let length_bits = map
+        .iter()
+        .filter(|entry| entry.kind == COMPRESSION_TYPE_0)
+        .map(|entry| 32 - entry.length.leading_zeros())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut bits = BitWriter::default();
+    // Every type gets a 4-bit code, so the code of a type is the type itself
+    for _ in 0..16 {
+        bits.write(4, 4);
+    }
+    for entry in map {
+        bits.write(entry.kind as u32, 4);
+    }
+    for entry in map {
+        if entry.kind == COMPRESSION_TYPE_0 {
+            bits.write(entry.length, length_bits as u8);
+        }
+        bits.write(entry.crc as u32, 16);
+    }
+    let data = bits.finish();
+
+    let mut output = Vec::with_capacity(MAP_HEADER_SIZE + data.len());
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(&first_offset.to_be_bytes()[2..]);
+    output.extend_from_slice(&crc16(&raw).to_be_bytes());
+    output.extend_from_slice(&[length_bits as u8, 0, 0, 0]);
+    output.extend_from_slice(&data);
+    output
+}
+
+/// Decodes a compressed v5 map of `hunks` entries.
+fn decode_map(header: &[u8; MAP_HEADER_SIZE], data: &[u8], hunks: usize, hunk_size: u32) -> Result<Vec<MapEntry>> {
+    let invalid = || Error::ImageInvalid("corrupt CHD map".to_string());
+    let mut offset = u64::from_be_bytes([0, 0, header[4], header[5], header[6], header[7], header[8], header[9]]);
+    let map_crc = u16::from_be_bytes([header[10], header[11]]);
+    let length_bits = header[12];
+
+    let mut bits = BitReader::new(data);
+    let tree = HuffmanTree::read(&mut bits).ok_or_else(invalid)?;
+    let mut kinds = Vec::with_capacity(hunks);
+    let (mut last, mut repeat) = (0u8, 0usize);
+    while kinds.len() < hunks {
+        if repeat > 0 {
+            kinds.push(last);
+            repeat -= 1;
+            continue;
+        }
+        let value = tree.decode(&mut bits).ok_or_else(invalid)?;
+        // A run covers the current hunk and `repeat` more with the previous type
+        match value {
+            COMPRESSION_RLE_SMALL => repeat = 2 + tree.decode(&mut bits).ok_or_else(invalid)? as usize,
+            COMPRESSION_RLE_LARGE => {
+                let high = tree.decode(&mut bits).ok_or_else(invalid)? as usize;
+                let low = tree.decode(&mut bits).ok_or_else(invalid)? as usize;
+                repeat = 2 + 16 + (high << 4) + low;
+            }
+            kind => last = kind,
+        }
+        kinds.push(last);
+    }
+
+    let mut map = Vec::with_capacity(hunks);
+    for kind in kinds {
+        let length = match kind {
+            0..=3 => bits.read(length_bits).ok_or_else(invalid)?,
+            COMPRESSION_NONE => hunk_size,
+            _ => return Err(Error::ImageInvalid("CHDs with self or parent references are not supported".to_string())),
+        };
+        let crc = bits.read(16).ok_or_else(invalid)? as u16;
+        map.push(MapEntry { kind, length, offset, crc });
+        offset += length as u64;
+    }
+
+    let raw: Vec<u8> = map.iter().flat_map(MapEntry::encode).collect();
+    if crc16(&raw) != map_crc {
+        return Err(Error::ImageInvalid("CHD map checksum mismatch".to_string()));
+    }
+    Ok(map)
+}
+
+impl MapEntry {
+    /// Encodes the entry in its 12-byte uncompressed form, which the map CRC covers.
+    fn encode(&self) -> [u8; MAP_ENTRY_SIZE] {
+        let mut entry = [0u8; MAP_ENTRY_SIZE];
+        entry[0] = self.kind;
+        entry[1..4].copy_from_slice(&self.length.to_be_bytes()[1..]);
+        entry[4..10].copy_from_slice(&self.offset.to_be_bytes()[2..]);
+        entry[10..].copy_from_slice(&self.crc.to_be_bytes());
+        entry
+    }
+}
+
+/// Writes bits most significant first.
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    accumulator: u64,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u8) {
+        self.accumulator = (self.accumulator << bits) | (value as u64 & ((1u64 << bits) - 1));
+        self.count += bits;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.data.push((self.accumulator >> self.count) as u8);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.data.push((self.accumulator << (8 - self.count)) as u8);
+        }
+        self.data
+    }
+}
+
+/// Reads bits most significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, bits: u8) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..bits {
+            let byte = self.data.get(self.position / 8)?;
+            value = (value << 1) | ((byte >> (7 - self.position % 8)) & 1) as u32;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+/// The canonical Huffman tree of the map types (16 symbols, codes up to 8 bits).
+struct HuffmanTree {
+    /// Code length and code of each symbol
+    codes: [(u8, u32); 16],
+}
+
+impl HuffmanTree {
+    /// Reads the run-length encoded code lengths and assigns canonical codes.
+    fn read(bits: &mut BitReader) -> Option<Self> {
+        let mut lengths = Vec::with_capacity(16);
+        while lengths.len() < 16 {
+            match bits.read(4)? {
+                1 => match bits.read(4)? {
+                    1 => lengths.push(1),
+                    length => {
+                        let repeat = bits.read(4)? + 3;
+                        lengths.extend(std::iter::repeat_n(length as u8, repeat as usize));
+                    }
+                },
+                length => lengths.push(length as u8),
+            }
+        }
+        if lengths.len() != 16 {
+            return None;
+        }
+
+        // Codes are handed out from the longest length down, as chdman does
+        let mut starts = [0u32; 33];
+        for &length in &lengths {
+            starts[length as usize] += 1;
+        }
+        let mut start = 0u32;
+        for length in (1..=32).rev() {
+            let next = (start + starts[length]) >> 1;
+            starts[length] = start;
+            start = next;
+        }
+        let mut codes = [(0u8, 0u32); 16];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                codes[symbol] = (length, starts[length as usize]);
+                starts[length as usize] += 1;
+            }
+        }
+        Some(Self { codes })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Option<u8> {
+        let mut code = 0u32;
+        for length in 1..=8u8 {
+            code = (code << 1) | bits.read(1)?;
+            if let Some(symbol) = self.codes.iter().position(|&entry| entry == (length, code)) {
+                return Some(symbol as u8);
+            }
+        }
+        None
+    }
+}
+
+/// CRC-16/CCITT as used for CHD hunks and maps.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads until `buffer` is full or the input ends.
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// The fields of a CHD v5 header.
+#[derive(Debug, Clone)]
+struct Header {
+    compressors: [u32; 4],
+    logical_size: u64,
+    map_offset: u64,
+    meta_offset: u64,
+    hunk_size: u32,
+    unit_size: u32,
+    raw_sha1: [u8; 20],
+    sha1: [u8; 20],
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_SIZE as usize] {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        header[..8].copy_from_slice(b"MComprHD");
+        header[8..12].copy_from_slice(&(HEADER_SIZE as u32).to_be_bytes());
+        header[12..16].copy_from_slice(&5u32.to_be_bytes());
+        for (index, codec) in self.compressors.iter().enumerate() {
+            header[16 + index * 4..20 + index * 4].copy_from_slice(&codec.to_be_bytes());
+        }
+        header[32..40].copy_from_slice(&self.logical_size.to_be_bytes());
+        header[40..48].copy_from_slice(&self.map_offset.to_be_bytes());
+        header[48..56].copy_from_slice(&self.meta_offset.to_be_bytes());
+        header[56..60].copy_from_slice(&self.hunk_size.to_be_bytes());
+        header[60..64].copy_from_slice(&self.unit_size.to_be_bytes());
+        header[64..84].copy_from_slice(&self.raw_sha1);
+        header[84..104].copy_from_slice(&self.sha1);
+        // The parent SHA-1 stays zero: images are never stored as diffs
+        header
+    }
+
+    fn decode(header: &[u8; HEADER_SIZE as usize]) -> Result<Self> {
+        if &header[..8] != b"MComprHD" || header[12..16] != 5u32.to_be_bytes() {
+            return Err(Error::ImageInvalid("not a CHD v5 file".to_string()));
+        }
+        let u32_at = |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+        let u64_at = |offset: usize| u64::from_be_bytes(header[offset..offset + 8].try_into().unwrap());
+        if header[104..124].iter().any(|&byte| byte != 0) {
+            return Err(Error::ImageInvalid("CHDs with a parent are not supported".to_string()));
+        }
+        if u32_at(16) == 0 {
+            return Err(Error::ImageInvalid("uncompressed CHDs are not supported".to_string()));
+        }
+        Ok(Self {
+            compressors: [u32_at(16), u32_at(20), u32_at(24), u32_at(28)],
+            logical_size: u64_at(32),
+            map_offset: u64_at(40),
+            meta_offset: u64_at(48),
+            hunk_size: u32_at(56),
+            unit_size: u32_at(60),
+            raw_sha1: header[64..84].try_into().unwrap(),
+            sha1: header[84..104].try_into().unwrap(),
+        })
+    }
+}
+
+/// Reads a CHD written by `convert` as the data it contains. CD images can be read as
+/// plain raw sectors with `sector_data`, which skips the subcode after every frame.
+pub struct ChdReader {
+    file: BufReader<File>,
+    header: Header,
+    map: Vec<MapEntry>,
+    cache: Option<(usize, Vec<u8>)>,
+    position: u64,
+    sector_data: bool,
+}
+
+impl ChdReader {
+    /// Opens a CHD and decodes its map.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        let header = Header::decode(&header)?;
+        if header.hunk_size == 0 {
+            return Err(Error::ImageInvalid("CHD hunk size is zero".to_string()));
+        }
+
+        let hunks = header.logical_size.div_ceil(header.hunk_size as u64) as usize;
+        let mut map_header = [0u8; MAP_HEADER_SIZE];
+        file.seek(SeekFrom::Start(header.map_offset))?;
+        file.read_exact(&mut map_header)?;
+        let map_size = u32::from_be_bytes(map_header[..4].try_into().unwrap()) as usize;
+        let mut map_data = vec![0u8; map_size];
+        file.read_exact(&mut map_data)?;
+        let map = decode_map(&map_header, &map_data, hunks, header.hunk_size)?;
+
+        Ok(Self {
+            file,
+            header,
+            map,
+            cache: None,
+            position: 0,
+            sector_data: false,
+        })
+    }
+
+    /// Whether the image uses the CD frame layout.
+    pub fn is_cd(&self) -> bool {
+        self.header.unit_size as usize == FRAME_SIZE
+    }
+
+    /// Size of the data in bytes.
+    pub fn logical_size(&self) -> u64 {
+        self.header.logical_size
+    }
+
+    /// Reads CD images as raw 2352-byte sectors, without subcode.
+    pub fn sector_data(mut self) -> Self {
+        self.sector_data = self.is_cd();
+        self
+    }
+
+    /// Reads and decompresses a hunk, checking its CRC.
+    fn read_hunk(&mut self, index: usize) -> Result<Vec<u8>> {
+        let entry = *self
+            .map
+            .get(index)
+            .ok_or_else(|| Error::ImageInvalid(format!("hunk {} is outside the image", index)))?;
+        let mut data = vec![0u8; entry.length as usize];
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        self.file.read_exact(&mut data)?;
+        let hunk_size = self.header.hunk_size as usize;
+        let hunk = match entry.kind {
+            COMPRESSION_NONE => data,
+            kind => decompress_hunk(self.header.compressors[kind as usize], &data, hunk_size)?,
+        };
+        if hunk.len() != hunk_size || crc16(&hunk) != entry.crc {
+            return Err(Error::ImageInvalid(format!("hunk {} fails its CRC check", index)));
+        }
+        Ok(hunk)
+    }
+
+    /// Reads the metadata entries as (tag, flags, data).
+    fn metadata(&mut self) -> Result<Vec<(u32, u8, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        let mut offset = self.header.meta_offset;
+        while offset != 0 {
+            let mut header = [0u8; METADATA_HEADER_SIZE];
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut header)?;
+            let tag = u32::from_be_bytes(header[..4].try_into().unwrap());
+            let length = u32::from_be_bytes([0, header[5], header[6], header[7]]) as usize;
+            let mut data = vec![0u8; length];
+            self.file.read_exact(&mut data)?;
+            entries.push((tag, header[4], data));
+            offset = u64::from_be_bytes(header[8..].try_into().unwrap());
+        }
+        Ok(entries)
+    }
+
+    /// Size of the data as seen through `Read`.
+    fn length(&self) -> u64 {
+        match self.sector_data {
+            true => self.header.logical_size / FRAME_SIZE as u64 * SECTOR_SIZE,
+            false => self.header.logical_size,
+        }
+    }
+}
+
+impl Read for ChdReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.length() || buf.is_empty() {
+            return Ok(0);
+        }
+        // Map the read position to the data offset, skipping subcode when reading sectors
+        let (offset, available) = match self.sector_data {
+            true => {
+                let (frame, within) = (self.position / SECTOR_SIZE, self.position % SECTOR_SIZE);
+                (frame * FRAME_SIZE as u64 + within, SECTOR_SIZE - within)
+            }
+            false => (self.position, self.length() - self.position),
+        };
+        let hunk_size = self.header.hunk_size as u64;
+        let index = (offset / hunk_size) as usize;
+        if self.cache.as_ref().is_none_or(|(cached, _)| *cached != index) {
+            let hunk = self.read_hunk(index).map_err(io::Error::other)?;
+            self.cache = Some((index, hunk));
+        }
+        let (_, hunk) = self.cache.as_ref().unwrap();
+        let within = (offset % hunk_size) as usize;
+        let count = buf.len().min(hunk.len() - within).min(available as usize);
+        buf[..count].copy_from_slice(&hunk[within..within + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for ChdReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.position)
+    }
+}
+
+/// Decompresses every hunk of a CHD, checking the hunk CRCs and both SHA-1s of the header.
+/// Returns the CRC32 of the data. `on_progress` receives the number of bytes read so far.
+pub fn verify(path: &Path, cancel: &CancellationToken, mut on_progress: impl FnMut(u64)) -> Result<u32> {
+    let mut reader = ChdReader::open(path)?;
+    let mut raw_sha1 = Sha1::new();
+    let mut crc32 = crc32fast::Hasher::new();
+    let mut remaining = reader.header.logical_size;
+    for index in 0..reader.map.len() {
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let hunk = reader.read_hunk(index)?;
+        let logical = (hunk.len() as u64).min(remaining) as usize;
+        raw_sha1.update(&hunk[..logical]);
+        crc32.update(&hunk[..logical]);
+        remaining -= logical as u64;
+        on_progress(reader.header.logical_size - remaining);
+    }
+
+    let raw_sha1: [u8; 20] = raw_sha1.finalize().into();
+    let metadata: Vec<(u32, Vec<u8>)> = reader
+        .metadata()?
+        .into_iter()
+        .filter(|(_, flags, _)| flags & METADATA_CHECKSUM != 0)
+        .map(|(tag, _, data)| (tag, data))
+        .collect();
+    let sha1 = overall_sha1(&raw_sha1, &metadata);
+    for (expected, actual) in [(reader.header.raw_sha1, raw_sha1), (reader.header.sha1, sha1)] {
+        if expected != actual {
+            return Err(Error::ChecksumMismatch {
+                file: path.display().to_string(),
+                expected: hex(&expected),
+                actual: hex(&actual),
+            });
+        }
+    }
+    Ok(crc32.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder under the system temp folder, emptied for the test.
+    fn scratch(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("ps2rd-chd-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// Bytes that deflate does not shrink.
+    fn noise(length: usize) -> Vec<u8> {
+        let mut state = 0x9E37_79B9u32;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crc16_is_ccitt_false() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn header_field_offsets() {
+        let header = Header {
+            compressors: [CODEC_ZLIB, 0, 0, 0],
+            logical_size: 0x0102_0304_0506,
+            map_offset: 0x1000,
+            meta_offset: 0x2000,
+            hunk_size: 4096,
+            unit_size: 2048,
+            raw_sha1: [0xAA; 20],
+            sha1: [0xBB; 20],
+        };
+        let bytes = header.encode();
+        assert_eq!(&bytes[..8], b"MComprHD");
+        assert_eq!(bytes[8..12], 124u32.to_be_bytes());
+        assert_eq!(bytes[12..16], 5u32.to_be_bytes());
+        assert_eq!(&bytes[16..20], b"zlib");
+        assert!(bytes[20..32].iter().all(|&b| b == 0));
+        assert_eq!(bytes[32..40], 0x0102_0304_0506u64.to_be_bytes());
+        assert_eq!(bytes[40..48], 0x1000u64.to_be_bytes());
+        assert_eq!(bytes[48..56], 0x2000u64.to_be_bytes());
+        assert_eq!(bytes[56..60], 4096u32.to_be_bytes());
+        assert_eq!(bytes[60..64], 2048u32.to_be_bytes());
+        assert_eq!(bytes[64..84], [0xAA; 20]);
+        assert_eq!(bytes[84..104], [0xBB; 20]);
+        assert!(bytes[104..124].iter().all(|&b| b == 0), "no parent");
+
+        let decoded = Header::decode(&bytes).unwrap();
+        assert_eq!(decoded.logical_size, header.logical_size);
+        assert_eq!((decoded.map_offset, decoded.meta_offset), (0x1000, 0x2000));
+        assert_eq!((decoded.hunk_size, decoded.unit_size), (4096, 2048));
+        assert_eq!((decoded.raw_sha1, decoded.sha1), (header.raw_sha1, header.sha1));
+    }
+
+    #[test]
+    fn map_round_trip() {
+        let map = [
+            MapEntry { kind: COMPRESSION_TYPE_0, length: 300, offset: 124, crc: 0x1234 },
+            MapEntry { kind: COMPRESSION_NONE, length: 4096, offset: 424, crc: 0xBEEF },
+            MapEntry { kind: COMPRESSION_TYPE_0, length: 5, offset: 4520, crc: 0x0001 },
+        ];
+        let encoded = encode_map(&map, 124);
+        let header: [u8; MAP_HEADER_SIZE] = encoded[..MAP_HEADER_SIZE].try_into().unwrap();
+        assert_eq!(u32::from_be_bytes(header[..4].try_into().unwrap()) as usize, encoded.len() - MAP_HEADER_SIZE);
+        assert_eq!(header[4..10], 124u64.to_be_bytes()[2..]);
+        assert_eq!(header[12], 9, "bits needed for the longest compressed length");
+
+        let decoded = decode_map(&header, &encoded[MAP_HEADER_SIZE..], map.len(), 4096).unwrap();
+        for (decoded, entry) in decoded.iter().zip(&map) {
+            assert_eq!(decoded.encode(), entry.encode());
+        }
+    }
+
+    #[test]
+    fn map_with_type_runs() {
+        // chdman run-length encodes repeated types: type 0, a short run of 2 + 1 more, then an uncompressed hunk
+        let lengths = [10u32, 20, 30, 40, 50];
+        let mut bits = BitWriter::default();
+        for _ in 0..16 {
+            bits.write(4, 4);
+        }
+        for value in [COMPRESSION_TYPE_0, COMPRESSION_RLE_SMALL, 1, COMPRESSION_NONE] {
+            bits.write(value as u32, 4);
+        }
+        for (index, length) in lengths.iter().enumerate() {
+            bits.write(*length, 6);
+            bits.write(index as u32, 16);
+        }
+        bits.write(0xFFFF, 16);
+        let data = bits.finish();
+
+        let mut expected = Vec::new();
+        let mut offset = 124u64;
+        for (index, &length) in lengths.iter().chain([&64]).enumerate() {
+            let kind = if index < lengths.len() { COMPRESSION_TYPE_0 } else { COMPRESSION_NONE };
+            let crc = if index < lengths.len() { index as u16 } else { 0xFFFF };
+            expected.push(MapEntry { kind, length, offset, crc });
+            offset += length as u64;
+        }
+        let raw: Vec<u8> = expected.iter().flat_map(MapEntry::encode).collect();
+        let mut header = [0u8; MAP_HEADER_SIZE];
+        header[..4].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        header[4..10].copy_from_slice(&124u64.to_be_bytes()[2..]);
+        header[10..12].copy_from_slice(&crc16(&raw).to_be_bytes());
+        header[12] = 6;
+
+        let decoded = decode_map(&header, &data, expected.len(), 64).unwrap();
+        let decoded: Vec<u8> = decoded.iter().flat_map(MapEntry::encode).collect();
+        assert_eq!(decoded, raw);
+
+        header[10] ^= 1;
+        assert!(decode_map(&header, &data, expected.len(), 64).is_err());
+    }
+
+    #[test]
+    fn dvd_round_trip() {
+        let folder = scratch("dvd");
+        let (iso, chd) = (folder.join("game.iso"), folder.join("game.chd"));
+        // A compressible hunk, an incompressible one and a last hunk half filled
+        let mut data = vec![0x20u8; DVD_HUNK_SIZE];
+        data.extend(noise(DVD_HUNK_SIZE));
+        data.extend(vec![7u8; DVD_SECTOR_SIZE as usize]);
+        fs::write(&iso, &data).unwrap();
+
+        let converted = convert(&iso, &chd, 2, &CancellationToken::new(), |_| {}).unwrap();
+        assert_eq!(converted.logical_size, data.len() as u64);
+        assert_eq!(converted.crc32, crc32fast::hash(&data));
+        assert_eq!(verify(&chd, &CancellationToken::new(), |_| {}).unwrap(), converted.crc32);
+
+        let mut reader = ChdReader::open(&chd).unwrap();
+        assert!(!reader.is_cd());
+        let kinds: Vec<u8> = reader.map.iter().map(|entry| entry.kind).collect();
+        assert_eq!(kinds, [COMPRESSION_TYPE_0, COMPRESSION_NONE, COMPRESSION_TYPE_0]);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn cd_round_trip() {
+        let folder = scratch("cd");
+        let (cue, chd) = (folder.join("game.cue"), folder.join("game.chd"));
+        // 12 frames: one and a half hunks, already a multiple of the track padding
+        let mut data = vec![0x11u8; 8 * SECTOR_SIZE as usize];
+        data.extend(noise(4 * SECTOR_SIZE as usize));
+        fs::write(folder.join("game.bin"), &data).unwrap();
+        fs::write(&cue, "FILE \"game.bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n").unwrap();
+
+        let converted = convert(&cue, &chd, 1, &CancellationToken::new(), |_| {}).unwrap();
+        assert_eq!(converted.logical_size, 12 * FRAME_SIZE as u64);
+        assert_eq!(verify(&chd, &CancellationToken::new(), |_| {}).unwrap(), converted.crc32);
+
+        let mut reader = ChdReader::open(&chd).unwrap();
+        assert!(reader.is_cd());
+        assert_eq!(reader.header.compressors[0], CODEC_CD_ZLIB);
+        let metadata = reader.metadata().unwrap();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].0, TAG_CD_TRACK);
+        assert!(String::from_utf8_lossy(&metadata[0].2).starts_with("TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:12 "));
+
+        let mut read = Vec::new();
+        reader.sector_data().read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,528 @@
+use crate::error::{Error, Result};
+use tokio_util::sync::CancellationToken;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of the uncompressed blocks (one ISO9660 sector).
+const BLOCK_SIZE: u32 = 2048;
+/// Size of the CSO/ZSO header.
+const HEADER_SIZE: u32 = 0x18;
+/// Index flag marking a block stored without compression.
+const PLAIN_FLAG: u32 = 0x8000_0000;
+/// Blocks read and compressed per batch.
+const BATCH_BLOCKS: usize = 4096;
+
+/// Block-compressed image formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// CISO with raw deflate blocks
+    Cso,
+    /// ZISO with raw LZ4 blocks
+    Zso,
+}
+
+impl Format {
+    /// Parses a format name ('cso' or 'zso').
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cso" => Some(Format::Cso),
+            "zso" => Some(Format::Zso),
+            _ => None,
+        }
+    }
+
+    /// File extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Cso => "cso",
+            Format::Zso => "zso",
+        }
+    }
+
+    fn magic(self) -> &'static [u8; 4] {
+        match self {
+            Format::Cso => b"CISO",
+            Format::Zso => b"ZISO",
+        }
+    }
+
+    fn from_magic(magic: &[u8]) -> Option<Self> {
+        match magic {
+            b"CISO" => Some(Format::Cso),
+            b"ZISO" => Some(Format::Zso),
+            _ => None,
+        }
+    }
+
+    /// Compresses one block; returns None when compression does not save space.
+    fn compress_block(self, block: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let compressed = match self {
+            Format::Cso => {
+                let mut encoder = DeflateEncoder::new(Vec::with_capacity(block.len()), flate2::Compression::best());
+                encoder.write_all(block)?;
+                encoder.finish()?
+            }
+            Format::Zso => lz4_flex::block::compress(block),
+        };
+        Ok((compressed.len() < block.len()).then_some(compressed))
+    }
+
+    /// Decompresses one block of `size` bytes.
+    fn decompress_block(self, data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        match self {
+            Format::Cso => {
+                let mut block = Vec::with_capacity(size);
+                DeflateDecoder::new(data).take(size as u64).read_to_end(&mut block)?;
+                Ok(block)
+            }
+            Format::Zso => lz4_decompress(data, size),
+        }
+    }
+}
+
+/// Decodes an LZ4 block, stopping once `size` bytes are produced. Unlike a strict decoder
+/// this tolerates the alignment padding that follows blocks in ZSO images.
+fn lz4_decompress(input: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid LZ4 block");
+    let mut output = Vec::with_capacity(size);
+    let mut i = 0;
+    // Lengths of 15 continue in the following bytes until one is not 255
+    let read_length = |mut length: usize, i: &mut usize| -> io::Result<usize> {
+        if length == 15 {
+            loop {
+                let byte = *input.get(*i).ok_or_else(invalid)?;
+                *i += 1;
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+
+    while output.len() < size {
+        let token = *input.get(i).ok_or_else(invalid)?;
+        i += 1;
+        let literals = read_length((token >> 4) as usize, &mut i)?;
+        output.extend_from_slice(input.get(i..i + literals).ok_or_else(invalid)?);
+        i += literals;
+        if output.len() >= size {
+            break;
+        }
+
+        let offset = u16::from_le_bytes(input.get(i..i + 2).ok_or_else(invalid)?.try_into().unwrap()) as usize;
+        i += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(invalid());
+        }
+        let length = read_length((token & 0x0F) as usize, &mut i)? + 4;
+        // Matches may overlap the bytes they produce, so copy one byte at a time
+        let start = output.len() - offset;
+        for k in 0..length {
+            output.push(output[start + k]);
+        }
+    }
+    output.truncate(size);
+    Ok(output)
+}
+
+/// Sizes and checksum of a compressed image.
+#[derive(Debug, Clone, Copy)]
+pub struct Compressed {
+    /// Size of the ISO in bytes
+    pub iso_size: u64,
+    /// Size of the compressed image in bytes
+    pub size: u64,
+    /// CRC32 of the ISO, checked against the decompressed image
+    pub crc32: u32,
+}
+
+/// Path of the compressed image written next to `iso`.
+pub fn output_path(iso: &Path, format: Format) -> PathBuf {
+    iso.with_extension(format.extension())
+}
+
+/// Compresses `iso` into `output` using `threads` worker threads (0 uses every core).
+/// `on_progress` receives the number of ISO bytes processed so far.
+/// Cancelling removes the partial output.
+pub fn compress(
+    iso: &Path,
+    output: &Path,
+    format: Format,
+    threads: usize,
+    cancel: &CancellationToken,
+    on_progress: impl FnMut(u64),
+) -> Result<Compressed> {
+    let result = write_compressed(iso, output, format, threads, cancel, on_progress);
+    if result.is_err() {
+        let _ = fs::remove_file(output);
+    }
+    result
+}
+
+fn write_compressed(
+    iso: &Path,
+    output: &Path,
+    format: Format,
+    threads: usize,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(u64),
+) -> Result<Compressed> {
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let iso_size = fs::metadata(iso)?.len();
+    let blocks = iso_size.div_ceil(BLOCK_SIZE as u64) as usize;
+    let index_size = (blocks as u64 + 1) * 4;
+
+    // Offsets are stored shifted right by `align` and must fit in 31 bits
+    let mut align = 0u8;
+    while (HEADER_SIZE as u64 + index_size + iso_size) >> align >= PLAIN_FLAG as u64 {
+        align += 1;
+    }
+
+    let mut input = BufReader::new(File::open(iso)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_all(format.magic())?;
+    writer.write_all(&HEADER_SIZE.to_le_bytes())?;
+    writer.write_all(&iso_size.to_le_bytes())?;
+    writer.write_all(&BLOCK_SIZE.to_le_bytes())?;
+    writer.write_all(&[1, align, 0, 0])?;
+    // The index is written once all block offsets are known
+    writer.write_all(&vec![0u8; index_size as usize])?;
+
+    let mut index = Vec::with_capacity(blocks + 1);
+    let mut position = HEADER_SIZE as u64 + index_size;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut processed = 0u64;
+    let mut buffer = vec![0u8; BATCH_BLOCKS * BLOCK_SIZE as usize];
+
+    loop {
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let read = read_full(&mut input, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        let chunks: Vec<&[u8]> = buffer[..read].chunks(BLOCK_SIZE as usize).collect();
+        let compressed = compress_batch(format, &chunks, threads)?;
+
+        for (chunk, compressed) in chunks.iter().zip(compressed) {
+            // Pad so the block starts on an aligned offset
+            let padding = position.next_multiple_of(1 << align) - position;
+            writer.write_all(&vec![0u8; padding as usize])?;
+            position += padding;
+
+            let entry = (position >> align) as u32;
+            match compressed {
+                Some(data) => {
+                    index.push(entry);
+                    writer.write_all(&data)?;
+                    position += data.len() as u64;
+                }
+                None => {
+                    index.push(entry | PLAIN_FLAG);
+                    writer.write_all(chunk)?;
+                    position += chunk.len() as u64;
+                }
+            }
+        }
+        processed += read as u64;
+        on_progress(processed);
+    }
+    let padding = position.next_multiple_of(1 << align) - position;
+    writer.write_all(&vec![0u8; padding as usize])?;
+    position += padding;
+    index.push((position >> align) as u32);
+
+    writer.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+    for entry in &index {
+        writer.write_all(&entry.to_le_bytes())?;
+    }
+    writer.flush()?;
+
+    Ok(Compressed {
+        iso_size,
+        size: position,
+        crc32: hasher.finalize(),
+    })
+}
+
+/// Compresses a batch of blocks, spreading them over `threads` threads.
+fn compress_batch(format: Format, blocks: &[&[u8]], threads: usize) -> Result<Vec<Option<Vec<u8>>>> {
+    let per_thread = blocks.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = blocks
+            .chunks(per_thread)
+            .map(|group| {
+                scope.spawn(move || {
+                    group
+                        .iter()
+                        .map(|block| format.compress_block(block))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        let mut compressed = Vec::with_capacity(blocks.len());
+        for handle in handles {
+            let group = handle
+                .join()
+                .map_err(|_| Error::Io(io::Error::other("compression thread panicked")))??;
+            compressed.extend(group);
+        }
+        Ok(compressed)
+    })
+}
+
+/// Reads until `buffer` is full or the input ends.
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Decompresses an image and returns the CRC32 of the ISO it contains.
+/// `on_progress` receives the number of ISO bytes decompressed so far.
+pub fn image_crc32(path: &Path, cancel: &CancellationToken, mut on_progress: impl FnMut(u64)) -> Result<u32> {
+    let mut reader = CompressedReader::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; BATCH_BLOCKS * BLOCK_SIZE as usize];
+    let mut processed = 0u64;
+    loop {
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let read = read_full(&mut reader, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        processed += read as u64;
+        on_progress(processed);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Reads a CSO or ZSO image as the ISO it contains.
+pub struct CompressedReader {
+    file: BufReader<File>,
+    format: Format,
+    iso_size: u64,
+    block_size: u64,
+    align: u8,
+    index: Vec<u32>,
+    position: u64,
+    /// Last decompressed block and its number
+    cache: Option<(u64, Vec<u8>)>,
+}
+
+impl CompressedReader {
+    /// Opens an image and reads its block index.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        let format = Format::from_magic(&header[..4])
+            .ok_or_else(|| Error::ImageInvalid(format!("{} is not a CSO or ZSO image", path.display())))?;
+        let header_size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+        let iso_size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let block_size = u32::from_le_bytes(header[16..20].try_into().unwrap()) as u64;
+        let align = header[21];
+        if block_size == 0 || align > 31 {
+            return Err(Error::ImageInvalid(format!("{} has an invalid header", path.display())));
+        }
+
+        let blocks = iso_size.div_ceil(block_size) as usize;
+        let mut raw_index = vec![0u8; (blocks + 1) * 4];
+        file.seek(SeekFrom::Start(header_size.max(HEADER_SIZE as u64)))?;
+        file.read_exact(&mut raw_index)?;
+        let index = raw_index
+            .chunks_exact(4)
+            .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            file,
+            format,
+            iso_size,
+            block_size,
+            align,
+            index,
+            position: 0,
+            cache: None,
+        })
+    }
+
+    /// Size of the ISO contained in the image.
+    pub fn iso_size(&self) -> u64 {
+        self.iso_size
+    }
+
+    /// Decompresses block `number`.
+    fn read_block(&mut self, number: u64) -> io::Result<Vec<u8>> {
+        let entry = self.index[number as usize];
+        let next = self.index[number as usize + 1];
+        let start = ((entry & !PLAIN_FLAG) as u64) << self.align;
+        let end = ((next & !PLAIN_FLAG) as u64) << self.align;
+        let size = self.block_size.min(self.iso_size - number * self.block_size) as usize;
+
+        self.file.seek(SeekFrom::Start(start))?;
+        if entry & PLAIN_FLAG != 0 {
+            let mut block = vec![0u8; size];
+            self.file.read_exact(&mut block)?;
+            return Ok(block);
+        }
+        // The stored length includes alignment padding, which the decoders ignore
+        let mut data = vec![0u8; end.saturating_sub(start) as usize];
+        self.file.read_exact(&mut data)?;
+        let block = self.format.decompress_block(&data, size)?;
+        if block.len() != size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("block {} is truncated", number)));
+        }
+        Ok(block)
+    }
+}
+
+impl Read for CompressedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.iso_size || buf.is_empty() {
+            return Ok(0);
+        }
+        let number = self.position / self.block_size;
+        let block = match self.cache.take() {
+            Some((cached, block)) if cached == number => block,
+            _ => self.read_block(number)?,
+        };
+        let offset = (self.position % self.block_size) as usize;
+        let count = buf.len().min(block.len() - offset);
+        buf[..count].copy_from_slice(&block[offset..offset + count]);
+        self.position += count as u64;
+        self.cache = Some((number, block));
+        Ok(count)
+    }
+}
+
+impl Seek for CompressedReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.iso_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file path under the system temp folder, unique to the test.
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ps2rd-compress-{}-{}", name, std::process::id()))
+    }
+
+    /// Two blocks of zeros, one block of noise that does not compress, and a short last block.
+    fn sample_iso() -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        let noise = (0..BLOCK_SIZE).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        });
+        let mut iso = vec![0u8; 2 * BLOCK_SIZE as usize];
+        iso.extend(noise);
+        iso.extend(b"end of the image".repeat(20));
+        iso
+    }
+
+    fn round_trip(format: Format) {
+        let (iso, image) = (scratch(&format!("{}.iso", format.extension())), scratch(format.extension()));
+        let data = sample_iso();
+        fs::write(&iso, &data).unwrap();
+
+        let compressed = compress(&iso, &image, format, 2, &CancellationToken::new(), |_| {}).unwrap();
+        assert_eq!(compressed.iso_size, data.len() as u64);
+        assert_eq!(compressed.crc32, crc32fast::hash(&data));
+        assert_eq!(compressed.size, fs::metadata(&image).unwrap().len());
+
+        let file = fs::read(&image).unwrap();
+        assert_eq!(&file[..4], format.magic());
+        assert_eq!(u32::from_le_bytes(file[4..8].try_into().unwrap()), HEADER_SIZE);
+        assert_eq!(u64::from_le_bytes(file[8..16].try_into().unwrap()), data.len() as u64);
+        assert_eq!(u32::from_le_bytes(file[16..20].try_into().unwrap()), BLOCK_SIZE);
+        assert_eq!(file[20], 1, "version");
+        let index: Vec<u32> = file[HEADER_SIZE as usize..HEADER_SIZE as usize + 5 * 4]
+            .chunks_exact(4)
+            .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+            .collect();
+        assert_eq!(index[0], HEADER_SIZE + 5 * 4, "the first block follows the index");
+        assert_eq!(index[0] & PLAIN_FLAG, 0, "zeros are compressed");
+        assert_ne!(index[2] & PLAIN_FLAG, 0, "noise is stored as it is");
+        assert_eq!(
+            (index[3] & !PLAIN_FLAG) - (index[2] & !PLAIN_FLAG),
+            BLOCK_SIZE,
+            "a stored block takes a full block"
+        );
+        assert_eq!(index[4] as u64, compressed.size, "the last entry ends the data");
+
+        let mut reader = CompressedReader::open(&image).unwrap();
+        assert_eq!(reader.iso_size(), data.len() as u64);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        // Reads inside the stored block and across into the short one
+        let mut window = vec![0u8; 100];
+        reader.seek(SeekFrom::Start(3 * BLOCK_SIZE as u64 - 50)).unwrap();
+        reader.read_exact(&mut window).unwrap();
+        assert_eq!(window[..], data[3 * BLOCK_SIZE as usize - 50..3 * BLOCK_SIZE as usize + 50]);
+        assert_eq!(image_crc32(&image, &CancellationToken::new(), |_| {}).unwrap(), compressed.crc32);
+
+        fs::remove_file(iso).unwrap();
+        fs::remove_file(image).unwrap();
+    }
+
+    #[test]
+    fn cso_round_trip() {
+        round_trip(Format::Cso);
+    }
+
+    #[test]
+    fn zso_round_trip() {
+        round_trip(Format::Zso);
+    }
+
+    #[test]
+    fn lz4_ignores_alignment_padding() {
+        let block = b"abcabcabcabcabcabcabcabcabcabcabcabc the rest".repeat(10);
+        let mut data = lz4_flex::block::compress(&block);
+        data.extend([0u8; 3]);
+        assert_eq!(lz4_decompress(&data, block.len()).unwrap(), block);
+        assert!(lz4_decompress(&data[..data.len() - 8], block.len()).is_err());
+    }
+
+    #[test]
+    fn cancelling_removes_the_output() {
+        let (iso, image) = (scratch("cancel.iso"), scratch("cancel.cso"));
+        fs::write(&iso, sample_iso()).unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(compress(&iso, &image, Format::Cso, 1, &cancel, |_| {}), Err(Error::Cancelled)));
+        assert!(!image.exists());
+        fs::remove_file(iso).unwrap();
+    }
+}
@@ -1,12 +1,1232 @@
-use anyhow::Result;
+use crate::compress::Format;
+use crate::error::{Error, Result};
+use crate::hooks::Hooks;
+use crate::one_game::Preferences;
+use crate::opl::{OplExport, PlaceMode};
+use crate::server::DEFAULT_LISTEN;
 use configparser::ini::Ini;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-/// Configuration for the downloader application, loaded from config.ini.
+/// Prefix of the environment variables overriding settings: `PS2RD_<SECTION>_<KEY>`.
+pub const ENV_PREFIX: &str = "PS2RD_";
+
+/// Environment variable selecting a profile when `--profile` is not given.
+pub const PROFILE_ENV: &str = "PS2RD_PROFILE";
+
+/// Section holding the profiles: `[profile.<name>]`, with the settings as `[profile.<name>.<section>]`
+/// tables or `<section>.<KEY>` keys.
+const PROFILE_SECTION: &str = "profile";
+
+/// Version of the config file layout, stored in the file's top-level `VERSION` key. Files without
+/// one are version 1: the original INI layout, or TOML written before the layout was versioned.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// How a setting's value is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Free text
+    Text,
+    /// An http(s) URL of a folder, ending in '/'
+    Url,
+    /// Folder URLs separated by ';' (an array in TOML)
+    Urls,
+    /// Text that is not printed by `config show`
+    Secret,
+    /// Whole number
+    Number,
+    /// Whole number greater than 0
+    Count,
+    /// '1'/'0' or 'true'/'false'
+    Flag,
+    /// Values separated by ';' (an array in TOML)
+    List,
+    /// One of a fixed set of words
+    Choice(&'static [&'static str]),
+}
+
+/// A setting of the configuration file.
+#[derive(Debug)]
+pub struct Setting {
+    /// Section the setting lives in, e.g. 'Download'
+    pub section: &'static str,
+    /// Key inside the section, e.g. 'MAX_RETRIES'
+    pub key: &'static str,
+    /// How the value is interpreted
+    pub kind: Kind,
+    /// Built-in default
+    pub default: &'static str,
+    /// What the setting does, written as a comment into new config files
+    pub description: &'static str,
+}
+
+impl Setting {
+    /// Name of the environment variable overriding this setting.
+    pub fn env_name(&self) -> String {
+        format!("{}{}_{}", ENV_PREFIX, self.section.to_ascii_uppercase(), self.key)
+    }
+
+    /// Returns true if `section` and `key` name this setting (ignoring case, like configparser).
+    fn is(&self, section: &str, key: &str) -> bool {
+        self.section.eq_ignore_ascii_case(section) && self.key.eq_ignore_ascii_case(key)
+    }
+}
+
+/// Every setting, in the order they appear in a new config file.
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        section: "url",
+        key: "ISO",
+        kind: Kind::Url,
+        default: "https://myrient.erista.me/files/Redump/Sony%20-%20PlayStation%202/",
+        description: "Folder listing the Redump PS2 ZIPs; the catalog is scraped from it",
+    },
+    Setting {
+        section: "url",
+        key: "MIRRORS",
+        kind: Kind::Urls,
+        default: "",
+        description: "Folders with the same ZIPs, tried in order when a download from ISO fails",
+    },
+    Setting {
+        section: "Download",
+        key: "LIST_FILES_JSON_NAME",
+        kind: Kind::Text,
+        default: "listPS2Titles.json",
+        description: "File name of the cached game list",
+    },
+    Setting {
+        section: "Download",
+        key: "EXTERNAL_ISO",
+        kind: Kind::Flag,
+        default: "false",
+        description: "Download ISO files using the web browser",
+    },
+    Setting {
+        section: "Download",
+        key: "MAX_RETRIES",
+        kind: Kind::Count,
+        default: "10",
+        description: "Attempts before a download is given up",
+    },
+    Setting {
+        section: "Download",
+        key: "DELAY_BETWEEN_RETRIES",
+        kind: Kind::Count,
+        default: "10",
+        description: "Seconds to wait between attempts",
+    },
+    Setting {
+        section: "Download",
+        key: "TIMEOUT_REQUEST",
+        kind: Kind::Number,
+        default: "600",
+        description: "Seconds a download may take (empty for no limit)",
+    },
+    Setting {
+        section: "Download",
+        key: "CONCURRENCY",
+        kind: Kind::Count,
+        default: "1",
+        description: "Games downloaded at the same time by 'download' and the interactive prompt",
+    },
+    Setting {
+        section: "Download",
+        key: "SPEED_LIMIT",
+        kind: Kind::Number,
+        default: "0",
+        description: "KiB/s all downloads together may use (0 for no limit)",
+    },
+    Setting {
+        section: "Download",
+        key: "M3U",
+        kind: Kind::Flag,
+        default: "false",
+        description: "Write an .m3u playlist for multi-disc games once every disc is downloaded",
+    },
+    Setting {
+        section: "folder",
+        key: "TMP_FOLDER_NAME",
+        kind: Kind::Text,
+        default: "~/PS2-Games",
+        description: "Download folder; the game list and library index are kept here too",
+    },
+    Setting {
+        section: "folder",
+        key: "TMP_ISO_FOLDER_NAME",
+        kind: Kind::Text,
+        default: "iso_files",
+        description: "Folder inside TMP_FOLDER_NAME for the images (empty puts them in TMP_FOLDER_NAME)",
+    },
+    Setting {
+        section: "library",
+        key: "ROOTS",
+        kind: Kind::List,
+        default: "",
+        description: "Extra folders with games you already own",
+    },
+    Setting {
+        section: "library",
+        key: "CHECKSUM",
+        kind: Kind::Flag,
+        default: "false",
+        description: "Compute checksums to recognise renamed files (slower)",
+    },
+    Setting {
+        section: "1g1r",
+        key: "REGIONS",
+        kind: Kind::List,
+        default: "USA; World; Europe; Japan",
+        description: "Regions in order of preference when picking one version per game",
+    },
+    Setting {
+        section: "1g1r",
+        key: "LANGUAGES",
+        kind: Kind::List,
+        default: "En; Ja",
+        description: "Language codes in order of preference, used between versions of the same region",
+    },
+    Setting {
+        section: "1g1r",
+        key: "EXCLUDE",
+        kind: Kind::List,
+        default: "Demo; Beta; Proto; Sample; Kiosk; Taikenban",
+        description: "Title tags that rule a version out",
+    },
+    Setting {
+        section: "opl",
+        key: "FOLDER",
+        kind: Kind::Text,
+        default: "",
+        description: "Open PS2 Loader folder (USB drive or SMB share) to export ISOs to; leave empty to disable",
+    },
+    Setting {
+        section: "opl",
+        key: "MODE",
+        kind: Kind::Choice(&["copy", "hardlink"]),
+        default: "copy",
+        description: "'copy' or 'hardlink' (hardlinks need the same filesystem, otherwise the ISO is copied)",
+    },
+    Setting {
+        section: "opl",
+        key: "SPLIT",
+        kind: Kind::Flag,
+        default: "false",
+        description: "Extract ISOs into the OPL folder as 1 GB USBExtreme parts listed in ul.cfg (for FAT32 drives)",
+    },
+    Setting {
+        section: "compress",
+        key: "FORMAT",
+        kind: Kind::Choice(&["none", "cso", "zso", "chd"]),
+        default: "none",
+        description: "Convert extracted ISOs to 'cso' (deflate) or 'zso' (LZ4), or ISOs and BIN/CUE sets to 'chd'; 'none' keeps plain images",
+    },
+    Setting {
+        section: "compress",
+        key: "THREADS",
+        kind: Kind::Number,
+        default: "0",
+        description: "Compression threads (0 uses every core)",
+    },
+    Setting {
+        section: "compress",
+        key: "KEEP_ISO",
+        kind: Kind::Flag,
+        default: "false",
+        description: "Keep the ISO (or BIN/CUE set) after the compressed image has been verified",
+    },
+    Setting {
+        section: "patch",
+        key: "FOLDER",
+        kind: Kind::Text,
+        default: "",
+        description: "Folder with IPS/PPF/xdelta patches, mapped to titles in patches.json or named after the title",
+    },
+    Setting {
+        section: "hooks",
+        key: "ON_DOWNLOAD_COMPLETE",
+        kind: Kind::Text,
+        default: "",
+        description: "Shell command run after each download; details are passed as PS2RD_* environment variables",
+    },
+    Setting {
+        section: "hooks",
+        key: "ON_VERIFY_FAILED",
+        kind: Kind::Text,
+        default: "",
+        description: "Shell command run when an image does not match its checksum",
+    },
+    Setting {
+        section: "hooks",
+        key: "ON_QUEUE_EMPTY",
+        kind: Kind::Text,
+        default: "",
+        description: "Shell command run when the download queue is empty",
+    },
+    Setting {
+        section: "hooks",
+        key: "ON_WATCH_MATCH",
+        kind: Kind::Text,
+        default: "",
+        description: "Shell command run when a saved search matches new or changed dumps ('watch')",
+    },
+    Setting {
+        section: "hooks",
+        key: "TIMEOUT",
+        kind: Kind::Number,
+        default: "300",
+        description: "Seconds a hook may run before it is killed",
+    },
+    Setting {
+        section: "server",
+        key: "LISTEN",
+        kind: Kind::Text,
+        default: DEFAULT_LISTEN,
+        description: "Address of the HTTP API started with 'serve'",
+    },
+    Setting {
+        section: "server",
+        key: "TOKEN",
+        kind: Kind::Secret,
+        default: "",
+        description: "Token clients must send; required unless LISTEN is a loopback address",
+    },
+];
+
+/// Where the effective value of a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// The built-in default
+    Default,
+    /// A config file, with the line the setting is on when it could be found
+    File(PathBuf, Option<usize>),
+    /// An environment variable
+    Env(String),
+    /// A `--set` command line flag
+    Cli,
+    /// An answer to `config init`
+    Prompt,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path, Some(line)) => write!(f, "{}:{}", path.display(), line),
+            Source::File(path, None) => write!(f, "{}", path.display()),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Cli => write!(f, "--set"),
+            Source::Prompt => write!(f, "config init"),
+        }
+    }
+}
+
+/// The effective value of a setting.
+#[derive(Debug, Clone)]
+pub struct Value {
+    /// The setting
+    pub setting: &'static Setting,
+    /// Value as text ('1'/'0' or 'true'/'false' for flags, ';'-separated for lists)
+    pub value: String,
+    /// Layer the value came from
+    pub source: Source,
+}
+
+/// A problem found in the configuration, e.g. an unknown key or a value of the wrong type.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// Where the problem is: 'file:line', 'env NAME' or '--set'
+    pub location: String,
+    /// What is wrong, with a suggestion when one is known
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Error::ConfigInvalid(diagnostic.to_string())
+    }
+}
+
+type Checked<T> = std::result::Result<T, Diagnostic>;
+
+impl Value {
+    /// The value as shown to the user; secrets are masked.
+    pub fn display(&self) -> &str {
+        match self.setting.kind {
+            Kind::Secret if !self.value.is_empty() => "********",
+            _ => &self.value,
+        }
+    }
+
+    fn invalid(&self, expected: &str) -> Diagnostic {
+        Diagnostic {
+            location: self.source.to_string(),
+            message: format!("{}.{} must be {}, got '{}'", self.setting.section, self.setting.key, expected, self.display()),
+        }
+    }
+
+    /// Checks that the value fits the setting's kind.
+    fn validate(&self) -> Checked<()> {
+        match self.setting.kind {
+            Kind::Text | Kind::Secret | Kind::List => Ok(()),
+            Kind::Url => self.url().map(drop),
+            Kind::Urls => self.urls().map(drop),
+            // An empty TIMEOUT_REQUEST means no limit
+            Kind::Number if self.value.trim().is_empty() && self.setting.is("Download", "TIMEOUT_REQUEST") => Ok(()),
+            Kind::Number => self.number().map(drop),
+            Kind::Count => self.positive().map(drop),
+            Kind::Flag => self.flag().map(drop),
+            Kind::Choice(_) => self.choice().map(drop),
+        }
+    }
+
+    fn text(&self) -> String {
+        self.value.trim().to_string()
+    }
+
+    fn url(&self) -> Checked<String> {
+        let text = self.text();
+        match is_folder_url(&text) {
+            true => Ok(text),
+            false => Err(self.invalid("an http(s) URL ending in '/'")),
+        }
+    }
+
+    fn urls(&self) -> Checked<Vec<String>> {
+        let urls = self.list();
+        match urls.iter().all(|url| is_folder_url(url)) {
+            true => Ok(urls),
+            false => Err(self.invalid("http(s) URLs ending in '/'")),
+        }
+    }
+
+    fn number(&self) -> Checked<u64> {
+        self.value.trim().parse().map_err(|_| self.invalid("a whole number"))
+    }
+
+    /// A number that must not be 0.
+    fn positive(&self) -> Checked<u64> {
+        match self.number()? {
+            0 => Err(self.invalid("greater than 0")),
+            number => Ok(number),
+        }
+    }
+
+    fn flag(&self) -> Checked<bool> {
+        match self.value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(self.invalid("true or false")),
+        }
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.value.split(';').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+    }
+
+    fn choice(&self) -> Checked<String> {
+        let value = self.value.trim().to_ascii_lowercase();
+        match self.setting.kind {
+            Kind::Choice(choices) if !choices.contains(&value.as_str()) => {
+                let choices: Vec<String> = choices.iter().map(|choice| format!("'{}'", choice)).collect();
+                Err(self.invalid(&format!("one of {}", choices.join(", "))))
+            }
+            _ => Ok(value),
+        }
+    }
+}
+
+/// Setting values merged from the built-in defaults, a config file, `PS2RD_*` environment variables
+/// and `--set` flags, each layer overriding the previous one.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    values: Vec<Value>,
+    file: Option<PathBuf>,
+    unknown: Vec<Diagnostic>,
+    profiles: Vec<Profile>,
+    profile: Option<String>,
+}
+
+/// Values of a `[profile.<name>]` section of the config file.
+#[derive(Debug, Clone)]
+struct Profile {
+    name: String,
+    values: Vec<Value>,
+}
+
+impl Settings {
+    /// The built-in defaults.
+    pub fn defaults() -> Self {
+        Self {
+            values: SETTINGS
+                .iter()
+                .map(|setting| Value {
+                    setting,
+                    value: setting.default.to_string(),
+                    source: Source::Default,
+                })
+                .collect(),
+            file: None,
+            unknown: Vec::new(),
+            profiles: Vec::new(),
+            profile: None,
+        }
+    }
+
+    /// Loads the layered settings: defaults, then `file` (or the first config file found in the
+    /// recommended locations, created if there is none; see `Config::locate`), then the file's `[profile.<name>]` section
+    /// for `profile` (or `PS2RD_PROFILE`), then environment variables, then `overrides`
+    /// ('SECTION.KEY=VALUE'). Values are not validated yet; see `config` and `check`.
+    pub fn load(file: Option<&Path>, profile: Option<&str>, overrides: &[String]) -> Result<Self> {
+        let path = Config::locate(file)?;
+        let mut settings = Self::defaults();
+        settings.apply_file(&path)?;
+        // Loading never rewrites the file; 'config migrate' does
+        if needs_migration(&path) {
+            eprintln!(
+                "Warning: {} uses an older config layout and needs migration; run 'ps2-redump-downloader config migrate'",
+                path.display()
+            );
+        }
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| std::env::var(PROFILE_ENV).ok().filter(|name| !name.is_empty()));
+        if let Some(name) = profile {
+            settings.apply_profile(&name)?;
+        }
+        settings.apply_env();
+        for assignment in overrides {
+            settings.apply_override(assignment)?;
+        }
+        Ok(settings)
+    }
+
+    /// Applies a config file: TOML if the name ends in '.toml', INI otherwise. `[profile.<name>]`
+    /// sections are kept for `apply_profile`. Sections and keys that are not settings are kept as
+    /// warnings (see `warnings`).
+    pub fn apply_file(&mut self, path: &Path) -> Result<()> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::ConfigInvalid(format!("Failed to load config {}: {}", path.display(), e)))?;
+        // (section, key, value) in file order; a key of None is a whole section. Profile settings
+        // are in sections named 'profile.<name>.<section>'.
+        let mut entries: Vec<(String, Option<String>, Option<String>)> = Vec::new();
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+            let table: toml::Table = content
+                .parse()
+                .map_err(|e| Error::ConfigInvalid(format!("{}: {}", path.display(), e)))?;
+            if let Some(version) = table.get("VERSION").and_then(toml::Value::as_integer) {
+                check_version(path, version)?;
+            }
+            let unsupported = |section: &str, key: &str| {
+                let location = Source::File(path.to_path_buf(), locate(&content, section, Some(key)));
+                Error::ConfigInvalid(format!("{}: {}.{} has an unsupported value", location, section, key))
+            };
+            for (section, keys) in &table {
+                if section == "VERSION" {
+                    continue;
+                }
+                let Some(keys) = keys.as_table() else {
+                    entries.push((String::new(), Some(section.clone()), None));
+                    continue;
+                };
+                entries.push((section.clone(), None, None));
+                for (key, value) in keys {
+                    match value.as_table() {
+                        Some(profile) if section.eq_ignore_ascii_case(PROFILE_SECTION) => {
+                            let name = format!("{}.{}", PROFILE_SECTION, key);
+                            for (inner, keys) in profile {
+                                let Some(keys) = keys.as_table() else {
+                                    let value = toml_text(keys).ok_or_else(|| unsupported(&name, inner))?;
+                                    entries.push((name.clone(), Some(inner.clone()), Some(value)));
+                                    continue;
+                                };
+                                let inner = format!("{}.{}", name, inner);
+                                entries.push((inner.clone(), None, None));
+                                for (key, value) in keys {
+                                    let value = toml_text(value).ok_or_else(|| unsupported(&inner, key))?;
+                                    entries.push((inner.clone(), Some(key.clone()), Some(value)));
+                                }
+                            }
+                            entries.push((name, None, None));
+                        }
+                        _ => {
+                            let value = toml_text(value).ok_or_else(|| unsupported(section, key))?;
+                            entries.push((section.clone(), Some(key.clone()), Some(value)));
+                        }
+                    }
+                }
+            }
+        } else {
+            let mut ini = Ini::new_cs();
+            // ';' separates list items, so only '#' starts an inline comment
+            ini.set_inline_comment_symbols(Some(&['#']));
+            let map = ini
+                .read(content.clone())
+                .map_err(|e| Error::ConfigInvalid(format!("Failed to load config {}: {}", path.display(), e)))?;
+            for (section, keys) in map {
+                // configparser puts keys that precede every section header into 'default'
+                let section = if section == ini.defaults().default_section { String::new() } else { section };
+                if !section.is_empty() {
+                    entries.push((section.clone(), None, None));
+                }
+                for (key, value) in keys {
+                    if section.is_empty() && key.eq_ignore_ascii_case("VERSION") {
+                        continue;
+                    }
+                    // Profile keys name their section: 'download.max_retries'
+                    match key.split_once('.') {
+                        Some((inner, key)) if profile_name(&section).is_some() => {
+                            let inner = format!("{}.{}", section, inner);
+                            entries.push((inner.clone(), None, None));
+                            entries.push((inner, Some(key.to_string()), value));
+                        }
+                        _ => entries.push((section.clone(), Some(key), value)),
+                    }
+                }
+            }
+        }
+
+        let mut entries: Vec<_> = entries
+            .into_iter()
+            .map(|(section, key, value)| (locate(&content, &section, key.as_deref()), section, key, value))
+            .collect();
+        entries.sort_by_key(|(line, ..)| line.unwrap_or(usize::MAX));
+
+        for (line, section, key, value) in entries {
+            let source = Source::File(path.to_path_buf(), line);
+            // Settings of a profile are checked like the base ones, then kept for `apply_profile`
+            let (profile, inner) = match profile_name(&section) {
+                Some((name, inner)) => (Some(name), inner),
+                None => (None, section.as_str()),
+            };
+            match (key, value) {
+                (None, _) => {
+                    if let (Some(name), "") = (profile, inner) {
+                        self.profile_mut(name);
+                        continue;
+                    }
+                    if profile.is_none() && section.eq_ignore_ascii_case(PROFILE_SECTION) {
+                        continue;
+                    }
+                    if !SETTINGS.iter().any(|setting| setting.section.eq_ignore_ascii_case(inner)) {
+                        let suggestion = suggest(inner, SETTINGS.iter().map(|setting| setting.section))
+                            .map(|known| match profile {
+                                Some(name) => format!("; did you mean [{}.{}.{}]?", PROFILE_SECTION, name, known),
+                                None => format!("; did you mean [{}]?", known),
+                            })
+                            .unwrap_or_default();
+                        self.unknown.push(Diagnostic {
+                            location: source.to_string(),
+                            message: format!("unknown section [{}]{}", section, suggestion),
+                        });
+                    }
+                }
+                (Some(key), value) => {
+                    let known_section = SETTINGS.iter().any(|setting| setting.section.eq_ignore_ascii_case(inner));
+                    if !known_section && !inner.is_empty() {
+                        // Already reported with the section
+                        continue;
+                    }
+                    let setting = SETTINGS.iter().find(|setting| setting.is(inner, &key));
+                    match (setting, value, profile) {
+                        (Some(setting), Some(value), Some(name)) => {
+                            self.profile_mut(name).values.push(Value { setting, value, source });
+                        }
+                        (Some(setting), Some(value), None) => {
+                            self.set(setting.section, setting.key, value, source);
+                        }
+                        // An INI key without '=' keeps the default
+                        (Some(_), None, _) => {}
+                        (None, ..) => {
+                            let message = if let (Some(_), "") = (profile, inner) {
+                                let hint = suggest(&key, SETTINGS.iter().map(|setting| setting.key))
+                                    .and_then(|known| SETTINGS.iter().find(|setting| setting.key == known))
+                                    .map(|setting| format!("; did you mean '{}.{}'?", setting.section, setting.key))
+                                    .unwrap_or_default();
+                                format!("key '{}' in [{}] needs a section{}", key, section, hint)
+                            } else if inner.is_empty() {
+                                format!("key '{}' is not in a section{}", key, suggest_setting(inner, &key))
+                            } else {
+                                format!("unknown key '{}' in [{}]{}", key, section, suggest_setting(inner, &key))
+                            };
+                            self.unknown.push(Diagnostic { location: source.to_string(), message });
+                        }
+                    }
+                }
+            }
+        }
+        self.file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// The profile of the config file with this name, added if it is not known yet.
+    fn profile_mut(&mut self, name: &str) -> &mut Profile {
+        let index = match self.profiles.iter().position(|profile| profile.name == name) {
+            Some(index) => index,
+            None => {
+                self.profiles.push(Profile {
+                    name: name.to_string(),
+                    values: Vec::new(),
+                });
+                self.profiles.len() - 1
+            }
+        };
+        &mut self.profiles[index]
+    }
+
+    /// Applies the values of the config file's `[profile.<name>]` section over the base sections.
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        let Some(profile) = self.profiles.iter().find(|profile| profile.name == name).cloned() else {
+            let names: Vec<&str> = self.profiles.iter().map(|profile| profile.name.as_str()).collect();
+            let hint = match suggest(name, names.iter().copied()) {
+                Some(known) => format!("; did you mean '{}'?", known),
+                None if names.is_empty() => " (the config file defines no profiles)".to_string(),
+                None => format!(" (profiles: {})", names.join(", ")),
+            };
+            return Err(Error::ConfigInvalid(format!("unknown profile '{}'{}", name, hint)));
+        };
+        for value in profile.values {
+            self.set(value.setting.section, value.setting.key, value.value, value.source);
+        }
+        self.profile = Some(profile.name);
+        Ok(())
+    }
+
+    /// Applies every `PS2RD_<SECTION>_<KEY>` environment variable that is set.
+    pub fn apply_env(&mut self) {
+        for setting in SETTINGS {
+            let name = setting.env_name();
+            if let Ok(value) = std::env::var(&name) {
+                self.set(setting.section, setting.key, value, Source::Env(name));
+            }
+        }
+    }
+
+    /// Applies a `--set` flag of the form 'SECTION.KEY=VALUE' (names are not case-sensitive).
+    pub fn apply_override(&mut self, assignment: &str) -> Result<()> {
+        let invalid = || Error::ConfigInvalid(format!("--set expects SECTION.KEY=VALUE, got '{}'", assignment));
+        let (name, value) = assignment.split_once('=').ok_or_else(invalid)?;
+        let (section, key) = name.trim().split_once('.').ok_or_else(invalid)?;
+        if !self.set(section.trim(), key.trim(), value.trim().to_string(), Source::Cli) {
+            return Err(Error::ConfigInvalid(format!(
+                "--set: unknown setting '{}'{}",
+                name.trim(),
+                suggest_setting(section.trim(), key.trim())
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sets a setting to an answer of `config init`, checking that it fits the setting's kind.
+    pub fn assign(&mut self, section: &str, key: &str, value: &str) -> std::result::Result<(), Diagnostic> {
+        let setting = SETTINGS
+            .iter()
+            .find(|setting| setting.is(section, key))
+            .expect("setting is listed in SETTINGS");
+        let answer = Value {
+            setting,
+            value: value.trim().to_string(),
+            source: Source::Prompt,
+        };
+        answer.validate()?;
+        self.set(section, key, answer.value, Source::Prompt);
+        Ok(())
+    }
+
+    /// Renders the values as a documented TOML config file, each setting under its description.
+    pub fn to_file(&self) -> String {
+        let mut file = format!(
+            "# PS2 Redump Downloader configuration\n\
+             # Any setting can be overridden with a PS2RD_<SECTION>_<KEY> environment variable\n\
+             # or with --set SECTION.KEY=VALUE; 'ps2-redump-downloader config show' lists the effective values.\n\
+             \n{}\nVERSION = {}\n",
+            VERSION_COMMENT, SCHEMA_VERSION
+        );
+        let mut section = "";
+        for value in &self.values {
+            let setting = value.setting;
+            if setting.section != section {
+                section = setting.section;
+                file.push_str(&format!("\n[{}]\n", section));
+            }
+            file.push_str(&format!(
+                "# {}\n{} = {}\n",
+                setting.description,
+                setting.key,
+                toml_value(setting.kind, &value.value)
+            ));
+        }
+        file
+    }
+
+    /// Sets a value; returns false if no setting has this name.
+    fn set(&mut self, section: &str, key: &str, value: String, source: Source) -> bool {
+        match self.values.iter_mut().find(|entry| entry.setting.is(section, key)) {
+            Some(entry) => {
+                entry.value = value;
+                entry.source = source;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The effective values, in file order.
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    /// The profile that was applied, if any.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// The config file that was applied, if any.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Sections and keys of the config file that are not settings (most likely typos).
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.unknown
+    }
+
+    /// Every problem of the configuration: unknown sections and keys, values of the wrong type and
+    /// settings that contradict each other. An empty list means the configuration is valid.
+    pub fn check(&self) -> Vec<Diagnostic> {
+        let mut problems = self.unknown.clone();
+        problems.extend(self.values.iter().filter_map(|value| value.validate().err()));
+        let valid = problems.len() == self.unknown.len();
+        // Every profile, not just the selected one
+        for problem in self.profiles.iter().flat_map(|profile| &profile.values).filter_map(|value| value.validate().err()) {
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+        if valid {
+            match self.build() {
+                Ok(config) => problems.extend(self.constraints(&config)),
+                Err(problem) => problems.push(problem),
+            }
+        }
+        problems
+    }
+
+    fn value(&self, section: &str, key: &str) -> &Value {
+        self.values
+            .iter()
+            .find(|value| value.setting.is(section, key))
+            .expect("setting is listed in SETTINGS")
+    }
+
+    /// Builds the configuration from the effective values, failing on the first invalid one.
+    pub fn config(&self) -> Result<Config> {
+        let config = self.build()?;
+        if let Some(problem) = self.constraints(&config).into_iter().next() {
+            return Err(problem.into());
+        }
+        Ok(config)
+    }
+
+    fn build(&self) -> Checked<Config> {
+        let value = |section, key| self.value(section, key);
+
+        let compress_format = Some(value("compress", "FORMAT").choice()?).filter(|format| format != "none");
+        let timeout_request = match value("Download", "TIMEOUT_REQUEST") {
+            timeout if timeout.value.trim().is_empty() => None,
+            timeout => Some(timeout.number()?),
+        };
+        let server_listen = Some(value("server", "LISTEN").text())
+            .filter(|listen| !listen.is_empty())
+            .unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+
+        Ok(Config {
+            iso_url: value("url", "ISO").url()?,
+            mirrors: value("url", "MIRRORS").urls()?,
+            list_files_json_name: value("Download", "LIST_FILES_JSON_NAME").text(),
+            external_iso_download: value("Download", "EXTERNAL_ISO").flag()?,
+            max_retries: value("Download", "MAX_RETRIES").positive()? as u32,
+            delay_between_retries: value("Download", "DELAY_BETWEEN_RETRIES").positive()?,
+            timeout_request,
+            download_concurrency: value("Download", "CONCURRENCY").positive()? as usize,
+            speed_limit: Some(value("Download", "SPEED_LIMIT").number()?).filter(|&limit| limit > 0),
+            m3u_playlists: value("Download", "M3U").flag()?,
+            tmp_folder_name: value("folder", "TMP_FOLDER_NAME").text(),
+            tmp_iso_folder_name: value("folder", "TMP_ISO_FOLDER_NAME").text(),
+            library_roots: value("library", "ROOTS").list(),
+            library_checksum: value("library", "CHECKSUM").flag()?,
+            preferred_regions: value("1g1r", "REGIONS").list(),
+            preferred_languages: value("1g1r", "LANGUAGES").list(),
+            excluded_tags: value("1g1r", "EXCLUDE").list(),
+            opl_folder: value("opl", "FOLDER").text(),
+            opl_hardlink: value("opl", "MODE").choice()? == "hardlink",
+            opl_split: value("opl", "SPLIT").flag()?,
+            compress_format,
+            compress_threads: value("compress", "THREADS").number()? as usize,
+            compress_keep_iso: value("compress", "KEEP_ISO").flag()?,
+            patch_folder: value("patch", "FOLDER").text(),
+            hook_on_download_complete: value("hooks", "ON_DOWNLOAD_COMPLETE").text(),
+            hook_on_verify_failed: value("hooks", "ON_VERIFY_FAILED").text(),
+            hook_on_queue_empty: value("hooks", "ON_QUEUE_EMPTY").text(),
+            hook_on_watch_match: value("hooks", "ON_WATCH_MATCH").text(),
+            hook_timeout: value("hooks", "TIMEOUT").number()?,
+            server_listen,
+            server_token: value("server", "TOKEN").text(),
+        })
+    }
+
+    /// Rules spanning several settings.
+    fn constraints(&self, config: &Config) -> Vec<Diagnostic> {
+        let mut problems = Vec::new();
+        if config.opl_split && config.opl_folder.is_empty() {
+            let split = self.value("opl", "SPLIT");
+            problems.push(Diagnostic {
+                location: split.source.to_string(),
+                message: "opl.SPLIT needs opl.FOLDER".to_string(),
+            });
+        }
+        problems
+    }
+}
+
+/// Returns true if `text` is an http(s) URL ending in '/', which file names are appended to.
+fn is_folder_url(text: &str) -> bool {
+    reqwest::Url::parse(text)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host() && text.ends_with('/'))
+}
+
+/// Splits a 'profile.<name>.<section>' section name into profile name and section (empty for the
+/// profile's own section). Returns None for other sections.
+fn profile_name(section: &str) -> Option<(&str, &str)> {
+    let prefix = section.get(..PROFILE_SECTION.len() + 1)?;
+    if !prefix.eq_ignore_ascii_case("profile.") {
+        return None;
+    }
+    let rest = &section[prefix.len()..];
+    Some(rest.split_once('.').unwrap_or((rest, "")))
+}
+
+/// Finds the line of a setting like `find_line`, including profile settings written as dotted keys:
+/// `Download.MAX_RETRIES = 3` under `[profile.<name>]`.
+fn locate(content: &str, section: &str, key: Option<&str>) -> Option<usize> {
+    find_line(content, section, key).or_else(|| {
+        let (name, inner) = profile_name(section)?;
+        let key = format!("{}.{}", inner, key?);
+        find_line(content, &format!("{}.{}", PROFILE_SECTION, name), Some(&key))
+    })
+}
+
+/// Finds the 1-based line of a section header (`key` None) or of a key inside a section of an INI
+/// or TOML file. Names are compared without regard to case; an empty section is the file's top.
+fn find_line(content: &str, section: &str, key: Option<&str>) -> Option<usize> {
+    let mut current = String::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            current = header.split(']').next().unwrap_or_default().trim().trim_matches('"').to_string();
+            if key.is_none() && current.eq_ignore_ascii_case(section) {
+                return Some(index + 1);
+            }
+            continue;
+        }
+        let Some(key) = key else {
+            continue;
+        };
+        let name = line.split(['=', ':']).next().unwrap_or_default().trim().trim_matches(['"', '\'']);
+        if current.eq_ignore_ascii_case(section) && name.eq_ignore_ascii_case(key) {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+
+/// The candidate closest to `name` by edit distance, if it is close enough to be a likely typo.
+fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name = name.to_ascii_lowercase();
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_ascii_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// A "did you mean" hint for an unknown key: a close key of the same section, or a setting with
+/// this key in another section.
+fn suggest_setting(section: &str, key: &str) -> String {
+    let same_section = SETTINGS.iter().filter(|setting| setting.section.eq_ignore_ascii_case(section));
+    if let Some(known) = suggest(key, same_section.map(|setting| setting.key)) {
+        return format!("; did you mean '{}'?", known);
+    }
+    let keys = SETTINGS.iter().map(|setting| setting.key);
+    match suggest(key, keys).and_then(|known| SETTINGS.iter().find(|setting| setting.key == known)) {
+        Some(setting) => format!("; did you mean '{}' in [{}]?", setting.key, setting.section),
+        None => String::new(),
+    }
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// A TOML value as setting text; arrays become ';'-separated lists.
+fn toml_text(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(text) => Some(text.clone()),
+        toml::Value::Integer(number) => Some(number.to_string()),
+        toml::Value::Boolean(flag) => Some(flag.to_string()),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(toml_text)
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(";")),
+        _ => None,
+    }
+}
+
+/// A setting value as TOML: numbers and flags bare when they parse, lists as arrays, the rest as strings.
+fn toml_value(kind: Kind, value: &str) -> String {
+    let text = value.trim();
+    let string = |text: &str| toml::Value::String(text.to_string()).to_string();
+    match kind {
+        Kind::Number | Kind::Count if text.parse::<u64>().is_ok() => text.to_string(),
+        Kind::Flag => match text.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => "true".to_string(),
+            "0" | "false" | "no" | "off" => "false".to_string(),
+            _ => string(value),
+        },
+        Kind::List | Kind::Urls => {
+            let items: Vec<String> =
+                text.split(';').map(str::trim).filter(|item| !item.is_empty()).map(string).collect();
+            format!("[{}]", items.join(", "))
+        }
+        _ => string(value),
+    }
+}
+
+/// A section or key name as TOML, quoted unless it is a bare key.
+fn toml_key(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        name.to_string()
+    } else {
+        toml::Value::String(name.to_string()).to_string()
+    }
+}
+
+/// Comment above the `VERSION` key of TOML files.
+const VERSION_COMMENT: &str = "# Layout version of this file; 'ps2-redump-downloader config migrate' upgrades files written for older versions";
+
+/// Fails if a file was written for a newer schema than this build understands.
+fn check_version(path: &Path, version: i64) -> Result<()> {
+    if version > i64::from(SCHEMA_VERSION) {
+        return Err(Error::ConfigInvalid(format!(
+            "{} uses config schema version {}, but this build only understands up to version {}; please update",
+            path.display(),
+            version,
+            SCHEMA_VERSION
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `migrate_file` would rewrite the file: an INI file without a TOML file next to it, or a
+/// TOML file with an older `VERSION`. Unreadable and invalid files are reported when applied, not here.
+pub fn needs_migration(path: &Path) -> bool {
+    let Ok(content) = fs::read_to_string(path) else {
+        return false;
+    };
+    if !path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+        return !path.with_extension("toml").exists();
+    }
+    content
+        .parse::<toml::Table>()
+        .is_ok_and(|table| table.get("VERSION").and_then(toml::Value::as_integer).unwrap_or(1) < i64::from(SCHEMA_VERSION))
+}
+
+/// Upgrades a config file written for an older schema to `SCHEMA_VERSION`, keeping its comments,
+/// and returns the path of the up-to-date file. An INI file is converted to a TOML file next to it
+/// and renamed with a '.bak' suffix; an older TOML file is copied to '.bak' before it is rewritten.
+/// A file that cannot be migrated is left alone (with a warning) and read as it is.
+pub fn migrate_file(path: &Path) -> Result<PathBuf> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::ConfigInvalid(format!("Failed to load config {}: {}", path.display(), e)))?;
+    let is_toml = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+    let (target, migrated) = if is_toml {
+        // Parse errors are reported when the file is applied
+        let Ok(table) = content.parse::<toml::Table>() else {
+            return Ok(path.to_path_buf());
+        };
+        let version = table.get("VERSION").and_then(toml::Value::as_integer).unwrap_or(1);
+        check_version(path, version)?;
+        if version == i64::from(SCHEMA_VERSION) {
+            return Ok(path.to_path_buf());
+        }
+        (path.to_path_buf(), stamp_version(&content))
+    } else {
+        let target = path.with_extension("toml");
+        if target.exists() {
+            eprintln!(
+                "Warning: {} was not migrated to TOML because {} already exists",
+                path.display(),
+                target.display()
+            );
+            return Ok(path.to_path_buf());
+        }
+        (target, stamp_version(&ini_to_toml(&content)))
+    };
+    if let Err(e) = migrated.parse::<toml::Table>() {
+        eprintln!("Warning: {} could not be migrated and is read as it is: {}", path.display(), e);
+        return Ok(path.to_path_buf());
+    }
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let backup = path.with_file_name(format!("{}.bak", file_name));
+    let written = if is_toml { fs::copy(path, &backup).map(drop) } else { Ok(()) }
+        .and_then(|_| fs::write(&target, &migrated))
+        .and_then(|_| if is_toml { Ok(()) } else { fs::rename(path, &backup) });
+    if let Err(e) = written {
+        eprintln!("Warning: {} could not be migrated and is read as it is: {}", path.display(), e);
+        return Ok(path.to_path_buf());
+    }
+    eprintln!(
+        "Migrated {} to config schema version {} ({}); the previous file is kept as {}",
+        path.display(),
+        SCHEMA_VERSION,
+        target.display(),
+        backup.display()
+    );
+    Ok(target)
+}
+
+/// Adds the `VERSION` key to an unversioned TOML file, after its leading comments.
+fn stamp_version(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let header = lines.iter().take_while(|line| line.trim_start().starts_with('#')).count();
+    let mut file = String::new();
+    for line in &lines[..header] {
+        file.push_str(line);
+        file.push('\n');
+    }
+    if header > 0 {
+        file.push('\n');
+    }
+    file.push_str(&format!("{}\nVERSION = {}\n", VERSION_COMMENT, SCHEMA_VERSION));
+    if lines.get(header).is_some_and(|line| !line.trim().is_empty()) {
+        file.push('\n');
+    }
+    for line in &lines[header..] {
+        file.push_str(line);
+        file.push('\n');
+    }
+    file
+}
+
+/// Converts an INI config file to TOML line by line, so comments and layout are kept.
+/// Values are typed after their setting; keys set twice keep the last value, like configparser.
+fn ini_to_toml(content: &str) -> String {
+    // (section, key) of each line, to find overridden keys
+    let mut section = String::new();
+    let mut assignments: Vec<Option<(String, String)>> = Vec::new();
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if let Some(header) = trimmed.strip_prefix('[') {
+            section = header.trim_end_matches(']').trim().to_string();
+            assignments.push(None);
+        } else {
+            let key = ini_assignment(trimmed).map(|(key, _)| key.to_ascii_lowercase());
+            assignments.push(key.map(|key| (section.to_ascii_lowercase(), key)));
+        }
+    }
+
+    let mut file = String::new();
+    let mut section = String::new();
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        let converted = if trimmed.is_empty() {
+            String::new()
+        } else if let Some(comment) = trimmed.strip_prefix('#').or_else(|| trimmed.strip_prefix(';')) {
+            format!("#{}", comment)
+        } else if let Some(header) = trimmed.strip_prefix('[') {
+            section = header.trim_end_matches(']').trim().to_string();
+            match profile_name(&section) {
+                Some((name, "")) => format!("[{}.{}]", PROFILE_SECTION, toml_key(name)),
+                _ => format!("[{}]", toml_key(&section)),
+            }
+        } else {
+            match ini_assignment(trimmed) {
+                Some(_) if assignments[index + 1..].contains(&assignments[index]) => {
+                    format!("# {}  (overridden below)", trimmed)
+                }
+                Some((key, value)) => {
+                    let (value, comment) = match value.split_once('#') {
+                        Some((value, comment)) => (value.trim(), format!("  #{}", comment)),
+                        None => (value, String::new()),
+                    };
+                    // Profile keys name their section: 'download.max_retries'
+                    let (inner, name) = match (profile_name(&section), key.split_once('.')) {
+                        (Some(_), Some((inner, name))) => (inner, name),
+                        _ => (section.as_str(), key),
+                    };
+                    let kind = SETTINGS
+                        .iter()
+                        .find(|setting| setting.is(inner, name))
+                        .map_or(Kind::Text, |setting| setting.kind);
+                    let key = match inner == section {
+                        true => toml_key(key),
+                        false => format!("{}.{}", toml_key(inner), toml_key(name)),
+                    };
+                    format!("{} = {}{}", key, toml_value(kind, value), comment)
+                }
+                None => format!("# {}", trimmed),
+            }
+        };
+        file.push_str(&converted);
+        file.push('\n');
+    }
+    file
+}
+
+/// Splits an INI line at the first '=' or ':' into key and value.
+fn ini_assignment(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(['=', ':'])?;
+    Some((key.trim(), value.trim())).filter(|(key, _)| !key.is_empty())
+}
+
+/// The documented config file written when none exists, generated from `SETTINGS`.
+pub fn default_file() -> String {
+    Settings::defaults().to_file()
+}
+
+/// Configuration for the downloader application, loaded from config.toml or config.ini.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Base URL for ISO downloads
     pub iso_url: String,
+    /// Fallback base URLs tried in order when a download from `iso_url` fails
+    pub mirrors: Vec<String>,
     /// Name of the JSON file containing the list of games
     pub list_files_json_name: String,
     /// Whether to use external browser for ISO download
@@ -17,159 +1237,176 @@ pub struct Config {
     pub delay_between_retries: u64,
     /// Timeout for requests (seconds)
     pub timeout_request: Option<u64>,
+    /// Number of games the download queue downloads at the same time
+    pub download_concurrency: usize,
+    /// KiB/s shared by all downloads, if limited
+    pub speed_limit: Option<u64>,
+    /// Whether an .m3u playlist is written for multi-disc games once every disc is present
+    pub m3u_playlists: bool,
     /// Name of the temporary folder
     pub tmp_folder_name: String,
     /// Name of the ISO folder inside the temporary folder
     pub tmp_iso_folder_name: String,
+    /// Extra folders scanned for games already owned (the ISO folder is always scanned)
+    pub library_roots: Vec<String>,
+    /// Whether the library scanner computes CRC32 checksums to recognise renamed files
+    pub library_checksum: bool,
+    /// Regions in order of preference for 1G1R selection
+    pub preferred_regions: Vec<String>,
+    /// Language codes in order of preference for 1G1R selection
+    pub preferred_languages: Vec<String>,
+    /// Title tags (e.g. 'Demo') that exclude a version from 1G1R selection
+    pub excluded_tags: Vec<String>,
+    /// Root of the Open PS2 Loader folder ISOs are exported to (empty disables the export)
+    pub opl_folder: String,
+    /// Whether ISOs are hardlinked into the OPL folder instead of copied
+    pub opl_hardlink: bool,
+    /// Whether ISOs are extracted straight into the OPL folder as USBExtreme parts (for FAT32 drives)
+    pub opl_split: bool,
+    /// Compressed format extracted images are converted to ('cso', 'zso' or 'chd'), if any
+    pub compress_format: Option<String>,
+    /// Threads used for compression (0 uses every core)
+    pub compress_threads: usize,
+    /// Whether the ISO is kept after a successful compression
+    pub compress_keep_iso: bool,
+    /// Folder holding patches and their `patches.json` manifest (empty disables patching)
+    pub patch_folder: String,
+    /// Command run after each download
+    pub hook_on_download_complete: String,
+    /// Command run when an image fails its checksum verification
+    pub hook_on_verify_failed: String,
+    /// Command run when the download queue is empty
+    pub hook_on_queue_empty: String,
+    /// Command run when a saved search matches new or changed dumps
+    pub hook_on_watch_match: String,
+    /// Seconds a hook may run before it is killed
+    pub hook_timeout: u64,
+    /// Address the HTTP API listens on (`serve`)
+    pub server_listen: String,
+    /// Token clients must send to the HTTP API (empty allows only loopback addresses)
+    pub server_token: String,
 }
 
 impl Config {
-    /// Loads configuration from the given path (expands tilde if present).
+    /// Loads configuration from the given TOML or INI file (expands tilde if present);
+    /// settings missing from the file keep their defaults.
     pub fn load(path: &str) -> Result<Self> {
-        let mut config = Ini::new();
-        config.load(Self::expand_tilde(path)).map_err(|e| anyhow::anyhow!("Failed to load config: {}", e))?;
-
-        let url_section = config.get("url", "ISO").map_or("https://myrient.erista.me/files/Redump/Sony%20-%20PlayStation%202/".to_string(), |s| s.to_string());
-        
-        let list_files_json_name = config.get("Download", "LIST_FILES_JSON_NAME").map_or("listPS2Titles.json".to_string(), |s| s.to_string());
-        let external_iso_download = config.getuint("Download", "EXTERNAL_ISO").unwrap_or(Some(0)).unwrap_or(0) != 0;
-        let max_retries = config.getuint("Download", "MAX_RETRIES").unwrap_or(Some(5)).unwrap_or(5) as u32;
-        let delay_between_retries = config.getuint("Download", "DELAY_BETWEEN_RETRIES").unwrap_or(Some(5)).unwrap_or(5) as u64;
-        let timeout_request = config.getuint("Download", "TIMEOUT_REQUEST").unwrap_or(None).map(|v| v as u64);
-        
-        let tmp_folder_name = config.get("folder", "TMP_FOLDER_NAME").map_or("tmp".to_string(), |s| s.to_string());
-        let tmp_iso_folder_name = config.get("folder", "TMP_ISO_FOLDER_NAME").map_or("iso_files".to_string(), |s| s.to_string());
-
-        let config = Config {
-            iso_url: url_section,
-            list_files_json_name,
-            external_iso_download,
-            max_retries,
-            delay_between_retries,
-            timeout_request,
-            tmp_folder_name,
-            tmp_iso_folder_name,
-        };
+        let mut settings = Settings::defaults();
+        settings.apply_file(&Self::expand_tilde(path))?;
+        settings.config()
+    }
 
-        // Validate configuration
-        if config.max_retries == 0 {
-            anyhow::bail!("MAX_RETRIES must be greater than 0");
-        }
-        if config.delay_between_retries == 0 {
-            anyhow::bail!("DELAY_BETWEEN_RETRIES must be greater than 0");
-        }
+    /// Loads configuration from the recommended locations, or creates a default config if not found,
+    /// then applies `PS2RD_*` environment variables.
+    pub fn load_or_create() -> Result<(Self, PathBuf)> {
+        let (config, settings) = Self::load_layered(None, None, &[])?;
+        let path = settings.file().map(Path::to_path_buf).unwrap_or_default();
+        Ok((config, path))
+    }
 
-        Ok(config)
+    /// Loads the layered configuration: defaults, then `file` (or the first config file found in the
+    /// recommended locations, created if there is none), then the `profile` section, then environment
+    /// variables, then `overrides` ('SECTION.KEY=VALUE'). Returns the settings too, to show where each
+    /// value came from.
+    pub fn load_layered(file: Option<&Path>, profile: Option<&str>, overrides: &[String]) -> Result<(Self, Settings)> {
+        let settings = Settings::load(file, profile, overrides)?;
+        Ok((settings.config()?, settings))
+    }
+
+    /// Where a new config file goes: config.toml in the user config folder.
+    pub fn default_path() -> PathBuf {
+        #[cfg(windows)]
+        let folder = std::env::var_os("APPDATA").map(|appdata| PathBuf::from(appdata).join("ps2-redump-downloader"));
+        #[cfg(not(windows))]
+        let folder = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/ps2-redump-downloader"));
+        folder.unwrap_or_default().join("config.toml")
     }
 
-    /// Loads configuration from the recommended locations, or creates a default config if not found.
-    pub fn load_or_create() -> Result<(Self, std::path::PathBuf)> {
-        use std::path::PathBuf;
-        use std::fs;
-        use std::io::Write;
+    /// Returns `file`, which must exist, or the config file found (or created) by `find_or_create`.
+    pub fn locate(file: Option<&Path>) -> Result<PathBuf> {
+        match file {
+            Some(path) if !path.exists() => Err(Error::ConfigInvalid(format!("config file {} not found", path.display()))),
+            Some(path) => Ok(path.to_path_buf()),
+            None => Self::find_or_create(),
+        }
+    }
 
-        // List of config paths to try, in order
-        let mut candidates = Vec::new();
+    /// Finds the config file (config.toml before config.ini in each location), creating a default
+    /// config.toml in the user config folder if there is none.
+    fn find_or_create() -> Result<PathBuf> {
+        // Folders to look in, in order
+        let mut folders = Vec::new();
         // 1. Current directory
-        candidates.push(PathBuf::from("config.ini"));
+        folders.push(PathBuf::new());
 
         #[cfg(windows)]
         {
             if let Some(appdata) = std::env::var_os("APPDATA") {
-                candidates.push(PathBuf::from(appdata).join("ps2-redump-downloader/config.ini"));
+                folders.push(PathBuf::from(appdata).join("ps2-redump-downloader"));
             }
-            candidates.push(PathBuf::from("C:/ProgramData/ps2-redump-downloader/config.ini"));
+            folders.push(PathBuf::from("C:/ProgramData/ps2-redump-downloader"));
         }
         #[cfg(not(windows))]
         {
             if let Some(home) = std::env::var_os("HOME") {
-                candidates.push(PathBuf::from(home).join(".config/ps2-redump-downloader/config.ini"));
+                folders.push(PathBuf::from(home).join(".config/ps2-redump-downloader"));
             }
-            candidates.push(PathBuf::from("/etc/ps2-redump-downloader/config.ini"));
+            folders.push(PathBuf::from("/etc/ps2-redump-downloader"));
         }
 
-        // Try to load from each candidate
-        for path in &candidates {
-            if path.exists() {
-                match Self::load(path.to_str().unwrap()) {
-                    Ok(cfg) => return Ok((cfg, path.clone())),
-                    Err(e) => eprintln!("Failed to load config from {}: {}", path.display(), e),
-                }
-            }
+        let found = folders
+            .iter()
+            .flat_map(|folder| [folder.join("config.toml"), folder.join("config.ini")])
+            .find(|path| path.exists());
+        if let Some(path) = found {
+            return Ok(path);
         }
 
         // Not found: create default config in user config dir
-        #[cfg(windows)]
-        let default_path = {
-            if let Some(appdata) = std::env::var_os("APPDATA") {
-                PathBuf::from(appdata).join("ps2-redump-downloader/config.ini")
-            } else {
-                PathBuf::from("config.ini")
-            }
-        };
-        #[cfg(not(windows))]
-        let default_path = {
-            if let Some(home) = std::env::var_os("HOME") {
-                PathBuf::from(home).join(".config/ps2-redump-downloader/config.ini")
-            } else {
-                PathBuf::from("config.ini")
-            }
-        };
+        let default_path = Self::default_path();
 
         // Ensure parent directory exists
         if let Some(parent) = default_path.parent() {
             let _ = fs::create_dir_all(parent);
         }
-
-        // Default config contents (from README.md)
-        let default_config = r#"[url]
-ISO = https://myrient.erista.me/files/Redump/Sony%20-%20PlayStation%202/
-
-[Download]
-# Downloaded Game list fileName 
-LIST_FILES_JSON_NAME = listPS2Titles.json 
-
-# Download ISO file using navigator
-EXTERNAL_ISO = 0 
-
-MAX_RETRIES = 10
-DELAY_BETWEEN_RETRIES = 10
-TIMEOUT_REQUEST = 600
-
-[folder]
-TMP_FOLDER_NAME = ~/PS2-Games
-TMP_ISO_FOLDER_NAME = iso_files
-"#;
-        let mut file = fs::File::create(&default_path)
-            .map_err(|e| anyhow::anyhow!("Failed to create default config at {}: {}", default_path.display(), e))?;
-        file.write_all(default_config.as_bytes())
-            .map_err(|e| anyhow::anyhow!("Failed to write default config at {}: {}", default_path.display(), e))?;
-        eprintln!("No config.ini found. Created a default config at {}. Please edit it as needed.", default_path.display());
-
-        // Now load the config
-        let cfg = Self::load(default_path.to_str().unwrap())?;
-        Ok((cfg, default_path))
+        // Failing to write is an I/O problem (permissions, disk full), not a content error
+        fs::write(&default_path, default_file()).map_err(|e| {
+            Error::from(std::io::Error::new(
+                e.kind(),
+                format!("Failed to create default config at {}: {}", default_path.display(), e),
+            ))
+        })?;
+        eprintln!("No config file found. Created a default config at {}. Please edit it as needed.", default_path.display());
+        Ok(default_path)
     }
 
     /// Expands a path that starts with ~ to the user's home directory.
     fn expand_tilde(path: &str) -> std::path::PathBuf {
-        if path.starts_with("~/") {
+        if let Some(rest) = path.strip_prefix("~/") {
             #[cfg(windows)]
             {
                 if let Some(home) = std::env::var_os("USERPROFILE") {
-                    return std::path::PathBuf::from(home).join(&path[2..]);
+                    return std::path::PathBuf::from(home).join(rest);
                 }
             }
             #[cfg(not(windows))]
             {
                 if let Some(home) = std::env::var_os("HOME") {
-                    return std::path::PathBuf::from(home).join(&path[2..]);
+                    return std::path::PathBuf::from(home).join(rest);
                 }
             }
         }
         std::path::PathBuf::from(path)
     }
 
+    /// URLs a game's ZIP is downloaded from, in the order they are tried: the ISO folder, then each mirror.
+    pub fn download_urls(&self, link: &str) -> Vec<String> {
+        std::iter::once(&self.iso_url)
+            .chain(&self.mirrors)
+            .map(|base| format!("{}{}", base, link))
+            .collect()
+    }
+
     /// Returns the expanded path to the temporary folder.
     pub fn tmp_folder_path(&self) -> std::path::PathBuf {
         Self::expand_tilde(&self.tmp_folder_name)
@@ -184,4 +1421,158 @@ TMP_ISO_FOLDER_NAME = iso_files
     pub fn list_json_path(&self) -> std::path::PathBuf {
         Self::expand_tilde(&self.tmp_folder_name).join(&self.list_files_json_name)
     }
+
+    /// Returns every folder scanned by the library scanner, starting with the ISO folder.
+    pub fn library_root_paths(&self) -> Vec<std::path::PathBuf> {
+        let mut roots = vec![self.tmp_iso_folder_path()];
+        roots.extend(self.library_roots.iter().map(|root| Self::expand_tilde(root)));
+        roots
+    }
+
+    /// Returns the format extracted ISOs are compressed to, if CSO/ZSO compression is enabled.
+    pub fn compression(&self) -> Option<Format> {
+        self.compress_format.as_deref().and_then(Format::from_name)
+    }
+
+    /// Returns true if extracted images are converted to CHD.
+    pub fn chd_output(&self) -> bool {
+        self.compress_format.as_deref() == Some("chd")
+    }
+
+    /// Returns the preferences used to pick one version per game.
+    pub fn preferences(&self) -> Preferences {
+        Preferences {
+            regions: self.preferred_regions.clone(),
+            languages: self.preferred_languages.clone(),
+            exclude: self.excluded_tags.clone(),
+        }
+    }
+
+    /// Returns the configured hook commands.
+    pub fn hooks(&self) -> Hooks {
+        Hooks::new(
+            &self.hook_on_download_complete,
+            &self.hook_on_verify_failed,
+            &self.hook_on_queue_empty,
+            &self.hook_on_watch_match,
+            std::time::Duration::from_secs(self.hook_timeout),
+        )
+    }
+
+    /// Returns the expanded path to the patch folder, if one is configured.
+    pub fn patch_folder_path(&self) -> Option<std::path::PathBuf> {
+        (!self.patch_folder.is_empty()).then(|| Self::expand_tilde(&self.patch_folder))
+    }
+
+    /// Returns the expanded path to the OPL folder, if one is configured.
+    pub fn opl_folder_path(&self) -> Option<std::path::PathBuf> {
+        (!self.opl_folder.is_empty()).then(|| Self::expand_tilde(&self.opl_folder))
+    }
+
+    /// Returns the OPL exporter, if an OPL folder is configured.
+    pub fn opl_export(&self) -> Option<OplExport> {
+        let mode = if self.opl_hardlink { PlaceMode::Hardlink } else { PlaceMode::Copy };
+        Some(OplExport::new(self.opl_folder_path()?, mode))
+    }
+
+    /// Returns the expanded path to the JSON file holding the library index.
+    pub fn library_index_path(&self) -> std::path::PathBuf {
+        Self::expand_tilde(&self.tmp_folder_name).join("library.json")
+    }
+
+    /// Returns the expanded path to the JSON file holding the wishlist.
+    pub fn wishlist_path(&self) -> std::path::PathBuf {
+        Self::expand_tilde(&self.tmp_folder_name).join("wishlist.json")
+    }
+
+    /// Returns the expanded path to the JSON file holding the saved searches and their snapshots.
+    pub fn saved_searches_path(&self) -> std::path::PathBuf {
+        Self::expand_tilde(&self.tmp_folder_name).join("searches.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty scratch folder for a test.
+    fn scratch(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("ps2rd-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// The typed configuration of a file, comparable across INI and TOML.
+    fn config_of(path: &Path) -> serde_json::Value {
+        let mut settings = Settings::defaults();
+        settings.apply_file(path).unwrap();
+        serde_json::to_value(settings.config().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn migrates_ini_to_toml_keeping_values_and_comments() {
+        let folder = scratch("ini");
+        let ini = folder.join("config.ini");
+        fs::write(&ini, include_str!("../config.ini")).unwrap();
+        let expected = config_of(&ini);
+        assert!(needs_migration(&ini));
+
+        let toml = migrate_file(&ini).unwrap();
+        assert_eq!(toml, folder.join("config.toml"));
+        assert!(!ini.exists());
+        assert_eq!(fs::read_to_string(folder.join("config.ini.bak")).unwrap(), include_str!("../config.ini"));
+        let content = fs::read_to_string(&toml).unwrap();
+        assert!(content.contains(&format!("VERSION = {}", SCHEMA_VERSION)));
+        assert!(content.contains("# Extra folders with games you already own"));
+        assert_eq!(config_of(&toml), expected);
+        assert!(!needs_migration(&toml));
+    }
+
+    #[test]
+    fn stamps_the_version_of_older_toml_files() {
+        let folder = scratch("v1");
+        let path = folder.join("config.toml");
+        let original = "# My settings\n\n[Download]\n# Be patient\nMAX_RETRIES = 3\n";
+        fs::write(&path, original).unwrap();
+        assert!(needs_migration(&path));
+
+        assert_eq!(migrate_file(&path).unwrap(), path);
+        assert_eq!(fs::read_to_string(folder.join("config.toml.bak")).unwrap(), original);
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# My settings\n"));
+        assert!(content.contains("# Be patient\nMAX_RETRIES = 3\n"));
+        let table: toml::Table = content.parse().unwrap();
+        assert_eq!(table["VERSION"].as_integer(), Some(i64::from(SCHEMA_VERSION)));
+        assert_eq!(config_of(&path)["max_retries"], 3);
+        assert!(!needs_migration(&path));
+    }
+
+    #[test]
+    fn leaves_current_files_unchanged() {
+        let folder = scratch("current");
+        let path = folder.join("config.toml");
+        let original = Settings::defaults().to_file();
+        fs::write(&path, &original).unwrap();
+        assert!(!needs_migration(&path));
+
+        assert_eq!(migrate_file(&path).unwrap(), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+        assert!(!folder.join("config.toml.bak").exists());
+    }
+
+    #[test]
+    fn keeps_ini_files_when_the_toml_file_exists() {
+        let folder = scratch("both");
+        let ini = folder.join("config.ini");
+        let toml = folder.join("config.toml");
+        fs::write(&ini, "[Download]\nMAX_RETRIES = 3\n").unwrap();
+        fs::write(&toml, "VERSION = 2\n").unwrap();
+        assert!(!needs_migration(&ini));
+
+        assert_eq!(migrate_file(&ini).unwrap(), ini);
+        assert_eq!(fs::read_to_string(&ini).unwrap(), "[Download]\nMAX_RETRIES = 3\n");
+        assert_eq!(fs::read_to_string(&toml).unwrap(), "VERSION = 2\n");
+        assert!(!folder.join("config.ini.bak").exists());
+    }
 }
@@ -0,0 +1,230 @@
+use crate::error::{Error, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Size of a raw CD sector.
+pub const SECTOR_SIZE: u64 = 2352;
+/// CD frames per second, used by cue sheet timestamps.
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// Track types supported in raw BIN images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    /// `MODE1/2352`
+    Mode1Raw,
+    /// `MODE2/2352`
+    Mode2Raw,
+    /// `AUDIO`
+    Audio,
+}
+
+impl TrackKind {
+    /// Name of the track type in CHD track metadata.
+    pub fn chd_name(self) -> &'static str {
+        match self {
+            TrackKind::Mode1Raw => "MODE1_RAW",
+            TrackKind::Mode2Raw => "MODE2_RAW",
+            TrackKind::Audio => "AUDIO",
+        }
+    }
+}
+
+/// A track of a cue sheet, located in its BIN file.
+#[derive(Debug, Clone)]
+pub struct CueTrack {
+    /// Track number (1-based)
+    pub number: u32,
+    /// Track type
+    pub kind: TrackKind,
+    /// BIN file holding the track
+    pub file: PathBuf,
+    /// First frame of the track in the file, including its pregap
+    pub start: u64,
+    /// Frames of the track, including its pregap
+    pub frames: u64,
+    /// Pregap frames stored in the file (between INDEX 00 and INDEX 01)
+    pub pregap: u64,
+}
+
+/// A TRACK command with the INDEX positions that followed it.
+struct ParsedTrack {
+    number: u32,
+    kind: TrackKind,
+    file: PathBuf,
+    index0: Option<u64>,
+    index1: Option<u64>,
+}
+
+/// Parses a cue sheet with raw (2352-byte) tracks, as used by Redump BIN/CUE sets.
+pub fn parse(cue: &Path) -> Result<Vec<CueTrack>> {
+    let content = fs::read_to_string(cue)?;
+    let folder = cue.parent().unwrap_or(Path::new("."));
+    let invalid = |line: usize, message: &str| Error::ImageInvalid(format!("{} line {}: {}", cue.display(), line, message));
+
+    let mut parsed: Vec<ParsedTrack> = Vec::new();
+    let mut file: Option<PathBuf> = None;
+
+    for (number, line) in content.lines().enumerate() {
+        let line_number = number + 1;
+        let line = line.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                let name = match rest.strip_prefix('"') {
+                    Some(quoted) => quoted.split('"').next().unwrap_or_default(),
+                    None => rest.split_whitespace().next().unwrap_or_default(),
+                };
+                if !rest.trim_end().to_ascii_uppercase().ends_with("BINARY") {
+                    return Err(invalid(line_number, "only BINARY files are supported"));
+                }
+                // The files are deleted after a conversion, so they must all be next to the cue sheet
+                if !is_in_folder(name) {
+                    return Err(invalid(line_number, "FILE must name a file in the cue sheet's folder"));
+                }
+                file = Some(folder.join(name));
+            }
+            "TRACK" => {
+                let mut parts = rest.split_whitespace();
+                let number = parts
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| invalid(line_number, "invalid track number"))?;
+                let kind = match parts.next().map(str::to_ascii_uppercase).as_deref() {
+                    Some("MODE1/2352") => TrackKind::Mode1Raw,
+                    Some("MODE2/2352") => TrackKind::Mode2Raw,
+                    Some("AUDIO") => TrackKind::Audio,
+                    _ => return Err(invalid(line_number, "unsupported track type")),
+                };
+                let file = file.clone().ok_or_else(|| invalid(line_number, "TRACK before FILE"))?;
+                parsed.push(ParsedTrack {
+                    number,
+                    kind,
+                    file,
+                    index0: None,
+                    index1: None,
+                });
+            }
+            "INDEX" => {
+                let mut parts = rest.split_whitespace();
+                let index: u32 = parts
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| invalid(line_number, "invalid index number"))?;
+                let frame = parts
+                    .next()
+                    .and_then(parse_time)
+                    .ok_or_else(|| invalid(line_number, "invalid index time"))?;
+                let track = parsed.last_mut().ok_or_else(|| invalid(line_number, "INDEX before TRACK"))?;
+                match index {
+                    0 => track.index0 = Some(frame),
+                    1 => track.index1 = Some(frame),
+                    _ => {}
+                }
+            }
+            "PREGAP" | "POSTGAP" => {
+                return Err(invalid(line_number, "generated pregaps and postgaps are not supported"));
+            }
+            _ => {}
+        }
+    }
+
+    let mut tracks = Vec::with_capacity(parsed.len());
+    for (i, track) in parsed.iter().enumerate() {
+        let index1 = track
+            .index1
+            .ok_or_else(|| Error::ImageInvalid(format!("track {} has no INDEX 01", track.number)))?;
+        let start = track.index0.unwrap_or(index1);
+        // The track ends where the next track in the same file starts, or at the end of the file
+        let end = match parsed.get(i + 1) {
+            Some(next) if next.file == track.file => next.index0.or(next.index1).unwrap_or(start),
+            _ => fs::metadata(&track.file)?.len() / SECTOR_SIZE,
+        };
+        if end <= start {
+            return Err(Error::ImageInvalid(format!("track {} is empty", track.number)));
+        }
+        tracks.push(CueTrack {
+            number: track.number,
+            kind: track.kind,
+            file: track.file.clone(),
+            start,
+            frames: end - start,
+            pregap: index1
+                .checked_sub(start)
+                .ok_or_else(|| Error::ImageInvalid(format!("track {} has INDEX 00 after INDEX 01", track.number)))?,
+        });
+    }
+    if tracks.is_empty() {
+        return Err(Error::ImageInvalid(format!("{} has no tracks", cue.display())));
+    }
+    Ok(tracks)
+}
+
+/// Files referenced by the tracks, without duplicates.
+pub fn files(tracks: &[CueTrack]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for track in tracks {
+        if !files.contains(&track.file) {
+            files.push(track.file.clone());
+        }
+    }
+    files
+}
+
+/// Returns true if `name` is a relative path that stays inside the folder it is joined to.
+fn is_in_folder(name: &str) -> bool {
+    let mut components = Path::new(name).components().peekable();
+    components.peek().is_some() && components.all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Parses an 'mm:ss:ff' timestamp into frames.
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `cue` and a BIN of `frames` frames into a scratch folder and parses the sheet.
+    fn parse_sheet(name: &str, cue: &str, frames: u64) -> Result<Vec<CueTrack>> {
+        let folder = std::env::temp_dir().join(format!("ps2rd-cue-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("game.bin"), vec![0u8; (frames * SECTOR_SIZE) as usize]).unwrap();
+        fs::write(folder.join("game.cue"), cue).unwrap();
+        let tracks = parse(&folder.join("game.cue"));
+        fs::remove_dir_all(folder).unwrap();
+        tracks
+    }
+
+    #[test]
+    fn parses_tracks_and_pregaps() {
+        let cue = "FILE \"game.bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n  \
+                   TRACK 02 AUDIO\n    INDEX 00 00:01:00\n    INDEX 01 00:03:00\n";
+        let tracks = parse_sheet("tracks", cue, 300).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!((tracks[0].kind, tracks[0].start, tracks[0].frames, tracks[0].pregap), (TrackKind::Mode2Raw, 0, 75, 0));
+        assert_eq!((tracks[1].kind, tracks[1].start, tracks[1].frames, tracks[1].pregap), (TrackKind::Audio, 75, 225, 150));
+        assert_eq!(files(&tracks).len(), 1);
+        assert!(tracks[0].file.ends_with("game.bin"));
+    }
+
+    #[test]
+    fn rejects_files_outside_the_folder() {
+        for name in ["../game.bin", "sub/../../game.bin", "/tmp/game.bin", ""] {
+            let cue = format!("FILE \"{}\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n", name);
+            let error = parse_sheet("outside", &cue, 10).unwrap_err();
+            assert!(matches!(&error, Error::ImageInvalid(message) if message.contains("line 1")), "{}: {}", name, error);
+        }
+        assert!(is_in_folder("game.bin") && is_in_folder("./tracks/game.bin"));
+    }
+
+    #[test]
+    fn rejects_index_00_after_index_01() {
+        let cue = "FILE \"game.bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:10\n    INDEX 00 00:00:20\n";
+        let error = parse_sheet("index", cue, 100).unwrap_err();
+        assert!(matches!(&error, Error::ImageInvalid(message) if message.contains("INDEX 00 after INDEX 01")), "{}", error);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::error::Result;
+use crate::models::Game;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A title released on several discs, e.g. 'Xenosaga Episode II (USA) (Disc 1)' and '(Disc 2)'.
+#[derive(Debug, Clone)]
+pub struct DiscSet {
+    /// Clean title without the disc tag, e.g. 'Xenosaga Episode II (USA)'
+    pub title: String,
+    /// Catalog entries of the discs, in disc order
+    pub discs: Vec<Game>,
+}
+
+/// The multi-disc sets of a catalog.
+#[derive(Debug, Clone, Default)]
+pub struct DiscSets {
+    /// Sets keyed by lowercased set title
+    sets: HashMap<String, DiscSet>,
+}
+
+impl DiscSets {
+    /// Groups the catalog entries tagged '(Disc N)' by set title; a lone disc is not a set.
+    pub fn new(catalog: &[Game]) -> Self {
+        let mut sets: HashMap<String, DiscSet> = HashMap::new();
+        for game in catalog.iter().filter(|game| game.disc().is_some()) {
+            let title = game.set_title();
+            sets.entry(title.to_lowercase())
+                .or_insert_with(|| DiscSet { title, discs: Vec::new() })
+                .discs
+                .push(game.clone());
+        }
+        sets.retain(|_, set| set.discs.len() > 1);
+        for set in sets.values_mut() {
+            set.discs.sort_by_key(|disc| disc.disc());
+        }
+        Self { sets }
+    }
+
+    /// Returns the set `game` is a disc of, if it belongs to one.
+    pub fn set_of(&self, game: &Game) -> Option<&DiscSet> {
+        game.disc()?;
+        self.sets.get(&game.set_title().to_lowercase())
+    }
+
+    /// Adds the missing discs of every multi-disc title in `games`, so each set is downloaded as a unit:
+    /// its discs replace the first one listed, in disc order. Duplicates are dropped.
+    pub fn complete(&self, games: impl IntoIterator<Item = Game>) -> Vec<Game> {
+        let mut completed: Vec<Game> = Vec::new();
+        for game in games {
+            let discs = match self.set_of(&game) {
+                Some(set) => set.discs.clone(),
+                None => vec![game],
+            };
+            for disc in discs {
+                if !completed.iter().any(|known| known.title == disc.title) {
+                    completed.push(disc);
+                }
+            }
+        }
+        completed
+    }
+}
+
+/// Writes '<set title>.m3u' next to the first image, listing `images` in disc order.
+/// Images in the same folder are listed by file name, others by absolute path.
+pub fn write_playlist(set: &DiscSet, images: &[PathBuf]) -> Result<PathBuf> {
+    let folder = images.first().and_then(|image| image.parent()).unwrap_or(Path::new("."));
+    let mut playlist = String::new();
+    for image in images {
+        match image.file_name().filter(|_| image.parent() == Some(folder)) {
+            Some(name) => playlist.push_str(&name.to_string_lossy()),
+            None => playlist.push_str(&image.display().to_string()),
+        }
+        playlist.push('\n');
+    }
+    let path = folder.join(format!("{}.m3u", set.title));
+    fs::write(&path, playlist)?;
+    Ok(path)
+}
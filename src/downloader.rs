@@ -1,17 +1,70 @@
+use crate::chd;
+use crate::compress::{self, Format};
+use crate::discs::{self, DiscSets};
+use crate::error::{Error, Result};
+use crate::hooks::{HookContext, HookEvent, Hooks};
+use crate::iso9660::{read_disc_info_from_path, DiscInfo};
+use crate::library::{ImageFormat, SharedLibrary};
+use crate::opl::{Exported, Media, OplExport, MAX_TITLE_LENGTH};
+use crate::patch;
+use crate::usbextreme::{self, PartWriter};
+use crate::output::{DownloadStatus, Event, Output, Stage};
+use crate::utils::{crc32_file, format_size};
 use crate::{config::Config, models::Game};
-use anyhow::Result;
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
 use std::fs;
-use std::path::Path;
-use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::io::{ErrorKind, Read, Write};
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncSeekExt, SeekFrom, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use zip::ZipArchive;
 
+/// Result of a successful download.
+#[derive(Debug, Clone)]
+pub struct DownloadResult {
+    /// Clean title of the game
+    pub title: String,
+    /// Whether the game was downloaded or already present
+    pub status: DownloadStatus,
+    /// Path of the extracted ISO, if it exists, or of its first USBExtreme part when it was split
+    pub path: Option<PathBuf>,
+    /// CRC32 of the extracted ISO, when it was extracted in this run
+    pub crc32: Option<u32>,
+    /// Serial and volume label read from the ISO, if it could be read
+    pub disc: Option<DiscInfo>,
+    /// Where the ISO was placed in the OPL folder, when exporting is enabled
+    pub opl: Option<Exported>,
+    /// Patched copies of the ISO written next to it
+    pub patched: Vec<PathBuf>,
+}
+
+/// An ISO extracted into the OPL folder as USBExtreme parts.
+#[derive(Debug)]
+struct SplitGame {
+    /// Path of the first part, which stands for the image
+    path: PathBuf,
+    /// Serial and volume label read while installing the parts
+    disc: DiscInfo,
+    /// Size of all parts together
+    size: u64,
+}
+
 /// Downloader handles downloading and extracting PS2 ISO files.
 pub struct Downloader {
     config: Config,
+    output: Output,
+    paused: watch::Sender<bool>,
+    /// When the speed limit lets the next chunk through, shared by concurrent downloads
+    next_chunk: Mutex<Instant>,
+    library: Option<SharedLibrary>,
+    opl: Option<OplExport>,
+    hooks: Hooks,
+    disc_sets: DiscSets,
 }
 
 impl Downloader {
@@ -19,71 +72,765 @@ impl Downloader {
     pub fn new(config: &Config) -> Self {
         Self {
             config: config.clone(),
+            output: Output::default(),
+            paused: watch::Sender::new(false),
+            next_chunk: Mutex::new(Instant::now()),
+            library: None,
+            opl: None,
+            hooks: config.hooks(),
+            disc_sets: DiscSets::default(),
         }
     }
 
+    /// Sets the output mode used for messages and progress reporting.
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Uses a library index: owned games are skipped and extracted ISOs are recorded in it.
+    pub fn with_library(mut self, library: SharedLibrary) -> Self {
+        self.library = Some(library);
+        self
+    }
+
+    /// Places extracted ISOs into an OPL folder after each download.
+    pub fn with_opl(mut self, opl: OplExport) -> Self {
+        self.opl = Some(opl);
+        self
+    }
+
+    /// Knows the multi-disc sets of the catalog, for keeping their discs together and writing playlists.
+    pub fn with_catalog(mut self, catalog: &[Game]) -> Self {
+        self.disc_sets = DiscSets::new(catalog);
+        self
+    }
+
+    /// The multi-disc sets of the catalog given to `with_catalog`.
+    pub fn disc_sets(&self) -> &DiscSets {
+        &self.disc_sets
+    }
+
+    /// The configuration downloads are made with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Pauses active downloads. The connection is dropped but the partial file is kept,
+    /// and the download continues from the same byte with a Range request on `resume`.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Resumes paused downloads.
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Returns true while downloads are paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
     /// Download and extract the selected PS2 game.
-    pub async fn download_ps2_element(&self, game: &Game) -> Result<()> {
+    /// Cancelling `cancel` stops the download and keeps the partial ZIP so it can be resumed later.
+    pub async fn download_ps2_element(&self, game: &Game, cancel: &CancellationToken) -> Result<DownloadResult> {
         let title = game.clean_title();
-        println!("\nSelected {}\n", title);
+        self.output.message(&format!("\nSelected {}\n", title));
+
+        // With split output the ISO goes straight into the OPL folder as USBExtreme parts
+        let split = self.config.opl_split.then(|| usbextreme::ul_name(&game.base_title()));
+        let (status, crc32, split) = match self.download_and_unzip(&game.link, &title, split.as_ref(), cancel).await {
+            Ok(result) => result,
+            Err(e) => {
+                if let Error::ChecksumMismatch { .. } = e {
+                    self.verify_failed(&title, None, &e).await;
+                }
+                self.output.emit(&Event::DownloadResult {
+                    title,
+                    status: match e {
+                        Error::Cancelled => DownloadStatus::Cancelled,
+                        _ => DownloadStatus::Failed,
+                    },
+                    path: None,
+                    crc32: None,
+                    serial: None,
+                    volume_label: None,
+                    error: Some(e.to_string()),
+                });
+                return Err(e);
+            }
+        };
+        self.output.message(&format!("\n{} downloaded :)", title));
+
+        // A split game is only in the OPL folder, where its first part stands for the image
+        let (path, disc) = match &split {
+            Some(split) => (Some(split.path.clone()), Some(split.disc.clone())),
+            None => {
+                let path = self.downloaded_image(&title);
+                let disc = match &path {
+                    Some(path) => self.read_disc_info(path).await,
+                    None => None,
+                };
+                (path, disc)
+            }
+        };
+        // The export is named '.iso', so only plain ISOs go; CSO/ZSO, BIN/CUE and CHD images stay put
+        let exportable = path.as_ref().is_some_and(|path| ImageFormat::from_path(path) == Some(ImageFormat::Iso));
+        let opl = match (&path, &disc) {
+            (Some(path), Some(disc)) if exportable => self.export_to_opl(path, disc, game).await,
+            _ => None,
+        };
+        // Patched copies are made from the plain ISO too, before it may be compressed away
+        let patched = match (&path, status) {
+            (Some(iso), DownloadStatus::Downloaded) if ImageFormat::from_path(iso) == Some(ImageFormat::Iso) => {
+                self.apply_patches(iso, &title, crc32, cancel).await
+            }
+            _ => Vec::new(),
+        };
+        // Compress after the OPL export, which needs the plain ISO; OPL reads split parts as they are
+        let path = match (path, self.config.compression(), status) {
+            (path, _, _) if split.is_some() => path,
+            (Some(source), _, DownloadStatus::Downloaded)
+                if self.config.chd_output() && ImageFormat::from_path(&source) != Some(ImageFormat::Chd) =>
+            {
+                match self.convert_to_chd(&source, &title, crc32, cancel).await {
+                    Ok(converted) => Some(converted),
+                    Err(e) => {
+                        if let Error::ChecksumMismatch { .. } = e {
+                            self.verify_failed(&title, Some(&source), &e).await;
+                        }
+                        self.output.message(&format!("CHD conversion failed, keeping the image: {}", e));
+                        Some(source)
+                    }
+                }
+            }
+            (Some(iso), Some(format), DownloadStatus::Downloaded) => {
+                match self.compress_iso(&iso, format, &title, crc32, cancel).await {
+                    Ok(compressed) => Some(compressed),
+                    Err(e) => {
+                        if let Error::ChecksumMismatch { .. } = e {
+                            self.verify_failed(&title, Some(&iso), &e).await;
+                        }
+                        self.output.message(&format!("Compression failed, keeping the ISO: {}", e));
+                        Some(iso)
+                    }
+                }
+            }
+            (path, _, _) => path,
+        };
+        if let (Some(library), Some(path), DownloadStatus::Downloaded) = (&self.library, &path, status) {
+            let mut library = library.lock().unwrap();
+            library.record_download(&title, path, crc32, disc.clone());
+            if let Err(e) = library.save(&self.config.library_index_path()) {
+                self.output.message(&format!("Error saving library index: {}", e));
+            }
+        }
+        if path.is_some() && self.config.m3u_playlists {
+            self.write_playlist(game);
+        }
+        if let (Some(path), DownloadStatus::Downloaded) = (&path, status) {
+            let context = HookContext {
+                title: Some(title.clone()),
+                path: Some(path.clone()),
+                size: match &split {
+                    Some(split) => Some(split.size),
+                    None => fs::metadata(path).ok().map(|metadata| metadata.len()),
+                },
+                serial: disc.as_ref().and_then(|disc| disc.serial.clone()),
+                crc32: crc32.map(|crc| format!("{:08x}", crc)),
+                ..HookContext::default()
+            };
+            self.run_hook(HookEvent::DownloadComplete, context).await;
+        }
+        self.output.emit(&Event::DownloadResult {
+            title: title.clone(),
+            status,
+            path: path.as_ref().map(|p| p.display().to_string()),
+            crc32: crc32.map(|crc| format!("{:08x}", crc)),
+            serial: disc.as_ref().and_then(|disc| disc.serial.clone()),
+            volume_label: disc.as_ref().map(|disc| disc.volume_label.clone()),
+            error: None,
+        });
+        if let Some(path) = &path {
+            if self.output.is_text() {
+                // A part means nothing on its own, so the OPL folder is shown instead
+                let shown = match &split {
+                    Some(_) => path.parent().unwrap_or(path),
+                    None => path,
+                };
+                self.open_explorer(shown);
+            }
+        }
+
+        Ok(DownloadResult {
+            title,
+            status,
+            path,
+            crc32,
+            disc,
+            opl,
+            patched,
+        })
+    }
+
+    /// Compresses an extracted ISO, checks the compressed image decompresses to the same CRC32,
+    /// and removes the ISO unless it should be kept. Returns the path of the compressed image.
+    async fn compress_iso(
+        &self,
+        iso: &Path,
+        format: Format,
+        title: &str,
+        expected_crc32: Option<u32>,
+        cancel: &CancellationToken,
+    ) -> Result<PathBuf> {
+        let output = compress::output_path(iso, format);
+        self.output.message(&format!("Compressing to {}...", format.extension().to_uppercase()));
+
+        let total = fs::metadata(iso)?.len();
+        let template = "{spinner:.green} Compressing: [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+        let mut progress = self.output.progress(title, Stage::Compress, Some(total), template);
+        let (source, target, threads, token) =
+            (iso.to_path_buf(), output.clone(), self.config.compress_threads, cancel.clone());
+        let (compressed, mut progress) = tokio::task::spawn_blocking(move || {
+            let result = compress::compress(&source, &target, format, threads, &token, |bytes| {
+                progress.set_position(bytes)
+            });
+            (result, progress)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        let compressed = compressed?;
+        progress.finish("Compression completed");
+
+        // Round trip: the image must decompress to the ISO that was read
+        let template = "{spinner:.green} Verifying:   [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+        let mut progress = self.output.progress(title, Stage::Verify, Some(total), template);
+        let (target, token) = (output.clone(), cancel.clone());
+        let (actual, mut progress) = tokio::task::spawn_blocking(move || {
+            let result = compress::image_crc32(&target, &token, |bytes| progress.set_position(bytes));
+            (result, progress)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        progress.finish("Verification completed");
+
+        let expected = expected_crc32.unwrap_or(compressed.crc32);
+        let mismatch = match actual {
+            Ok(actual) if actual == expected && compressed.crc32 == expected => None,
+            Ok(actual) => Some(Error::ChecksumMismatch {
+                file: output.display().to_string(),
+                expected: format!("{:08x}", expected),
+                actual: format!("{:08x}", actual),
+            }),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = mismatch {
+            self.remove_file(&output)?;
+            return Err(e);
+        }
+
+        self.output.message(&format!(
+            "Compressed {} to {} ({:.0}%)",
+            format_size(compressed.iso_size),
+            format_size(compressed.size),
+            compressed.size as f64 * 100.0 / compressed.iso_size.max(1) as f64
+        ));
+        if !self.config.compress_keep_iso {
+            self.remove_file(iso)?;
+        }
+        Ok(output)
+    }
+
+    /// Converts an ISO or BIN/CUE set to CHD, reads the CHD back to check it, and removes
+    /// the source files unless they should be kept. Returns the path of the CHD.
+    async fn convert_to_chd(
+        &self,
+        source: &Path,
+        title: &str,
+        expected_crc32: Option<u32>,
+        cancel: &CancellationToken,
+    ) -> Result<PathBuf> {
+        let output = chd::output_path(source);
+        self.output.message("Converting to CHD...");
+
+        let sources = chd::source_files(source)?;
+        let total = chd::source_size(source)?;
+        let template = "{spinner:.green} Converting: [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+        let mut progress = self.output.progress(title, Stage::Compress, Some(total), template);
+        let (file, target, threads, token) =
+            (source.to_path_buf(), output.clone(), self.config.compress_threads, cancel.clone());
+        let (converted, mut progress) = tokio::task::spawn_blocking(move || {
+            let result = chd::convert(&file, &target, threads, &token, |bytes| progress.set_position(bytes));
+            (result, progress)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        let converted = converted?;
+        progress.finish("Conversion completed");
+
+        // Round trip: hunk CRCs and SHA-1s must match, and the data must be what was read
+        let template = "{spinner:.green} Verifying:  [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+        let mut progress = self.output.progress(title, Stage::Verify, Some(converted.logical_size), template);
+        let (target, token) = (output.clone(), cancel.clone());
+        let (actual, mut progress) = tokio::task::spawn_blocking(move || {
+            let result = chd::verify(&target, &token, |bytes| progress.set_position(bytes));
+            (result, progress)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        progress.finish("Verification completed");
+
+        // The extraction CRC32 covers the ISO, which is the CHD data only for DVD images
+        let expected = match ImageFormat::from_path(source) {
+            Some(ImageFormat::Cue) => converted.crc32,
+            _ => expected_crc32.unwrap_or(converted.crc32),
+        };
+        let mismatch = match actual {
+            Ok(actual) if actual == expected && converted.crc32 == expected => None,
+            Ok(actual) => Some(Error::ChecksumMismatch {
+                file: output.display().to_string(),
+                expected: format!("{:08x}", expected),
+                actual: format!("{:08x}", actual),
+            }),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = mismatch {
+            self.remove_file(&output)?;
+            return Err(e);
+        }
 
-        // Construct the full URL by combining base URL with relative path
-        let full_url = format!("{}{}", self.config.iso_url, game.link);
-        self.download_and_unzip(&full_url, &title).await?;
-        println!("\n{} downloaded :)", title);
+        self.output.message(&format!(
+            "Converted {} to {} CHD (SHA-1 {})",
+            format_size(total),
+            format_size(converted.size),
+            converted.sha1
+        ));
+        if !self.config.compress_keep_iso {
+            for file in &sources {
+                self.remove_file(file)?;
+            }
+        }
+        Ok(output)
+    }
 
-        // Open the folder containing the downloaded ISO
-        let iso_file = self
+    /// Applies the patches mapped to `title` in the patch folder, each into its own copy of the ISO.
+    /// Failures are reported but not fatal. Returns the patched images.
+    async fn apply_patches(
+        &self,
+        iso: &Path,
+        title: &str,
+        crc32: Option<u32>,
+        cancel: &CancellationToken,
+    ) -> Vec<PathBuf> {
+        let Some(folder) = self.config.patch_folder_path() else {
+            return Vec::new();
+        };
+        let entries = match patch::patches_for(&folder, title) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.output.message(&format!("Could not read patches: {}", e));
+                return Vec::new();
+            }
+        };
+
+        let mut patched = Vec::new();
+        for entry in entries {
+            let patch_name = entry.patch.display().to_string();
+            let output = entry.output_path(iso);
+            match self.apply_patch(iso, &entry, &output, title, crc32, cancel).await {
+                Ok(applied) => {
+                    self.output.message(&format!(
+                        "Applied {} patch {} -> {}",
+                        applied.format.name(),
+                        patch_name,
+                        output.display()
+                    ));
+                    self.output.emit(&Event::Patched {
+                        title: title.to_string(),
+                        patch: patch_name,
+                        path: output.display().to_string(),
+                        size: applied.size,
+                    });
+                    patched.push(output);
+                }
+                Err(Error::Cancelled) => break,
+                Err(e) => {
+                    self.output.message(&format!("Patch {} not applied: {}", patch_name, e));
+                    if let Error::ChecksumMismatch { .. } = e {
+                        self.verify_failed(title, Some(iso), &e).await;
+                    }
+                }
+            }
+        }
+        patched
+    }
+
+    /// Applies one patch, checking the source CRC32 unless extraction already proved it.
+    async fn apply_patch(
+        &self,
+        iso: &Path,
+        entry: &patch::PatchEntry,
+        output: &Path,
+        title: &str,
+        crc32: Option<u32>,
+        cancel: &CancellationToken,
+    ) -> Result<patch::Patched> {
+        let expected = entry.expected_crc32()?;
+        if let (Some(expected), Some(actual)) = (expected, crc32) {
+            if expected != actual {
+                return Err(Error::ChecksumMismatch {
+                    file: iso.display().to_string(),
+                    expected: format!("{:08x}", expected),
+                    actual: format!("{:08x}", actual),
+                });
+            }
+        }
+        let expected = expected.filter(|_| crc32.is_none());
+
+        let total = fs::metadata(iso)?.len();
+        let template = "{spinner:.green} Patching:   [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+        let mut progress = self.output.progress(title, Stage::Patch, Some(total), template);
+        let (source, patch_file, target, token) =
+            (iso.to_path_buf(), entry.patch.clone(), output.to_path_buf(), cancel.clone());
+        let (result, mut progress) = tokio::task::spawn_blocking(move || {
+            let result = patch::apply(&source, &patch_file, &target, expected, &token, |bytes| {
+                progress.set_position(bytes)
+            });
+            (result, progress)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        progress.finish("Patch applied");
+        result
+    }
+
+    /// Returns the image a download of `title` left in the ISO folder: the ISO, the CUE sheet of a
+    /// BIN/CUE set, or the CHD or compressed image made from them.
+    fn downloaded_image(&self, title: &str) -> Option<PathBuf> {
+        let iso_file = self.config.tmp_iso_folder_path().join(format!("{}.iso", title));
+        let compressed = self
             .config
-            .tmp_iso_folder_path()
-            .join(format!("{}.iso", title));
-        if iso_file.exists() {
-            self.open_explorer(&iso_file);
+            .compression()
+            .map(|format| compress::output_path(&iso_file, format));
+        // CD titles come as a BIN/CUE set named after the title
+        let cue_file = iso_file.with_extension("cue");
+        let chd_file = chd::output_path(&iso_file);
+        [iso_file, cue_file, chd_file]
+            .into_iter()
+            .chain(compressed)
+            .find(|path| path.exists())
+    }
+
+    /// Writes the .m3u playlist of the multi-disc set `game` belongs to, once every disc is on disk.
+    /// Discs are looked up in the ISO folder, then in the library; until all are there, the missing ones are reported.
+    fn write_playlist(&self, game: &Game) {
+        let Some(set) = self.disc_sets.set_of(game) else {
+            return;
+        };
+        let mut images = Vec::new();
+        let mut missing = Vec::new();
+        for disc in &set.discs {
+            let in_library = || {
+                let library = self.library.as_ref()?.lock().unwrap();
+                // The CUE sheet stands for a BIN/CUE image
+                library
+                    .entries_for(disc)
+                    .into_iter()
+                    .find(|entry| entry.format != ImageFormat::Bin)
+                    .map(|entry| entry.path.clone())
+            };
+            match self.downloaded_image(&disc.clean_title()).or_else(in_library) {
+                Some(image) => images.push(image),
+                None => missing.push(disc.clean_title()),
+            }
+        }
+        if !missing.is_empty() {
+            self.output.message(&format!(
+                "Playlist for {} not written yet, missing: {}",
+                set.title,
+                missing.join(", ")
+            ));
+            return;
+        }
+        match discs::write_playlist(set, &images) {
+            Ok(path) => self.output.message(&format!("Wrote playlist {}", path.display())),
+            Err(e) => self.output.message(&format!("Error writing playlist for {}: {}", set.title, e)),
+        }
+    }
+
+    /// Places an extracted ISO into the OPL folder, if configured. Failures are reported but not fatal.
+    async fn export_to_opl(&self, path: &Path, disc: &DiscInfo, game: &Game) -> Option<Exported> {
+        let opl = self.opl.clone()?;
+        let (file, disc_info, base_title) = (path.to_path_buf(), disc.clone(), game.base_title());
+        let result = tokio::task::spawn_blocking(move || opl.export(&file, &disc_info, &base_title))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
+            .and_then(|result| result);
+        match result {
+            Ok(exported) => {
+                if exported.truncated {
+                    self.output
+                        .message(&format!("Title shortened to {} characters for OPL", MAX_TITLE_LENGTH));
+                }
+                self.output.message(&format!("Exported to OPL: {}", exported.path.display()));
+                self.output.emit(&Event::OplExport {
+                    title: game.clean_title(),
+                    path: exported.path.display().to_string(),
+                    media: exported.media,
+                    truncated: exported.truncated,
+                });
+                Some(exported)
+            }
+            Err(e @ Error::ExportFailed(_)) => {
+                self.output.message(&e.to_string());
+                None
+            }
+            Err(e) => {
+                self.output.message(&Error::ExportFailed(e.to_string()).to_string());
+                None
+            }
         }
+    }
 
+    /// Reads the serial and volume label of an extracted ISO. Failures are reported but not fatal.
+    async fn read_disc_info(&self, path: &Path) -> Option<DiscInfo> {
+        let file = path.to_path_buf();
+        let result = tokio::task::spawn_blocking(move || read_disc_info_from_path(&file))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
+            .and_then(|result| result);
+        match result {
+            Ok(disc) => {
+                if let Some(serial) = &disc.serial {
+                    self.output.message(&format!("Serial: {} ({})", serial, disc.volume_label));
+                }
+                Some(disc)
+            }
+            Err(e) => {
+                self.output.message(&format!("Could not read disc serial: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Verifies an extracted ISO against the CRC32 recorded when it was extracted.
+    /// CSO, ZSO and CHD images are decompressed and the data they contain is checked.
+    pub async fn verify(&self, path: &Path, expected_crc32: u32) -> Result<()> {
+        let file = path.to_path_buf();
+        let actual = tokio::task::spawn_blocking(move || match ImageFormat::from_path(&file) {
+            Some(ImageFormat::Cso | ImageFormat::Zso) => {
+                compress::image_crc32(&file, &CancellationToken::new(), |_| {})
+            }
+            Some(ImageFormat::Chd) => chd::verify(&file, &CancellationToken::new(), |_| {}),
+            _ => crc32_file(&file),
+        })
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+        if actual != expected_crc32 {
+            let e = Error::ChecksumMismatch {
+                file: path.display().to_string(),
+                expected: format!("{:08x}", expected_crc32),
+                actual: format!("{:08x}", actual),
+            };
+            // Images are named after their title
+            let title = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+            self.verify_failed(&title, Some(path), &e).await;
+            return Err(e);
+        }
         Ok(())
     }
 
+    /// Runs the hook for `event`, if one is configured, and reports how it went.
+    /// A failing hook never fails the download it belongs to.
+    pub async fn run_hook(&self, event: HookEvent, context: HookContext) {
+        let Some(result) = self.hooks.run(event, &context).await else {
+            return;
+        };
+        let run = match result {
+            Ok(run) => run,
+            Err(e) => {
+                self.output.message(&format!("Hook {} could not be started: {}", event.name(), e));
+                self.output.emit(&Event::Hook {
+                    hook: event,
+                    command: self.hooks.command(event).unwrap_or_default().to_string(),
+                    exit_code: None,
+                    timed_out: false,
+                    stdout: String::new(),
+                    stderr: e.to_string(),
+                });
+                return;
+            }
+        };
+
+        if run.timed_out {
+            self.output.message(&format!(
+                "Hook {} timed out after {}s and was stopped",
+                event.name(),
+                self.hooks.timeout().as_secs()
+            ));
+        } else if !run.succeeded() {
+            let status = run.exit_code.map_or("killed by a signal".to_string(), |code| format!("exit code {}", code));
+            match run.stderr.lines().last() {
+                Some(line) => self.output.message(&format!("Hook {} failed ({}): {}", event.name(), status, line)),
+                None => self.output.message(&format!("Hook {} failed ({})", event.name(), status)),
+            }
+        } else {
+            self.output.message(&format!("Hook {} finished", event.name()));
+        }
+        self.output.emit(&Event::Hook {
+            hook: event,
+            command: run.command,
+            exit_code: run.exit_code,
+            timed_out: run.timed_out,
+            stdout: run.stdout,
+            stderr: run.stderr,
+        });
+    }
+
+    /// Runs the verify-failed hook for a checksum mismatch.
+    async fn verify_failed(&self, title: &str, path: Option<&Path>, error: &Error) {
+        let expected = match error {
+            Error::ChecksumMismatch { expected, .. } => Some(expected.clone()),
+            _ => None,
+        };
+        let context = HookContext {
+            title: Some(title.to_string()),
+            path: path.map(Path::to_path_buf),
+            size: path.and_then(|path| fs::metadata(path).ok()).map(|metadata| metadata.len()),
+            crc32: expected,
+            error: Some(error.to_string()),
+            ..HookContext::default()
+        };
+        self.run_hook(HookEvent::VerifyFailed, context).await;
+    }
+
+    /// Returns true if the library index has an image of this title.
+    fn owns(&self, title: &str) -> bool {
+        self.library
+            .as_ref()
+            .is_some_and(|library| library.lock().unwrap().owns_title(title))
+    }
+
+    /// Waits until `bytes` more fit in the speed limit, if one is set. Each chunk reserves the time it
+    /// takes at the limit, so concurrent downloads share the bandwidth.
+    async fn throttle(&self, bytes: usize) {
+        let Some(limit) = self.config.speed_limit else {
+            return;
+        };
+        let until = {
+            let mut next = self.next_chunk.lock().unwrap();
+            let start = (*next).max(Instant::now());
+            *next = start + Duration::from_secs_f64(bytes as f64 / limit.saturating_mul(1024) as f64);
+            *next
+        };
+        tokio::time::sleep_until(until).await;
+    }
+
     /// Download and unzip the file, handling both direct and external download methods.
-    async fn download_and_unzip(&self, link: &str, title: &str) -> Result<()> {
-        println!(" # ISO file...");
+    /// `link` is the ZIP's path relative to the ISO folder (and to each mirror).
+    async fn download_and_unzip(
+        &self,
+        link: &str,
+        title: &str,
+        split: Option<&(String, bool)>,
+        cancel: &CancellationToken,
+    ) -> Result<(DownloadStatus, Option<u32>, Option<SplitGame>)> {
+        self.output.message(" # ISO file...");
 
         let unzipped_file_name = format!("{}.iso", title);
         let unzipped_file_path = self.config.tmp_iso_folder_path().join(&unzipped_file_name);
 
-        // Skip download if file already exists
-        if unzipped_file_path.exists() {
-            println!(" - File previously downloaded :)\n");
-            return Ok(());
+        // Skip download if file already exists (possibly compressed)
+        let compressed = self
+            .config
+            .compression()
+            .map(|format| compress::output_path(&unzipped_file_path, format));
+        let converted = [unzipped_file_path.with_extension("cue"), chd::output_path(&unzipped_file_path)];
+        if unzipped_file_path.exists()
+            || compressed.is_some_and(|path| path.exists())
+            || converted.iter().any(|path| path.exists())
+        {
+            self.output.message(" - File previously downloaded :)\n");
+            return Ok((DownloadStatus::AlreadyPresent, None, None));
+        }
+        if self.owns(title) {
+            self.output.message(" - Already in your library :)\n");
+            return Ok((DownloadStatus::AlreadyPresent, None, None));
+        }
+        if let (Some((name, _)), Some(root)) = (split, self.config.opl_folder_path()) {
+            if usbextreme::read_cfg(&root)?.iter().any(|entry| &entry.name == name) {
+                self.output.message(" - Already in ul.cfg :)\n");
+                return Ok((DownloadStatus::AlreadyPresent, None, None));
+            }
         }
 
         let new_file_name = format!("{}.zip", title);
         let tmp_file = self.config.tmp_iso_folder_path().join(&new_file_name);
 
+        // The ISO folder first, then each mirror
+        let urls = self.config.download_urls(link);
         if self.config.external_iso_download {
-            self.download_using_navigator(link, &new_file_name, &tmp_file, &unzipped_file_name)
+            self.download_using_navigator(&urls[0], &new_file_name, &tmp_file, &unzipped_file_name, cancel)
                 .await?;
         } else {
-            self.download_using_request(link, &tmp_file).await?;
+            for (index, url) in urls.iter().enumerate() {
+                match self.download_using_request(url, &tmp_file, title, cancel).await {
+                    Ok(()) => break,
+                    // A mirror has the same files, so the partial ZIP is resumed from it
+                    Err(e @ (Error::Network(_) | Error::HttpStatus { .. } | Error::RetriesExhausted { .. }))
+                        if index + 1 < urls.len() =>
+                    {
+                        self.output.message(&format!("Download from {} failed ({}), trying the next mirror", url, e));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
         }
 
         // Unzip and clean up
+        let (mut crc32, mut split_game) = (None, None);
         if tmp_file.exists() {
-            self.unzip_file(&tmp_file).await?;
+            (crc32, split_game) = match self.unzip_file(&tmp_file, title, split, cancel).await {
+                Ok(unzipped) => unzipped,
+                // Resuming a corrupt ZIP would fail the same way, so the next run downloads it again
+                Err(e @ (Error::ArchiveCorrupt(_) | Error::ChecksumMismatch { .. })) => {
+                    self.remove_file(&tmp_file)?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
             self.remove_file(&tmp_file)?;
         }
 
-        println!(" ");
-        Ok(())
+        self.output.message(" ");
+        Ok((DownloadStatus::Downloaded, crc32, split_game))
     }
 
     /// Downloads a file using reqwest, supporting resume and progress bar.
     /// Retries on failure up to max_retries.
-    async fn download_using_request(&self, link: &str, file_path: &Path) -> Result<()> {
+    async fn download_using_request(
+        &self,
+        link: &str,
+        file_path: &Path,
+        title: &str,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
         let total_size = self.get_file_size(link).await?;
         let mut retries = 0;
 
+        let mut paused = self.paused.subscribe();
+
         while retries < self.config.max_retries {
+            if self.is_paused() {
+                self.output.message("Download paused");
+                tokio::select! {
+                    _ = wait_for_pause_state(&mut paused, false) => {}
+                    _ = cancel.cancelled() => return Err(Error::Cancelled),
+                }
+                self.output.message("Download resumed");
+            }
+
             let mut headers = reqwest::header::HeaderMap::new();
             let mut first_byte = 0;
 
@@ -91,113 +838,143 @@ impl Downloader {
                 if file_path.exists() {
                     first_byte = fs::metadata(file_path)?.len();
                     if first_byte >= size {
-                        println!("The file {} was downloaded previously.", file_path.display());
+                        self.output.message(&format!("The file {} was downloaded previously.", file_path.display()));
                         return Ok(());
                     }
                 }
-                headers.insert("Range", format!("bytes={}-{}", first_byte, size - 1).parse()?);
+                if let Ok(range) = format!("bytes={}-{}", first_byte, size - 1).parse() {
+                    headers.insert("Range", range);
+                }
             }
 
-            let client = reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(
-                    self.config.timeout_request.unwrap_or(600), // Much longer timeout for large files
-                ))
-                .connect_timeout(std::time::Duration::from_secs(30)) // Add connection timeout
-                .build()?;
+            let mut client = reqwest::Client::builder().connect_timeout(std::time::Duration::from_secs(30)); // Add connection timeout
+            // An empty TIMEOUT_REQUEST lets large files take as long as they need
+            if let Some(timeout) = self.config.timeout_request {
+                client = client.timeout(std::time::Duration::from_secs(timeout));
+            }
+            let client = client.build()?;
 
-            println!("Attempting download from: {}", link);
+            self.output.message(&format!("Attempting download from: {}", link));
             
-            match client.get(link).headers(headers).send().await {
+            let response = tokio::select! {
+                response = client.get(link).headers(headers).send() => response,
+                _ = cancel.cancelled() => return Err(Error::Cancelled),
+            };
+
+            match response {
                 Ok(response) => {
                     if response.status().is_success() {
                         // Open file for append and seek to the correct position
                         let mut file = OpenOptions::new()
                             .create(true)
-                            .append(false)
+                            .truncate(false)
                             .write(true)
                             .open(file_path)
                             .await?;
                         file.seek(SeekFrom::Start(first_byte)).await?;
                         let mut stream = response.bytes_stream();
 
-                        let progress_bar = if let Some(total) = total_size {
-                            let pb = ProgressBar::new(total);
-                            pb.set_style(
-                                ProgressStyle::default_bar()
-                                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                                    .unwrap()
-                                    .progress_chars("#>-")
-                            );
-                            pb.set_position(first_byte);
-                            Some(pb)
-                        } else {
-                            None
-                        };
-
-                        let mut downloaded = first_byte;
+                        let mut progress = self.output.progress(
+                            title,
+                            Stage::Download,
+                            total_size,
+                            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+                        );
+                        progress.set_position(first_byte);
+
                         let mut error_occurred = false;
-                        while let Some(chunk_result) = stream.next().await {
+                        let mut pause_requested = false;
+                        loop {
+                            let chunk_result = tokio::select! {
+                                chunk = stream.next() => chunk,
+                                _ = cancel.cancelled() => {
+                                    // Keep the partial file so the next run resumes with a Range request
+                                    file.flush().await?;
+                                    file.sync_all().await?;
+                                    progress.finish("Download cancelled");
+                                    return Err(Error::Cancelled);
+                                }
+                                _ = wait_for_pause_state(&mut paused, true) => {
+                                    // Drop the connection; the partial file is the resume point
+                                    file.flush().await?;
+                                    file.sync_all().await?;
+                                    progress.finish("Download paused");
+                                    pause_requested = true;
+                                    break;
+                                }
+                            };
+                            let Some(chunk_result) = chunk_result else {
+                                break;
+                            };
                             match chunk_result {
                                 Ok(chunk) => {
                                     file.write_all(&chunk).await?;
-                                    downloaded += chunk.len() as u64;
-                                    if let Some(pb) = &progress_bar {
-                                        pb.set_position(downloaded);
-                                    }
+                                    progress.inc(chunk.len() as u64);
+                                    self.throttle(chunk.len()).await;
                                 }
                                 Err(e) => {
-                                    println!("Error during download: {}", e);
+                                    self.output.message(&format!("Error during download: {}", e));
                                     error_occurred = true;
                                     break;
                                 }
                             }
                         }
 
+                        if pause_requested {
+                            continue;
+                        }
+
                         if error_occurred {
                             retries += 1;
                             if retries < self.config.max_retries {
-                                println!("Waiting {} seconds before retry...", self.config.delay_between_retries);
-                                tokio::time::sleep(tokio::time::Duration::from_secs(
-                                    self.config.delay_between_retries,
-                                ))
-                                .await;
+                                self.output.message(&format!("Waiting {} seconds before retry...", self.config.delay_between_retries));
+                                tokio::select! {
+                                    _ = tokio::time::sleep(tokio::time::Duration::from_secs(
+                                        self.config.delay_between_retries,
+                                    )) => {}
+                                    _ = cancel.cancelled() => return Err(Error::Cancelled),
+                                }
                             }
                             continue;
                         }
 
                         // Check if download was completed successfully
-                        if let Some(pb) = &progress_bar {
-                            if let Some(length) = pb.length() {
-                                if pb.position() >= length {
-                                    pb.finish_with_message("Download completed");
-                                    break;
-                                } else {
-                                    pb.finish_with_message("Download incomplete");
-                                }
-                            } else {
-                                pb.finish_with_message("Download completed");
+                        match total_size {
+                            Some(length) if progress.position() < length => {
+                                progress.finish("Download incomplete");
+                            }
+                            _ => {
+                                progress.finish("Download completed");
                                 break;
                             }
-                        } else {
-                            break;
                         }
                     } else {
-                        println!("HTTP error: {} - {}", response.status(), response.status().as_str());
+                        let status = response.status();
+                        self.output.message(&format!("HTTP error: {} - {}", status, status.as_str()));
+                        // Client errors (missing file, forbidden...) will not go away by retrying
+                        if status.is_client_error()
+                            && status != reqwest::StatusCode::REQUEST_TIMEOUT
+                            && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                        {
+                            return Err(Error::HttpStatus {
+                                status: status.as_u16(),
+                                url: link.to_string(),
+                            });
+                        }
                         retries += 1;
                     }
                 }
                 Err(e) => {
-                    println!("Request error (attempt {}/{}): {}", retries + 1, self.config.max_retries, e);
+                    self.output.message(&format!("Request error (attempt {}/{}): {}", retries + 1, self.config.max_retries, e));
                     retries += 1;
                 }
             }
         }
 
         if retries == self.config.max_retries {
-            anyhow::bail!(
-                "Failed to download file after {} attempts.",
-                self.config.max_retries
-            );
+            return Err(Error::RetriesExhausted {
+                attempts: self.config.max_retries,
+            });
         }
 
         Ok(())
@@ -210,37 +987,41 @@ impl Downloader {
         downloaded_file_name: &str,
         zip_file: &Path,
         unzipped_file: &str,
+        cancel: &CancellationToken,
     ) -> Result<()> {
         let destination_folder = self.config.tmp_iso_folder_path();
 
-        println!("Opening browser with download link ({})", route);
+        self.output.message(&format!("Opening browser with download link ({})", route));
         open::that(route)?;
 
         tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-        println!(
+        self.output.message(&format!(
             "Please download the file and copy '{}' to '{}'",
             downloaded_file_name,
             destination_folder.display()
-        );
+        ));
         self.open_explorer(&destination_folder);
 
         tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-        println!("Waiting for the file to be copied...");
+        self.output.message("Waiting for the file to be copied...");
 
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
 
         // Wait until the file is present
         while !zip_file.exists() && !destination_folder.join(unzipped_file).exists() {
-            println!(
+            self.output.message(&format!(
                 "\nFile not found!! Make sure to download and copy the file to '{}'",
                 destination_folder.display()
-            );
+            ));
             let mut input = String::new();
             std::io::stdin().read_line(&mut input)?;
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
         }
 
-        println!();
+        self.output.message("");
         Ok(())
     }
 
@@ -276,112 +1057,77 @@ impl Downloader {
     }
 
     /// Unzips the downloaded file, showing a progress bar if possible.
-    async fn unzip_file(&self, zip_path: &Path) -> Result<()> {
-        println!("Extracting ZIP file...");
-        let dest = zip_path.parent().unwrap();
-        
-        // Check file size first
-        let file_size = fs::metadata(zip_path)?.len();
-        if file_size == 0 {
-            anyhow::bail!("ZIP file is empty (0 bytes)");
-        }
-        
-        let file = fs::File::open(zip_path)?;
-        let mut archive = match ZipArchive::new(file) {
-            Ok(archive) => archive,
-            Err(e) => {
-                anyhow::bail!("Invalid ZIP archive: {}. The file may be corrupted or incomplete. Try downloading again.", e);
-            }
-        };
+    /// On cancellation the partially extracted file is removed and the ZIP is kept for the next run.
+    /// Returns the CRC32 of the extracted ISO, if the archive contained one.
+    /// With `split` (the ul.cfg name and whether it was shortened), ISOs are written as USBExtreme parts
+    /// into the OPL folder instead, and the installed game is returned too.
+    async fn unzip_file(
+        &self,
+        zip_path: &Path,
+        title: &str,
+        split: Option<&(String, bool)>,
+        cancel: &CancellationToken,
+    ) -> Result<(Option<u32>, Option<SplitGame>)> {
+        self.output.message("Extracting ZIP file...");
 
-        // Calculate total size first
-        let total_size: u64 = {
-            let file_names: Vec<String> = archive.file_names().map(|s| s.to_string()).collect();
-            file_names
-                .iter()
-                .filter_map(|name| {
-                    archive
-                        .by_name(name)
-                        .ok()
-                        .and_then(|file| file.size().checked_add(0))
-                })
-                .sum()
-        };
+        // Reading the archive and extracting it block, so both run off the async workers
+        let file = zip_path.to_path_buf();
+        let (archive, total_size) = tokio::task::spawn_blocking(move || open_zip(&file))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))??;
 
-        if total_size > 0 {
-            let progress_bar = ProgressBar::new(total_size);
-            progress_bar.set_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} Extracting: [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                    .unwrap()
-                    .progress_chars("#>-")
-            );
-
-            for i in 0..archive.len() {
-                let mut file = archive.by_index(i)?;
-                let outpath = dest.join(file.name());
-
-                if file.name().ends_with('/') {
-                    fs::create_dir_all(&outpath)?;
-                } else {
-                    if let Some(p) = outpath.parent() {
-                        if !p.exists() {
-                            fs::create_dir_all(p)?;
-                        }
-                    }
-                    let mut outfile = fs::File::create(&outpath)?;
-                    let mut buffer = [0u8; 8192];
-                    loop {
-                        let bytes_read = file.read(&mut buffer)?;
-                        if bytes_read == 0 {
-                            break;
-                        }
-                        outfile.write_all(&buffer[..bytes_read])?;
-                        progress_bar.inc(bytes_read as u64);
-                    }
-                }
-            }
+        // Fall back to a spinner for small files or when size calculation fails
+        let mut progress = self.output.progress(
+            title,
+            Stage::Extract,
+            (total_size > 0).then_some(total_size),
+            "{spinner:.green} Extracting: [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+        );
 
-            progress_bar.finish_with_message("Extraction completed");
-        } else {
-            // Fallback spinner for small files or when size calculation fails
-            let spinner = ProgressBar::new_spinner();
-            spinner.set_style(
-                ProgressStyle::default_spinner()
-                    .template("{spinner:.green} Extracting files... {elapsed_precise}")
-                    .unwrap()
-                    .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏"),
-            );
-
-            for i in 0..archive.len() {
-                let mut file = archive.by_index(i)?;
-                let outpath = dest.join(file.name());
-
-                if file.name().ends_with('/') {
-                    fs::create_dir_all(&outpath)?;
-                } else {
-                    if let Some(p) = outpath.parent() {
-                        if !p.exists() {
-                            fs::create_dir_all(p)?;
-                        }
-                    }
-                    let mut outfile = fs::File::create(&outpath)?;
-                    let mut buffer = [0u8; 8192];
-                    loop {
-                        let bytes_read = file.read(&mut buffer)?;
-                        if bytes_read == 0 {
-                            break;
-                        }
-                        outfile.write_all(&buffer[..bytes_read])?;
-                    }
-                }
-                spinner.tick();
+        let dest = zip_path.parent().unwrap().to_path_buf();
+        let parts_target = match (split, self.config.opl_folder_path()) {
+            (Some((name, _)), Some(root)) => Some((root, name.clone())),
+            _ => None,
+        };
+        let token = cancel.clone();
+        let (extracted, mut progress) = tokio::task::spawn_blocking(move || {
+            let result = extract_zip(archive, &dest, parts_target.as_ref(), &token, |bytes| progress.inc(bytes));
+            (result, progress)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        let extracted = match extracted {
+            Ok(extracted) => extracted,
+            Err(Error::Cancelled) => {
+                progress.finish("Extraction cancelled");
+                return Err(Error::Cancelled);
             }
+            Err(e) => return Err(e),
+        };
 
-            spinner.finish_with_message("Extraction completed");
+        let mut split_game = None;
+        if let (Some((parts, media)), Some((name, truncated)), Some(root)) =
+            (extracted.parts, split, self.config.opl_folder_path())
+        {
+            let size = parts.iter().filter_map(|part| fs::metadata(part).ok()).map(|metadata| metadata.len()).sum();
+            let (entry, disc) = usbextreme::install(&root, name, parts, media)?;
+            let path = entry.part_paths(&root).remove(0);
+            self.output.message(&format!(
+                "Split into {} parts for OPL as {} ({})",
+                entry.parts, entry.name, entry.serial
+            ));
+            self.output.emit(&Event::OplExport {
+                title: title.to_string(),
+                path: path.display().to_string(),
+                media: entry.media,
+                truncated: *truncated,
+            });
+            split_game = Some(SplitGame { path, disc, size });
         }
 
-        Ok(())
+        progress.finish("Extraction completed");
+
+        Ok((extracted.iso_crc32, split_game))
     }
 
     /// Removes a file, printing an error if it fails.
@@ -389,7 +1135,7 @@ impl Downloader {
         match fs::remove_file(file_path) {
             Ok(_) => Ok(()),
             Err(e) => {
-                println!("Error removing {}: {}", file_path.display(), e);
+                self.output.message(&format!("Error removing {}: {}", file_path.display(), e));
                 Ok(())
             }
         }
@@ -398,7 +1144,313 @@ impl Downloader {
     /// Opens the file explorer at the given path.
     fn open_explorer(&self, path: &Path) {
         if let Err(e) = open::that(path) {
-            println!("Error opening {}: {}", path.display(), e);
+            self.output.message(&format!("Error opening {}: {}", path.display(), e));
         }
     }
 }
+
+/// Opens a downloaded ZIP and returns it with the uncompressed size of its entries.
+fn open_zip(zip_path: &Path) -> Result<(ZipArchive<fs::File>, u64)> {
+    // Check file size first
+    let file_size = fs::metadata(zip_path)?.len();
+    if file_size == 0 {
+        return Err(Error::ArchiveCorrupt("ZIP file is empty (0 bytes)".to_string()));
+    }
+
+    let file = fs::File::open(zip_path)?;
+    let mut archive = ZipArchive::new(file)?;
+    let total_size = (0..archive.len())
+        .filter_map(|i| archive.by_index(i).ok().map(|file| file.size()))
+        .sum();
+    Ok((archive, total_size))
+}
+
+/// What `extract_zip` wrote.
+#[derive(Debug)]
+struct Extracted {
+    /// CRC32 of the ISO entry, if the archive contained one
+    iso_crc32: Option<u32>,
+    /// USBExtreme parts of the ISO and its media type, when it was split into the OPL folder
+    parts: Option<(Vec<PathBuf>, Media)>,
+}
+
+/// Extracts every entry of `archive` into `dest`, checking each against its CRC32 and reporting
+/// the bytes written to `progress`. With `parts_target` (the OPL folder and ul.cfg name), the ISO
+/// is written as USBExtreme parts instead. Runs on a blocking thread.
+fn extract_zip(
+    mut archive: ZipArchive<fs::File>,
+    dest: &Path,
+    parts_target: Option<&(PathBuf, String)>,
+    cancel: &CancellationToken,
+    mut progress: impl FnMut(u64),
+) -> Result<Extracted> {
+    let mut extracted = Extracted { iso_crc32: None, parts: None };
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        // Entry names come from the mirror: one that is absolute or climbs out with '..' is refused
+        let outpath = match file.enclosed_name() {
+            Some(name) => dest.join(name),
+            None => return Err(Error::ArchiveCorrupt(format!("{}: entry path leaves the download folder", file.name()))),
+        };
+
+        if file.name().ends_with('/') {
+            fs::create_dir_all(&outpath)?;
+        } else {
+            if let Some(p) = outpath.parent() {
+                if !p.exists() {
+                    fs::create_dir_all(p)?;
+                }
+            }
+            let is_iso = file.name().to_lowercase().ends_with(".iso");
+            let mut outfile = match parts_target {
+                Some((root, name)) if is_iso => Sink::Split(PartWriter::new(root, name)?),
+                _ => Sink::File(fs::File::create(&outpath)?),
+            };
+            let (file_name, size, expected) = (file.name().to_string(), file.size(), file.crc32());
+            let mut hasher = crc32fast::Hasher::new();
+            let mut buffer = [0u8; 8192];
+            // Reading no further than the entry's size stops before the zip reader's own end-of-entry
+            // checksum error, so a mismatch is caught by comparing the hasher below
+            let mut entry = (&mut file).take(size);
+            let copied = loop {
+                let bytes_read = match entry.read(&mut buffer) {
+                    Ok(0) => break Ok(()),
+                    Ok(n) => n,
+                    Err(e) if is_corrupt_data(&e) => break Err(Error::ArchiveCorrupt(format!("{}: {}", file_name, e))),
+                    Err(e) => break Err(e.into()),
+                };
+                if cancel.is_cancelled() {
+                    break Err(Error::Cancelled);
+                }
+                hasher.update(&buffer[..bytes_read]);
+                if let Err(e) = outfile.write_all(&buffer[..bytes_read]) {
+                    break Err(e.into());
+                }
+                progress(bytes_read as u64);
+            };
+
+            let actual = hasher.finalize();
+            let copied = copied.and_then(|()| match actual == expected {
+                true => Ok(()),
+                false => Err(Error::ChecksumMismatch {
+                    file: file_name,
+                    expected: format!("{:08x}", expected),
+                    actual: format!("{:08x}", actual),
+                }),
+            });
+            // A partial or corrupt image must not be taken for a finished download on the next run
+            if let Err(e) = copied {
+                outfile.discard(&outpath);
+                return Err(e);
+            }
+            if is_iso {
+                extracted.iso_crc32 = Some(actual);
+            }
+            if let Sink::Split(parts) = outfile {
+                extracted.parts = Some((parts.finish()?, Media::of_image(&outpath, size)));
+            }
+        }
+    }
+    Ok(extracted)
+}
+
+/// Returns true if reading a ZIP entry failed on undecodable or truncated data rather than on the disk.
+fn is_corrupt_data(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::InvalidInput | ErrorKind::InvalidData | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Destination of an extracted entry.
+enum Sink {
+    /// A regular file next to the ZIP
+    File(fs::File),
+    /// USBExtreme parts in the OPL folder
+    Split(PartWriter),
+}
+
+impl Sink {
+    /// Removes what was written so far; `path` is the regular file's.
+    fn discard(self, path: &Path) {
+        match self {
+            Sink::File(file) => {
+                drop(file);
+                let _ = fs::remove_file(path);
+            }
+            Sink::Split(parts) => parts.discard(),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::File(file) => file.write(buf),
+            Sink::Split(parts) => parts.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::File(file) => file.flush(),
+            Sink::Split(parts) => parts.flush(),
+        }
+    }
+}
+
+/// Waits until the paused flag equals `state`. Never returns if the sender is gone.
+async fn wait_for_pause_state(paused: &mut watch::Receiver<bool>, state: bool) {
+    loop {
+        if *paused.borrow_and_update() == state {
+            return;
+        }
+        if paused.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::write::{FileOptions, ZipWriter};
+
+    /// A folder under the system temp folder, emptied for the test.
+    fn scratch(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("ps2rd-downloader-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// Writes a stored ZIP with the given entries and opens it.
+    fn zip(folder: &Path, entries: &[(&str, &[u8])]) -> ZipArchive<fs::File> {
+        let path = folder.join("game.zip");
+        let mut writer = ZipWriter::new(fs::File::create(&path).unwrap());
+        for (name, data) in entries {
+            writer.start_file(*name, FileOptions::default().compression_method(zip::CompressionMethod::Stored)).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+        open_zip(&path).unwrap().0
+    }
+
+    #[test]
+    fn extracts_and_checks_entries() {
+        let folder = scratch("extract");
+        let archive = zip(&folder, &[("Alpha Game (USA).iso", &[7u8; 4096])]);
+        let mut written = 0;
+        let extracted = extract_zip(archive, &folder, None, &CancellationToken::new(), |bytes| written += bytes).unwrap();
+        assert_eq!(written, 4096);
+        assert_eq!(extracted.iso_crc32, Some(crc32fast::hash(&[7u8; 4096])));
+        assert_eq!(fs::read(folder.join("Alpha Game (USA).iso")).unwrap(), [7u8; 4096]);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn refuses_entries_outside_the_folder() {
+        let folder = scratch("slip");
+        let dest = folder.join("iso");
+        fs::create_dir_all(&dest).unwrap();
+        for name in ["../escaped.iso", "/tmp/escaped.iso"] {
+            let archive = zip(&folder, &[(name, b"data")]);
+            let error = extract_zip(archive, &dest, None, &CancellationToken::new(), |_| {}).unwrap_err();
+            assert!(matches!(error, Error::ArchiveCorrupt(_)), "{}: {}", name, error);
+        }
+        assert!(!folder.join("escaped.iso").exists());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn checksum_mismatch_removes_the_entry() {
+        let folder = scratch("crc");
+        let data = [3u8; 2048];
+        let archive = zip(&folder, &[("Alpha Game (USA).iso", &data)]);
+        // Flip one byte of the stored data, after the 30-byte local header and the name
+        let zip_path = folder.join("game.zip");
+        let mut bytes = fs::read(&zip_path).unwrap();
+        bytes[30 + "Alpha Game (USA).iso".len() + 100] ^= 0xFF;
+        drop(archive);
+        fs::write(&zip_path, bytes).unwrap();
+
+        let (archive, _) = open_zip(&zip_path).unwrap();
+        let error = extract_zip(archive, &folder, None, &CancellationToken::new(), |_| {}).unwrap_err();
+        assert!(matches!(error, Error::ChecksumMismatch { .. }), "{}", error);
+        assert!(!folder.join("Alpha Game (USA).iso").exists());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn split_download_reaches_the_result_library_and_hook() {
+        let folder = scratch("split");
+        let iso = crate::iso9660::tests::image("BOOT2 = cdrom0:\\SLUS_209.46;1\r\n", 2048);
+        let mut archive = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        archive.start_file("Alpha Game (USA).iso", FileOptions::default()).unwrap();
+        archive.write_all(&iso).unwrap();
+        let archive = archive.finish().unwrap().into_inner();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = axum::Router::new().route("/alpha.zip", axum::routing::get(move || async move { archive }));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut config = crate::config::Settings::defaults().config().unwrap();
+        config.iso_url = format!("http://{}/", address);
+        config.tmp_folder_name = folder.display().to_string();
+        config.opl_folder = folder.join("opl").display().to_string();
+        config.opl_split = true;
+        config.hook_on_download_complete = r#"echo "$PS2RD_SERIAL|$PS2RD_PATH|$PS2RD_SIZE""#.to_string();
+        fs::create_dir_all(config.tmp_iso_folder_path()).unwrap();
+        fs::create_dir_all(folder.join("opl")).unwrap();
+
+        let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let library = SharedLibrary::default();
+        let downloader = Downloader::new(&config).with_output(Output::Channel(sender)).with_library(library.clone());
+        let game = Game {
+            title: "Alpha Game (USA).zip".to_string(),
+            link: "alpha.zip".to_string(),
+            size: "40 KiB".to_string(),
+            lowercased_title: "alpha game (usa).zip".to_string(),
+        };
+        let result = downloader.download_ps2_element(&game, &CancellationToken::new()).await.unwrap();
+
+        let name = usbextreme::ul_name("Alpha Game").0;
+        let first_part = folder.join("opl").join(usbextreme::part_name(&name, "SLUS_209.46", 0));
+        assert!(matches!(result.status, DownloadStatus::Downloaded));
+        assert_eq!(result.path.as_deref(), Some(first_part.as_path()));
+        assert_eq!(result.disc.as_ref().and_then(|disc| disc.serial.as_deref()), Some("SLUS_209.46"));
+        assert!(!config.tmp_iso_folder_path().join("Alpha Game (USA).iso").exists());
+
+        let library = library.lock().unwrap();
+        assert!(library.owns(&game));
+        assert_eq!(library.entries()[0].serial.as_deref(), Some("SLUS_209.46"));
+
+        let mut hook = None;
+        while let Ok(event) = events.try_recv() {
+            if let Event::Hook { hook: HookEvent::DownloadComplete, stdout, .. } = event {
+                hook = Some(stdout);
+            }
+        }
+        assert_eq!(hook, Some(format!("SLUS_209.46|{}|{}", first_part.display(), iso.len())));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn only_undecodable_data_counts_as_corrupt() {
+        assert!(is_corrupt_data(&std::io::Error::new(ErrorKind::InvalidInput, "corrupt deflate stream")));
+        assert!(is_corrupt_data(&std::io::Error::from(ErrorKind::UnexpectedEof)));
+        assert!(!is_corrupt_data(&std::io::Error::other("device went away")));
+        assert!(!is_corrupt_data(&std::io::Error::from(ErrorKind::PermissionDenied)));
+    }
+
+    #[tokio::test]
+    async fn throttle_handles_the_largest_speed_limit() {
+        let mut config = crate::config::Settings::defaults().config().unwrap();
+        config.speed_limit = Some(u64::MAX);
+        let downloader = Downloader::new(&config);
+        let started = Instant::now();
+        downloader.throttle(1024 * 1024).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,142 @@
+use std::io;
+
+/// Result type used by the library API.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by `Scraper`, `Downloader` and `Config`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The request could not be sent or the connection failed
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    /// The server answered with a non-success status code
+    #[error("HTTP {status} for {url}")]
+    HttpStatus {
+        /// Status code returned by the server
+        status: u16,
+        /// Requested URL
+        url: String,
+    },
+    /// The download kept failing after all retries
+    #[error("Failed to download file after {attempts} attempts.")]
+    RetriesExhausted {
+        /// Number of attempts made
+        attempts: u32,
+    },
+    /// The downloaded archive is empty, truncated or otherwise unreadable
+    #[error("Invalid ZIP archive: {0}. The file may be corrupted or incomplete. Try downloading again.")]
+    ArchiveCorrupt(String),
+    /// A disc image has no readable ISO9660 filesystem
+    #[error("Invalid disc image: {0}")]
+    ImageInvalid(String),
+    /// An image could not be placed into the OPL folder
+    #[error("OPL export failed: {0}")]
+    ExportFailed(String),
+    /// A patch could not be read or does not fit the image
+    #[error("Patch failed: {0}")]
+    PatchFailed(String),
+    /// Extracted data does not match the expected checksum
+    #[error("Checksum mismatch for {file}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        /// File whose checksum was verified
+        file: String,
+        /// Expected checksum (hex)
+        expected: String,
+        /// Computed checksum (hex)
+        actual: String,
+    },
+    /// No space left on the destination device
+    #[error("Disk full: {0}")]
+    DiskFull(io::Error),
+    /// The configuration file is missing, unreadable or has invalid values
+    #[error("Invalid configuration: {0}")]
+    ConfigInvalid(String),
+    /// The operation was cancelled by the user
+    #[error("Cancelled")]
+    Cancelled,
+    /// Any other I/O failure
+    #[error("I/O error: {0}")]
+    Io(io::Error),
+    /// The cached game list could not be read or written
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::StorageFull {
+            Error::DiskFull(e)
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => e.into(),
+            e => Error::ArchiveCorrupt(e.to_string()),
+        }
+    }
+}
+
+impl Error {
+    /// Process exit code the CLI uses for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ConfigInvalid(_) => 2,
+            Error::Network(_) => 3,
+            Error::HttpStatus { .. } => 4,
+            Error::RetriesExhausted { .. } => 5,
+            Error::ArchiveCorrupt(_) => 6,
+            Error::ChecksumMismatch { .. } => 7,
+            Error::DiskFull(_) => 8,
+            Error::Io(_) | Error::Json(_) => 9,
+            Error::ImageInvalid(_) => 10,
+            Error::ExportFailed(_) => 11,
+            Error::PatchFailed(_) => 12,
+            Error::Cancelled => 130,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_match_the_readme() {
+        let cases = [
+            (Error::ConfigInvalid("x".to_string()), 2),
+            (Error::HttpStatus { status: 404, url: "u".to_string() }, 4),
+            (Error::RetriesExhausted { attempts: 3 }, 5),
+            (Error::ArchiveCorrupt("x".to_string()), 6),
+            (Error::ChecksumMismatch { file: "f".to_string(), expected: "0".to_string(), actual: "1".to_string() }, 7),
+            (Error::DiskFull(io::Error::from(io::ErrorKind::StorageFull)), 8),
+            (Error::Io(io::Error::from(io::ErrorKind::NotFound)), 9),
+            (serde_json::from_str::<u32>("{").unwrap_err().into(), 9),
+            (Error::ImageInvalid("x".to_string()), 10),
+            (Error::ExportFailed("x".to_string()), 11),
+            (Error::PatchFailed("x".to_string()), 12),
+            (Error::Cancelled, 130),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.exit_code(), code, "{}", error);
+        }
+    }
+
+    #[test]
+    fn io_errors_map_to_disk_full_or_io() {
+        assert!(matches!(Error::from(io::Error::from(io::ErrorKind::StorageFull)), Error::DiskFull(_)));
+        assert!(matches!(Error::from(io::Error::from(io::ErrorKind::PermissionDenied)), Error::Io(_)));
+    }
+
+    #[test]
+    fn zip_errors_map_to_corrupt_archive_unless_io() {
+        let error = Error::from(zip::result::ZipError::InvalidArchive("bad header"));
+        assert_eq!(error.exit_code(), 6);
+        let error = Error::from(zip::result::ZipError::Io(io::Error::from(io::ErrorKind::StorageFull)));
+        assert_eq!(error.exit_code(), 8);
+    }
+}
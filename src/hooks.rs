@@ -0,0 +1,205 @@
+use crate::error::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Hook timeout used when `[hooks] TIMEOUT` is not set.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Points at which a hook command runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    /// A game was downloaded and extracted
+    DownloadComplete,
+    /// An image did not match its recorded checksum
+    VerifyFailed,
+    /// The download queue ran out of games
+    QueueEmpty,
+    /// A saved search matched new or changed dumps (`watch`)
+    WatchMatch,
+}
+
+impl HookEvent {
+    /// Name of the event, as passed in `PS2RD_EVENT` and JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::DownloadComplete => "download_complete",
+            HookEvent::VerifyFailed => "verify_failed",
+            HookEvent::QueueEmpty => "queue_empty",
+            HookEvent::WatchMatch => "watch_match",
+        }
+    }
+}
+
+/// What a hook is told about the game, passed as `PS2RD_*` environment variables.
+#[derive(Debug, Clone, Default)]
+pub struct HookContext {
+    /// Clean title of the game (`PS2RD_TITLE`)
+    pub title: Option<String>,
+    /// Path of the image (`PS2RD_PATH`)
+    pub path: Option<PathBuf>,
+    /// Size of the image in bytes (`PS2RD_SIZE`)
+    pub size: Option<u64>,
+    /// Boot file serial, e.g. 'SLUS_209.46' (`PS2RD_SERIAL`)
+    pub serial: Option<String>,
+    /// CRC32 of the image (hex) (`PS2RD_CRC32`)
+    pub crc32: Option<String>,
+    /// Error message, for failed verifications (`PS2RD_ERROR`)
+    pub error: Option<String>,
+    /// Titles completed by the queue (`PS2RD_COMPLETED`)
+    pub completed: Option<usize>,
+    /// Titles that failed in the queue (`PS2RD_FAILED`)
+    pub failed: Option<usize>,
+    /// Saved search that matched (`PS2RD_QUERY`)
+    pub query: Option<String>,
+    /// New or changed titles matching the saved search, one per line (`PS2RD_MATCHES`)
+    pub matches: Option<Vec<String>>,
+}
+
+impl HookContext {
+    /// Environment variables for the hook; unknown values are empty.
+    fn variables(&self, event: HookEvent) -> Vec<(&'static str, String)> {
+        let text = |value: &Option<String>| value.clone().unwrap_or_default();
+        let number = |value: Option<u64>| value.map(|n| n.to_string()).unwrap_or_default();
+        vec![
+            ("PS2RD_EVENT", event.name().to_string()),
+            ("PS2RD_TITLE", text(&self.title)),
+            ("PS2RD_PATH", self.path.as_ref().map(|p| p.display().to_string()).unwrap_or_default()),
+            ("PS2RD_SIZE", number(self.size)),
+            ("PS2RD_SERIAL", text(&self.serial)),
+            ("PS2RD_CRC32", text(&self.crc32)),
+            ("PS2RD_ERROR", text(&self.error)),
+            ("PS2RD_COMPLETED", number(self.completed.map(|n| n as u64))),
+            ("PS2RD_FAILED", number(self.failed.map(|n| n as u64))),
+            ("PS2RD_QUERY", text(&self.query)),
+            ("PS2RD_MATCHES", self.matches.as_ref().map(|titles| titles.join("\n")).unwrap_or_default()),
+        ]
+    }
+}
+
+/// Result of running a hook command.
+#[derive(Debug, Clone)]
+pub struct HookRun {
+    /// Command line that was run
+    pub command: String,
+    /// Exit code, if the command exited normally
+    pub exit_code: Option<i32>,
+    /// Whether the command was killed for exceeding the timeout
+    pub timed_out: bool,
+    /// Captured standard output
+    pub stdout: String,
+    /// Captured standard error
+    pub stderr: String,
+}
+
+impl HookRun {
+    /// Returns true if the command exited with status 0.
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Commands configured in the `[hooks]` section.
+#[derive(Debug, Clone)]
+pub struct Hooks {
+    on_download_complete: Option<String>,
+    on_verify_failed: Option<String>,
+    on_queue_empty: Option<String>,
+    on_watch_match: Option<String>,
+    timeout: Duration,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            on_download_complete: None,
+            on_verify_failed: None,
+            on_queue_empty: None,
+            on_watch_match: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Hooks {
+    /// Creates hooks from the configured commands; empty commands are disabled.
+    pub fn new(
+        on_download_complete: &str,
+        on_verify_failed: &str,
+        on_queue_empty: &str,
+        on_watch_match: &str,
+        timeout: Duration,
+    ) -> Self {
+        let command = |command: &str| Some(command.trim().to_string()).filter(|command| !command.is_empty());
+        Self {
+            on_download_complete: command(on_download_complete),
+            on_verify_failed: command(on_verify_failed),
+            on_queue_empty: command(on_queue_empty),
+            on_watch_match: command(on_watch_match),
+            timeout,
+        }
+    }
+
+    /// The command configured for `event`, if any.
+    pub fn command(&self, event: HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::DownloadComplete => self.on_download_complete.as_deref(),
+            HookEvent::VerifyFailed => self.on_verify_failed.as_deref(),
+            HookEvent::QueueEmpty => self.on_queue_empty.as_deref(),
+            HookEvent::WatchMatch => self.on_watch_match.as_deref(),
+        }
+    }
+
+    /// Runs the command for `event` through the shell and captures its output.
+    /// Returns None when no command is configured. A command still running after
+    /// the timeout is killed.
+    pub async fn run(&self, event: HookEvent, context: &HookContext) -> Option<Result<HookRun>> {
+        let command = self.command(event)?;
+        Some(self.run_command(command, event, context).await)
+    }
+
+    async fn run_command(&self, command: &str, event: HookEvent, context: &HookContext) -> Result<HookRun> {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        let child = shell
+            .arg(command)
+            .envs(context.variables(event))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // Dropping the pending wait on timeout kills the child
+        let (exit_code, timed_out, stdout, stderr) =
+            match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+                Ok(output) => {
+                    let output = output?;
+                    (output.status.code(), false, output.stdout, output.stderr)
+                }
+                Err(_) => (None, true, Vec::new(), Vec::new()),
+            };
+        Ok(HookRun {
+            command: command.to_string(),
+            exit_code,
+            timed_out,
+            stdout: String::from_utf8_lossy(&stdout).trim_end().to_string(),
+            stderr: String::from_utf8_lossy(&stderr).trim_end().to_string(),
+        })
+    }
+
+    /// How long a hook may run before it is killed.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
@@ -0,0 +1,293 @@
+use crate::chd::ChdReader;
+use crate::compress::CompressedReader;
+use crate::cue;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Size of a logical ISO9660 sector.
+pub const SECTOR_SIZE: u64 = 2048;
+/// Size of a raw CD sector (BIN images).
+const RAW_SECTOR_SIZE: u64 = 2352;
+/// Sector of the first volume descriptor.
+const FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+/// Upper bound for the SYSTEM.CNF size; real files are a few hundred bytes.
+const MAX_SYSTEM_CNF_SIZE: u32 = 64 * 1024;
+/// Upper bound for the root directory size; PS2 discs use a few sectors.
+const MAX_DIRECTORY_SIZE: u32 = 4 * 1024 * 1024;
+/// Sync pattern at the start of every raw CD sector.
+const RAW_SYNC: [u8; 12] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+/// Identification data read from a PS2 disc image.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscInfo {
+    /// Boot file named by `BOOT2` in SYSTEM.CNF, e.g. 'SLUS_209.46'
+    pub serial: Option<String>,
+    /// Volume identifier of the primary volume descriptor
+    pub volume_label: String,
+}
+
+/// Reads the serial and volume label of an ISO, raw BIN, CUE, CSO, ZSO or CHD image.
+pub fn read_disc_info_from_path(path: &Path) -> Result<DiscInfo> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "cso" | "zso" => read_disc_info(CompressedReader::open(path)?),
+        "chd" => read_disc_info(ChdReader::open(path)?.sector_data()),
+        // The filesystem lives in the first track
+        "cue" => {
+            let tracks = cue::parse(path)?;
+            read_disc_info(BufReader::new(File::open(&tracks[0].file)?))
+        }
+        _ => read_disc_info(BufReader::new(File::open(path)?)),
+    }
+}
+
+/// Reads the serial and volume label from an ISO9660 filesystem.
+/// Both plain 2048-byte images and raw 2352-byte CD images (mode 1 or mode 2) are accepted.
+pub fn read_disc_info<R: Read + Seek>(reader: R) -> Result<DiscInfo> {
+    let mut image = SectorReader::new(reader)?;
+    let descriptor = image.primary_volume_descriptor()?;
+
+    let volume_label = String::from_utf8_lossy(&descriptor[40..72]).trim_end().to_string();
+    let root = DirectoryRecord::parse(&descriptor[156..190])
+        .ok_or_else(|| Error::ImageInvalid("invalid root directory record".to_string()))?;
+
+    let serial = match image.find_file(&root, "SYSTEM.CNF")? {
+        Some(record) => {
+            let content = image.read_file(&record, MAX_SYSTEM_CNF_SIZE)?;
+            parse_boot2(&String::from_utf8_lossy(&content))
+        }
+        None => None,
+    };
+
+    Ok(DiscInfo { serial, volume_label })
+}
+
+/// Extracts the boot file name from SYSTEM.CNF, e.g. 'BOOT2 = cdrom0:\SLUS_209.46;1' gives 'SLUS_209.46'.
+fn parse_boot2(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case("BOOT2") {
+            return None;
+        }
+        let file = value.trim().rsplit(['\\', '/', ':']).next()?;
+        let file = file.split(';').next()?.trim();
+        (!file.is_empty()).then(|| file.to_ascii_uppercase())
+    })
+}
+
+/// A directory record pointing at a file or directory extent.
+#[derive(Debug, Clone)]
+struct DirectoryRecord {
+    /// First sector of the extent
+    extent: u32,
+    /// Size of the extent in bytes
+    size: u32,
+    /// File identifier without the ';1' version suffix
+    name: String,
+    /// Whether the record is a directory
+    is_dir: bool,
+}
+
+impl DirectoryRecord {
+    /// Parses a record starting at the beginning of `data`.
+    fn parse(data: &[u8]) -> Option<Self> {
+        let length = *data.first()? as usize;
+        if length < 34 || data.len() < length {
+            return None;
+        }
+        let name_length = data[32] as usize;
+        let name = data.get(33..33 + name_length)?;
+        let name = String::from_utf8_lossy(name);
+        Some(Self {
+            extent: u32::from_le_bytes(data[2..6].try_into().ok()?),
+            size: u32::from_le_bytes(data[10..14].try_into().ok()?),
+            name: name.split(';').next().unwrap_or_default().to_string(),
+            is_dir: data[25] & 0x02 != 0,
+        })
+    }
+}
+
+/// Reads 2048-byte logical sectors from plain or raw images.
+struct SectorReader<R> {
+    reader: R,
+    /// Bytes per sector in the image
+    sector_size: u64,
+    /// Offset of the user data inside each sector
+    data_offset: u64,
+}
+
+impl<R: Read + Seek> SectorReader<R> {
+    /// Detects the sector layout from the first sector.
+    fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 16];
+        reader.seek(SeekFrom::Start(0))?;
+        let raw = reader.read_exact(&mut header).is_ok() && header[..12] == RAW_SYNC;
+        let (sector_size, data_offset) = match (raw, header[15]) {
+            (false, _) => (SECTOR_SIZE, 0),
+            // Mode 1 has user data right after the header, mode 2 form 1 after an 8-byte subheader
+            (true, 1) => (RAW_SECTOR_SIZE, 16),
+            (true, _) => (RAW_SECTOR_SIZE, 24),
+        };
+        Ok(Self {
+            reader,
+            sector_size,
+            data_offset,
+        })
+    }
+
+    /// Reads one logical sector.
+    fn read_sector(&mut self, sector: u64) -> Result<[u8; SECTOR_SIZE as usize]> {
+        let mut buffer = [0u8; SECTOR_SIZE as usize];
+        self.reader
+            .seek(SeekFrom::Start(sector * self.sector_size + self.data_offset))?;
+        self.reader.read_exact(&mut buffer).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => Error::ImageInvalid(format!("image ends before sector {}", sector)),
+            _ => e.into(),
+        })?;
+        Ok(buffer)
+    }
+
+    /// Finds the primary volume descriptor (type 1) in the descriptor set.
+    fn primary_volume_descriptor(&mut self) -> Result<[u8; SECTOR_SIZE as usize]> {
+        for sector in FIRST_DESCRIPTOR_SECTOR.. {
+            let descriptor = self.read_sector(sector)?;
+            if &descriptor[1..6] != b"CD001" {
+                return Err(Error::ImageInvalid("no ISO9660 volume descriptor".to_string()));
+            }
+            match descriptor[0] {
+                1 => return Ok(descriptor),
+                // Volume descriptor set terminator
+                255 => break,
+                _ => {}
+            }
+        }
+        Err(Error::ImageInvalid("no primary volume descriptor".to_string()))
+    }
+
+    /// Looks up a file in a directory by name (case-insensitive).
+    fn find_file(&mut self, dir: &DirectoryRecord, name: &str) -> Result<Option<DirectoryRecord>> {
+        // The size comes from the image, so a damaged record must not decide how much is read
+        if dir.size > MAX_DIRECTORY_SIZE {
+            return Err(Error::ImageInvalid(format!("directory of {} bytes is too large", dir.size)));
+        }
+        let data = self.read_file(dir, dir.size)?;
+        let mut offset = 0;
+        while offset < data.len() {
+            let length = data[offset] as usize;
+            if length == 0 {
+                // Records never cross sector boundaries; the rest of the sector is padding
+                offset = (offset / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+            if let Some(record) = DirectoryRecord::parse(&data[offset..]) {
+                if !record.is_dir && record.name.eq_ignore_ascii_case(name) {
+                    return Ok(Some(record));
+                }
+            }
+            offset += length;
+        }
+        Ok(None)
+    }
+
+    /// Reads the extent of a record, up to `limit` bytes.
+    fn read_file(&mut self, record: &DirectoryRecord, limit: u32) -> Result<Vec<u8>> {
+        let size = record.size.min(limit) as usize;
+        let mut data = Vec::with_capacity(size);
+        let mut sector = record.extent as u64;
+        while data.len() < size {
+            let buffer = self.read_sector(sector)?;
+            let take = (size - data.len()).min(buffer.len());
+            data.extend_from_slice(&buffer[..take]);
+            sector += 1;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A directory record for `name` at `extent`.
+    fn record(name: &str, extent: u32, size: u32, is_dir: bool) -> Vec<u8> {
+        let mut record = vec![0u8; 33];
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[25] = if is_dir { 0x02 } else { 0 };
+        record[32] = name.len() as u8;
+        record.extend(name.as_bytes());
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    /// A 2048-byte-sector image with a root directory holding SYSTEM.CNF.
+    pub(crate) fn image(system_cnf: &str, root_size: u32) -> Vec<u8> {
+        let mut image = vec![0u8; 20 * SECTOR_SIZE as usize];
+        let sector = |n: usize| n * SECTOR_SIZE as usize;
+
+        let descriptor = &mut image[sector(16)..sector(17)];
+        descriptor[0] = 1;
+        descriptor[1..6].copy_from_slice(b"CD001");
+        descriptor[40..72].copy_from_slice(&[b' '; 32]);
+        descriptor[40..50].copy_from_slice(b"SLUS_20946");
+        let root = record("\0", 18, root_size, true);
+        descriptor[156..156 + root.len()].copy_from_slice(&root);
+        image[sector(17)] = 255;
+        image[sector(17) + 1..sector(17) + 6].copy_from_slice(b"CD001");
+
+        let mut entries = record("\0", 18, SECTOR_SIZE as u32, true);
+        entries.extend(record("SYSTEM.CNF;1", 19, system_cnf.len() as u32, false));
+        image[sector(18)..sector(18) + entries.len()].copy_from_slice(&entries);
+        image[sector(19)..sector(19) + system_cnf.len()].copy_from_slice(system_cnf.as_bytes());
+        image
+    }
+
+    #[test]
+    fn parses_boot2() {
+        let cases = [
+            ("BOOT2 = cdrom0:\\SLUS_209.46;1\r\nVER = 1.00\r\nVMODE = NTSC\r\n", Some("SLUS_209.46")),
+            ("VER = 1.00\nboot2=cdrom0:\\slps_250.50;1\n", Some("SLPS_250.50")),
+            ("BOOT2 = cdrom0:\\DATA\\SCES_500.00;1", Some("SCES_500.00")),
+            ("BOOT2 = cdrom0:/SLES_123.45", Some("SLES_123.45")),
+            ("BOOT = cdrom:\\SLUS_000.01;1", None),
+            ("BOOT2 = cdrom0:\\;1", None),
+            ("", None),
+        ];
+        for (content, serial) in cases {
+            assert_eq!(parse_boot2(content).as_deref(), serial, "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn reads_serial_and_label() {
+        let image = image("BOOT2 = cdrom0:\\SLUS_209.46;1\r\nVER = 1.00\r\n", SECTOR_SIZE as u32);
+        let info = read_disc_info(Cursor::new(&image)).unwrap();
+        assert_eq!(info.serial.as_deref(), Some("SLUS_209.46"));
+        assert_eq!(info.volume_label, "SLUS_20946");
+
+        // The same filesystem in raw mode 2 sectors
+        let mut raw = Vec::new();
+        for sector in image.chunks(SECTOR_SIZE as usize) {
+            raw.extend(RAW_SYNC);
+            // Header (mode 2 in its last byte) and an 8-byte subheader, then the data and EDC/ECC
+            raw.extend([0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0]);
+            raw.extend(sector);
+            raw.extend([0u8; 280]);
+        }
+        assert_eq!(read_disc_info(Cursor::new(&raw)).unwrap(), info);
+    }
+
+    #[test]
+    fn rejects_oversized_directories() {
+        let image = image("BOOT2 = cdrom0:\\SLUS_209.46;1\n", u32::MAX);
+        let error = read_disc_info(Cursor::new(&image)).unwrap_err();
+        assert!(matches!(error, Error::ImageInvalid(message) if message.contains("too large")));
+    }
+}
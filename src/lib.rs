@@ -1,13 +1,39 @@
 // Module declarations for the PS2 Redump Downloader library
+pub mod batch;
+pub mod chd;
+pub mod compress;
 pub mod config;
+pub mod cue;
+pub mod discs;
 pub mod downloader;
+pub mod error;
+pub mod hooks;
+pub mod iso9660;
+pub mod library;
 pub mod models;
+pub mod one_game;
+pub mod opl;
+pub mod output;
+pub mod patch;
+pub mod queue;
 pub mod scraper;
+pub mod search;
+pub mod selection;
+pub mod server;
+pub mod tui;
+pub mod usbextreme;
 pub mod utils;
+pub mod watch;
+pub mod wishlist;
 
 // Re-export main types and functions for convenient access
 pub use config::Config;
 pub use downloader::Downloader;
+pub use error::{Error, Result};
+pub use library::Library;
 pub use models::Game;
+pub use output::Output;
+pub use queue::DownloadQueue;
 pub use scraper::Scraper;
 pub use utils::setup_folders;
+pub use wishlist::Wishlist;
@@ -0,0 +1,283 @@
+use crate::error::Result;
+use crate::iso9660::{read_disc_info_from_path, DiscInfo};
+use crate::models::Game;
+use crate::utils::crc32_file;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// Library index shared between the downloader and the user interfaces.
+pub type SharedLibrary = Arc<Mutex<Library>>;
+
+/// Disc image formats recognised by the scanner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// Plain ISO9660 image
+    Iso,
+    /// CUE sheet of a BIN/CUE image
+    Cue,
+    /// Raw BIN track
+    Bin,
+    /// MAME compressed hunks of data
+    Chd,
+    /// Deflate-compressed ISO
+    Cso,
+    /// LZ4-compressed ISO
+    Zso,
+}
+
+impl ImageFormat {
+    /// Recognises a disc image by its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "iso" => Some(ImageFormat::Iso),
+            "cue" => Some(ImageFormat::Cue),
+            "bin" => Some(ImageFormat::Bin),
+            "chd" => Some(ImageFormat::Chd),
+            "cso" => Some(ImageFormat::Cso),
+            "zso" => Some(ImageFormat::Zso),
+            _ => None,
+        }
+    }
+}
+
+/// A disc image found in one of the library roots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    /// Absolute path of the image
+    pub path: PathBuf,
+    /// Image format
+    pub format: ImageFormat,
+    /// Title derived from the file name (extension and track suffix removed)
+    pub title: String,
+    /// File size in bytes
+    pub size: u64,
+    /// Modification time (seconds since the Unix epoch), used to skip unchanged files
+    pub modified: Option<u64>,
+    /// CRC32 of the file (hex), when checksums are enabled or it was downloaded by this tool
+    pub crc32: Option<String>,
+    /// Boot file serial read from SYSTEM.CNF, e.g. 'SLUS_209.46'
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Volume label of the ISO9660 filesystem
+    #[serde(default)]
+    pub volume_label: Option<String>,
+}
+
+impl LibraryEntry {
+    /// Stores the serial and volume label read from the image.
+    fn set_disc_info(&mut self, disc: DiscInfo) {
+        self.serial = disc.serial;
+        self.volume_label = Some(disc.volume_label);
+    }
+}
+
+/// Summary of a library scan.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanSummary {
+    /// Images in the index after the scan
+    pub images: usize,
+    /// Images found for the first time or changed since the last scan
+    pub updated: usize,
+    /// Images removed because they no longer exist
+    pub removed: usize,
+}
+
+/// Persistent index of the disc images the user already has.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Library {
+    /// Images found by the last scan or recorded after a download
+    entries: Vec<LibraryEntry>,
+    /// CRC32 (hex) of images extracted by this tool, mapped to their catalog title
+    known_checksums: HashMap<String, String>,
+    /// Lowercased titles considered owned
+    #[serde(skip)]
+    owned: HashSet<String>,
+}
+
+impl Library {
+    /// Loads the index from `path`, or returns an empty library if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut library: Library = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            Library::default()
+        };
+        library.rebuild_owned();
+        Ok(library)
+    }
+
+    /// Saves the index to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Walks the library roots and updates the index. Unchanged files keep their checksum,
+    /// so only new or modified files are hashed when `checksum` is enabled.
+    pub fn scan(&mut self, roots: &[PathBuf], checksum: bool) -> Result<ScanSummary> {
+        let mut previous: HashMap<PathBuf, LibraryEntry> = self
+            .entries
+            .drain(..)
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        let mut summary = ScanSummary::default();
+
+        let mut files = Vec::new();
+        for root in roots {
+            if root.is_dir() {
+                collect_images(root, &mut files)?;
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for path in files {
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            let Some(format) = ImageFormat::from_path(&path) else {
+                continue;
+            };
+            let metadata = fs::metadata(&path)?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs());
+
+            let mut entry = match previous.remove(&path) {
+                Some(entry) if entry.size == metadata.len() && entry.modified == modified => entry,
+                _ => {
+                    summary.updated += 1;
+                    LibraryEntry {
+                        title: title_from_path(&path),
+                        path: path.clone(),
+                        format,
+                        size: metadata.len(),
+                        modified,
+                        crc32: None,
+                        serial: None,
+                        volume_label: None,
+                    }
+                }
+            };
+            if checksum && entry.crc32.is_none() {
+                entry.crc32 = Some(format!("{:08x}", crc32_file(&path)?));
+            }
+            // Images without a filesystem are retried on every scan; reading it only takes a few sectors
+            if entry.volume_label.is_none() {
+                if let Ok(disc) = read_disc_info_from_path(&path) {
+                    entry.set_disc_info(disc);
+                }
+            }
+            self.entries.push(entry);
+        }
+
+        summary.removed = previous.len();
+        summary.images = self.entries.len();
+        self.rebuild_owned();
+        Ok(summary)
+    }
+
+    /// Records an image extracted by the downloader so it is owned without a rescan.
+    pub fn record_download(&mut self, title: &str, path: &Path, crc32: Option<u32>, disc: Option<DiscInfo>) {
+        let crc32 = crc32.map(|crc| format!("{:08x}", crc));
+        if let Some(crc) = &crc32 {
+            self.known_checksums.insert(crc.clone(), title.to_string());
+        }
+        if let Ok(metadata) = fs::metadata(path) {
+            self.entries.retain(|entry| entry.path != path);
+            let mut entry = LibraryEntry {
+                path: path.to_path_buf(),
+                format: ImageFormat::from_path(path).unwrap_or(ImageFormat::Iso),
+                title: title.to_string(),
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_secs()),
+                crc32,
+                serial: None,
+                volume_label: None,
+            };
+            if let Some(disc) = disc {
+                entry.set_disc_info(disc);
+            }
+            self.entries.push(entry);
+        }
+        self.rebuild_owned();
+    }
+
+    /// Returns true if an image of this game is in the library.
+    pub fn owns(&self, game: &Game) -> bool {
+        self.owns_title(&game.clean_title())
+    }
+
+    /// Returns true if an image with this clean title is in the library.
+    pub fn owns_title(&self, title: &str) -> bool {
+        self.owned.contains(&title.to_lowercase())
+    }
+
+    /// Returns the images that belong to this game.
+    pub fn entries_for(&self, game: &Game) -> Vec<&LibraryEntry> {
+        let title = game.clean_title().to_lowercase();
+        self.entries
+            .iter()
+            .filter(|entry| self.entry_title(entry).to_lowercase() == title)
+            .collect()
+    }
+
+    /// All indexed images.
+    pub fn entries(&self) -> &[LibraryEntry] {
+        &self.entries
+    }
+
+    /// The catalog title of an entry: its file name, or the title recorded for its checksum.
+    fn entry_title<'a>(&'a self, entry: &'a LibraryEntry) -> &'a str {
+        entry
+            .crc32
+            .as_ref()
+            .and_then(|crc| self.known_checksums.get(crc))
+            .map_or(entry.title.as_str(), String::as_str)
+    }
+
+    fn rebuild_owned(&mut self) {
+        self.owned = self
+            .entries
+            .iter()
+            .map(|entry| self.entry_title(entry).to_lowercase())
+            .collect();
+    }
+}
+
+/// Recursively collects files with a recognised image extension.
+fn collect_images(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_images(&path, files)?;
+        } else if ImageFormat::from_path(&path).is_some() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Derives a catalog-style title from an image file name,
+/// e.g. 'Game (USA) (Track 1).bin' becomes 'Game (USA)'.
+fn title_from_path(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match stem.rfind(" (Track ") {
+        Some(index) if stem.ends_with(')') => stem[..index].to_string(),
+        _ => stem,
+    }
+}
@@ -1,99 +1,1257 @@
 use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
 use ps2_redump_downloader::{
-    config::Config, downloader::Downloader, models::Game, scraper::Scraper, utils::setup_folders,
+    batch::{self, Resolution},
+    hooks::{HookContext, HookEvent},
+    config::{self, Config, Settings},
+    downloader::Downloader,
+    error::Error,
+    iso9660::DiscInfo,
+    library::{ImageFormat, Library, SharedLibrary},
+    models::Game,
+    one_game,
+    output::{Event, Output, SearchResult, Stage, UnresolvedEntry},
+    patch::{self, PatchEntry},
+    queue::{DownloadQueue, QueueSummary},
+    scraper::Scraper,
+    search::filter_games,
+    selection::parse_selection,
+    server,
+    tui,
+    utils::{format_size, setup_folders},
+    watch::SavedSearches,
+    wishlist::Wishlist,
 };
 use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Search, download and extract PS2 ISOs from the Redump database.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Emit machine-readable JSON lines on stdout instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Config file to use instead of searching the default locations (TOML or INI)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Apply the config file's [profile.NAME] section over the base settings [env: PS2RD_PROFILE]
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Override a setting for this run, e.g. --set download.max_retries=3 (repeatable)
+    #[arg(long = "set", global = true, value_name = "SECTION.KEY=VALUE")]
+    overrides: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Browse the catalog and manage downloads in a full-screen terminal UI
+    Tui,
+    /// Download titles without the interactive prompt
+    Download(DownloadArgs),
+    /// Rescan the library roots and list the images found
+    Library,
+    /// Export every ISO of the library into the configured OPL folder
+    OplExport,
+    /// Apply an IPS, PPF or xdelta (VCDIFF) patch to an ISO, writing a new file
+    Patch(PatchArgs),
+    /// Run a download server with an HTTP API, for queueing downloads from other machines
+    Serve(ServeArgs),
+    /// Keep a list of titles to download later
+    #[command(subcommand)]
+    Wishlist(WishlistCommand),
+    /// Refresh the catalog and report new dumps matching the saved searches
+    Watch(WatchArgs),
+    /// Pick one version of every game by the [1g1r] preferences, then list or download them
+    #[command(name = "1g1r")]
+    OneGame(OneGameArgs),
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective value of every setting and where it came from
+    Show,
+    /// Report unknown sections and keys and invalid values, exiting with code 2 if there are any
+    Check,
+    /// Write a documented config file after asking for the most important settings
+    Init,
+    /// Upgrade a config file written for an older layout, keeping the previous file as '.bak'
+    Migrate,
+}
+
+#[derive(Subcommand)]
+enum WishlistCommand {
+    /// Add titles: exact titles, Redump serials or search queries matching a single game
+    Add {
+        /// Titles, serials or queries
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+    /// Remove titles by exact title or by their number in 'wishlist list'
+    Remove {
+        /// Titles or numbers
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+    /// List the wishlist, marking titles already in the library
+    List,
+}
+
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true)]
+struct WatchArgs {
+    #[command(subcommand)]
+    command: Option<WatchCommand>,
+
+    /// Queue new matches not yet in the library
+    #[arg(long)]
+    download: bool,
+
+    /// Start the download without confirmation
+    #[arg(long, short)]
+    yes: bool,
+
+    /// Compare the cached catalog instead of downloading it again
+    #[arg(long)]
+    cached: bool,
+}
+
+#[derive(Subcommand)]
+enum WatchCommand {
+    /// Save search queries; titles they match now are not reported
+    Add {
+        /// Queries, each quoted as a whole, e.g. "final fantasy usa"
+        #[arg(required = true)]
+        queries: Vec<String>,
+    },
+    /// Remove saved searches by query or by their number in 'watch list'
+    Remove {
+        /// Queries or numbers
+        #[arg(required = true)]
+        queries: Vec<String>,
+    },
+    /// List the saved searches
+    List,
+}
+
+#[derive(Args)]
+struct OneGameArgs {
+    /// Only consider titles matching this search
+    #[arg(long, short, value_name = "QUERY")]
+    query: Option<String>,
+
+    /// Write the picked titles to a list file for 'download --from-file'
+    #[arg(long, short, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Queue the picked titles not yet in the library
+    #[arg(long)]
+    download: bool,
+
+    /// Start the download without confirmation
+    #[arg(long, short)]
+    yes: bool,
+
+    /// Also list the versions passed over
+    #[arg(long, short)]
+    verbose: bool,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on [default: '[server] LISTEN' from the config]
+    #[arg(long, value_name = "ADDRESS")]
+    listen: Option<String>,
+}
+
+#[derive(Args)]
+struct PatchArgs {
+    /// ISO to patch (left untouched)
+    image: PathBuf,
+    /// Patch file
+    patch: PathBuf,
+    /// Output file [default: '<image> [<patch name>].iso' next to the image]
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// CRC32 (hex) the image must have before patching
+    #[arg(long, value_name = "CRC32")]
+    source_crc32: Option<String>,
+}
+
+#[derive(Args)]
+struct DownloadArgs {
+    #[command(flatten)]
+    source: DownloadSource,
+
+    /// Start without confirmation, skipping list entries that cannot be resolved
+    #[arg(long, short)]
+    yes: bool,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct DownloadSource {
+    /// Read exact titles, Redump serials or search queries from a file, one per line
+    #[arg(long, value_name = "FILE")]
+    from_file: Option<PathBuf>,
+
+    /// Download every wishlist title not yet in the library
+    #[arg(long)]
+    wishlist: bool,
+}
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
+async fn main() {
+    let cli = Cli::parse();
+    let output = if cli.json { Output::Json } else { Output::Text };
+
+    if let Err(e) = run(cli.command, cli.config, cli.profile, cli.overrides, output.clone()).await {
+        if output.is_json() {
+            output.emit(&Event::Error { message: format!("{:#}", e) });
+        } else {
+            eprintln!("Error: {:#}", e);
+        }
+        // Library errors map to distinct exit codes so wrappers can react to them
+        let code = e.downcast_ref::<Error>().map_or(1, Error::exit_code);
+        std::process::exit(code);
+    }
+}
 
-    // Load configuration (searches or creates in recommended locations)
-    let (config, config_path) = Config::load_or_create()?;
-    println!("Using config file at: {}", config_path.display());
+/// Loads the configuration and catalog, then runs the selected command (the interactive loop by default).
+async fn run(
+    command: Option<Command>,
+    config_file: Option<PathBuf>,
+    profile: Option<String>,
+    overrides: Vec<String>,
+    output: Output,
+) -> Result<()> {
+    // Initialize logging (stderr keeps stdout clean in JSON mode)
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+
+    // The wizard writes the config file, so it runs before one is searched for or created
+    if let Some(Command::Config(ConfigCommand::Init)) = command {
+        return run_config_init(config_file.as_deref(), &output);
+    }
+    // Migrating rewrites the file, so it does not load it first either
+    if let Some(Command::Config(ConfigCommand::Migrate)) = command {
+        return run_config_migrate(config_file.as_deref(), &output);
+    }
+
+    // Load configuration: defaults, the config file (searched or created), its profile, PS2RD_* variables,
+    // then --set flags
+    let settings = Settings::load(config_file.as_deref(), profile.as_deref(), &overrides)?;
+    if let Some(path) = settings.file() {
+        output.message(&format!("Using config file at: {}", path.display()));
+    }
+    if let Some(profile) = settings.profile() {
+        output.message(&format!("Using profile: {}", profile));
+    }
+    match command {
+        Some(Command::Config(ConfigCommand::Show)) => {
+            print_settings(&settings, &output);
+            return Ok(());
+        }
+        Some(Command::Config(ConfigCommand::Check)) => return check_settings(&settings, &output),
+        _ => {}
+    }
+    for warning in settings.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+    let config = settings.config()?;
 
     // Setup working folders
     setup_folders(&config)?;
 
+    // Index the images the user already has
+    let library = scan_library(&config, &output)?;
+    match command {
+        Some(Command::Library) => {
+            print_library(&library.lock().unwrap(), &output);
+            return Ok(());
+        }
+        Some(Command::OplExport) => return run_opl_export(&config, &library.lock().unwrap(), &output),
+        Some(Command::Patch(args)) => return run_patch(args, &output).await,
+        _ => {}
+    }
+
+    // The first Ctrl-C cancels the current operation cleanly, a second one exits immediately.
+    // Installed before the catalog is scraped, so that can be cancelled too
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.cancel();
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    std::process::exit(Error::Cancelled.exit_code());
+                }
+            }
+        }
+    });
+
     // Initialize scraper and downloader
-    let scraper = Scraper::new(&config);
-    let downloader = Downloader::new(&config);
+    let scraper = Scraper::new(&config).with_output(output.clone());
+    let mut downloader = Downloader::new(&config)
+        .with_output(output.clone())
+        .with_library(library.clone());
+    if let Some(opl) = config.opl_export() {
+        downloader = downloader.with_opl(opl);
+    }
 
     // Get PS2 game list
-    let games = scraper.get_ps2_list().await?;
+    let games = tokio::select! {
+        games = scraper.get_ps2_list() => games?,
+        _ = cancel.cancelled() => return Err(Error::Cancelled.into()),
+    };
+    let downloader = downloader.with_catalog(&games);
+
+    match command {
+        Some(Command::Tui) => {
+            tui::run(&config, games, library, &cancel).await?;
+            return Ok(());
+        }
+        Some(Command::Download(args)) => {
+            return run_download(args, &scraper, &downloader, &games, &library, &output, &cancel).await;
+        }
+        Some(Command::Wishlist(command)) => {
+            return run_wishlist(command, &scraper, &games, &library, &config, &output).await;
+        }
+        Some(Command::Watch(args)) => {
+            return run_watch(args, &scraper, &downloader, games, &library, &output, &cancel).await;
+        }
+        Some(Command::OneGame(args)) => {
+            return run_one_game(args, &downloader, &games, &library, &output, &cancel).await;
+        }
+        Some(Command::Serve(args)) => {
+            let listen = args.listen.as_deref().unwrap_or(&config.server_listen);
+            server::serve(&config, games, library, listen, &output, &cancel).await?;
+            return Ok(());
+        }
+        Some(Command::Library) | Some(Command::OplExport) | Some(Command::Patch(_)) | Some(Command::Config(_)) | None => {}
+    }
 
     // Main application loop
-    run_main_loop(&downloader, games).await?;
+    // Browser downloads read stdin themselves, so pause/resume commands are only read otherwise
+    let controls = !config.external_iso_download;
+    let mut wishlist = Wishlist::load(&config.wishlist_path())?;
+    run_main_loop(&downloader, games, &library, &mut wishlist, &output, controls, &cancel).await?;
 
     Ok(())
 }
 
+/// Loads the library index, rescans the library roots and saves the updated index.
+fn scan_library(config: &Config, output: &Output) -> Result<SharedLibrary> {
+    let index_path = config.library_index_path();
+    let mut library = Library::load(&index_path)?;
+    let summary = library.scan(&config.library_root_paths(), config.library_checksum)?;
+    library.save(&index_path)?;
+    output.message(&format!(
+        "Library: {} images ({} new or changed, {} removed)",
+        summary.images, summary.updated, summary.removed
+    ));
+    Ok(Arc::new(Mutex::new(library)))
+}
+
+/// Lists the effective settings with their source (one JSON line per setting in JSON mode).
+fn print_settings(settings: &Settings, output: &Output) {
+    let lines: Vec<(String, String)> = settings
+        .values()
+        .iter()
+        .map(|value| (format!("{} = {}", value.setting.key, value.display()), value.source.to_string()))
+        .collect();
+    let width = lines.iter().map(|(line, _)| line.chars().count()).max().unwrap_or(0);
+    let mut section = "";
+    for (index, (value, (line, source))) in settings.values().iter().zip(&lines).enumerate() {
+        if output.is_json() {
+            let line = serde_json::json!({
+                "section": value.setting.section,
+                "key": value.setting.key,
+                "value": value.display(),
+                "source": source,
+                "env": value.setting.env_name(),
+            });
+            println!("{}", line);
+            continue;
+        }
+        if value.setting.section != section {
+            section = value.setting.section;
+            if index > 0 {
+                println!();
+            }
+            println!("[{}]", section);
+        }
+        println!("{:<width$}  # {}", line, source, width = width);
+    }
+}
+
+/// Settings asked for by `config init`: section, key and question.
+const INIT_QUESTIONS: &[(&str, &str, &str)] = &[
+    ("folder", "TMP_FOLDER_NAME", "Download folder"),
+    ("url", "ISO", "Catalog and download URL"),
+    ("url", "MIRRORS", "Mirrors tried in order when a download fails, separated by ';'"),
+    ("Download", "CONCURRENCY", "Games downloaded at the same time"),
+];
+
+/// Asks for the most important settings and writes a documented TOML config file with them
+/// (to `--config FILE` or the user config folder). An existing file supplies the suggested answers.
+fn run_config_init(file: Option<&Path>, output: &Output) -> Result<()> {
+    let path = file.map_or_else(Config::default_path, Path::to_path_buf);
+    if !path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+        return Err(Error::ConfigInvalid(format!("config init writes TOML; use a .toml file instead of {}", path.display())).into());
+    }
+    let mut settings = Settings::defaults();
+    if path.exists() {
+        settings.apply_file(&path)?;
+        let answer = ask(output, &format!("{} already exists. Overwrite it? [y/N]: ", path.display()))?;
+        if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+            output.message("Config file left unchanged");
+            return Ok(());
+        }
+    }
+    output.message(&format!("Writing {} (press Enter to keep the suggested value)\n", path.display()));
+
+    for &(section, key, question) in INIT_QUESTIONS {
+        let current = settings
+            .values()
+            .iter()
+            .find(|value| value.setting.section == section && value.setting.key == key)
+            .map(|value| value.value.clone())
+            .unwrap_or_default();
+        loop {
+            let answer = ask(output, &format!("{} [{}]: ", question, current))?;
+            let answer = if answer.is_empty() { current.as_str() } else { answer.as_str() };
+            match settings.assign(section, key, answer) {
+                Ok(()) => break,
+                Err(problem) => output.message(&problem.message),
+            }
+        }
+    }
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, settings.to_file())?;
+    output.message(&format!("\nWrote {}; 'ps2-redump-downloader config check' validates it after editing", path.display()));
+    Ok(())
+}
+
+/// Upgrades the config file (`--config FILE` or the one found) to the current layout.
+fn run_config_migrate(file: Option<&Path>, output: &Output) -> Result<()> {
+    let path = Config::locate(file)?;
+    let is_toml = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+    if is_toml && !config::needs_migration(&path) {
+        output.message(&format!("{} is already at config schema version {}", path.display(), config::SCHEMA_VERSION));
+        return Ok(());
+    }
+    // A file that is left alone has already been reported with a warning
+    let migrated = config::migrate_file(&path)?;
+    let left_alone = if is_toml { config::needs_migration(&path) } else { migrated == path };
+    if left_alone {
+        return Err(Error::ConfigInvalid(format!("{} was not migrated", path.display())).into());
+    }
+    Ok(())
+}
+
+/// Asks a question on stdin and returns the trimmed answer.
+fn ask(output: &Output, question: &str) -> Result<String> {
+    prompt(output, question)?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        anyhow::bail!("standard input closed before every question was answered");
+    }
+    Ok(answer.trim().to_string())
+}
+
+/// Reports every problem of the configuration (one JSON line per problem in JSON mode).
+fn check_settings(settings: &Settings, output: &Output) -> Result<()> {
+    let problems = settings.check();
+    for problem in &problems {
+        if output.is_json() {
+            if let Ok(line) = serde_json::to_string(problem) {
+                println!("{}", line);
+            }
+        } else {
+            println!("{}", problem);
+        }
+    }
+    match problems.len() {
+        0 => {
+            output.message("Configuration is valid.");
+            Ok(())
+        }
+        count => Err(Error::ConfigInvalid(format!("{} problem{} found", count, if count == 1 { "" } else { "s" })).into()),
+    }
+}
+
+/// Lists the indexed images (one JSON line per image in JSON mode).
+fn print_library(library: &Library, output: &Output) {
+    for entry in library.entries() {
+        if output.is_json() {
+            if let Ok(line) = serde_json::to_string(entry) {
+                println!("{}", line);
+            }
+        } else {
+            let serial = entry.serial.as_ref().map(|serial| format!(" [{}]", serial)).unwrap_or_default();
+            println!("{}{} ({}) - {}", entry.title, serial, format_size(entry.size), entry.path.display());
+        }
+    }
+}
+
+/// Places every library ISO with a known serial into the OPL folder.
+fn run_opl_export(config: &Config, library: &Library, output: &Output) -> Result<()> {
+    let opl = config
+        .opl_export()
+        .ok_or_else(|| Error::ConfigInvalid("no OPL folder configured ([opl] FOLDER)".to_string()))?;
+    let mut failed = 0;
+
+    for entry in library.entries().iter().filter(|entry| entry.format == ImageFormat::Iso) {
+        let disc = DiscInfo {
+            serial: entry.serial.clone(),
+            volume_label: entry.volume_label.clone().unwrap_or_default(),
+        };
+        let base_title = entry.title.split(" (").next().unwrap_or(&entry.title).trim();
+        match opl.export(&entry.path, &disc, base_title) {
+            Ok(exported) => {
+                output.emit(&Event::OplExport {
+                    title: entry.title.clone(),
+                    path: exported.path.display().to_string(),
+                    media: exported.media,
+                    truncated: exported.truncated,
+                });
+                let note = match (exported.already_present, exported.truncated) {
+                    (true, _) => " (already there)",
+                    (false, true) => " (title shortened)",
+                    (false, false) => "",
+                };
+                output.message(&format!("{} -> {}{}", entry.title, exported.path.display(), note));
+            }
+            Err(e) => {
+                failed += 1;
+                output.message(&format!("{}: {}", entry.title, e));
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::ExportFailed(format!("{} images could not be exported to {}", failed, opl.root().display())).into());
+    }
+    Ok(())
+}
+
+/// Applies a single patch given on the command line.
+async fn run_patch(args: PatchArgs, output: &Output) -> Result<()> {
+    let entry = PatchEntry {
+        title: String::new(),
+        patch: args.patch.clone(),
+        source_crc32: args.source_crc32,
+        name: None,
+    };
+    let target = args.output.unwrap_or_else(|| entry.output_path(&args.image));
+    let expected = entry.expected_crc32()?;
+    let title = args.image.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+
+    let total = std::fs::metadata(&args.image)?.len();
+    let template = "{spinner:.green} Patching:   [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+    let mut progress = output.progress(&title, Stage::Patch, Some(total), template);
+    let (image, patch_file, path) = (args.image, args.patch, target.clone());
+    let (result, mut progress) = tokio::task::spawn_blocking(move || {
+        let result = patch::apply(&image, &patch_file, &path, expected, &CancellationToken::new(), |bytes| {
+            progress.set_position(bytes)
+        });
+        (result, progress)
+    })
+    .await?;
+    let applied = result?;
+    progress.finish("Patch applied");
+
+    output.emit(&Event::Patched {
+        title,
+        patch: entry.patch.display().to_string(),
+        path: target.display().to_string(),
+        size: applied.size,
+    });
+    output.message(&format!("Applied {} patch -> {}", applied.format.name(), target.display()));
+    Ok(())
+}
+
+/// Resolves the titles to download, reports problems, then downloads everything through the queue.
+async fn run_download(
+    args: DownloadArgs,
+    scraper: &Scraper,
+    downloader: &Downloader,
+    games: &[Game],
+    library: &SharedLibrary,
+    output: &Output,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let mut queue = DownloadQueue::new().with_concurrency(downloader.config().download_concurrency);
+    if args.source.wishlist {
+        let wishlist = Wishlist::load(&downloader.config().wishlist_path())?;
+        let (found, missing) = wishlist.resolve(games);
+        let library = library.lock().unwrap();
+        let owned = found.iter().filter(|game| library.owns(game)).count();
+        queue.extend(found.into_iter().filter(|game| !library.owns(game)).cloned());
+        output.message(&format!(
+            "Wishlist: {} titles ({} already in the library)",
+            wishlist.entries().len(),
+            owned
+        ));
+        for title in &missing {
+            output.message(&format!("'{}' is not in the catalog", title));
+        }
+    }
+
+    if let Some(path) = &args.source.from_file {
+        let lines = batch::read_list(path)?;
+        output.message(&format!("Resolving {} entries from {}...", lines.len(), path.display()));
+        let entries = batch::resolve(scraper, games, lines).await;
+
+        let unresolved: Vec<_> = entries.iter().filter_map(|entry| entry.unresolved()).collect();
+        for entry in entries {
+            if let Resolution::Found(game) = entry.resolution {
+                queue.push(game);
+            }
+        }
+
+        output.emit(&Event::BatchResolved {
+            resolved: queue.len(),
+            unresolved: unresolved.clone(),
+        });
+        if !output.is_json() {
+            for entry in &unresolved {
+                print_unresolved(&format!("Line {}: '{}'", entry.line, entry.text), entry);
+            }
+        }
+        if !unresolved.is_empty() && !args.yes && output.is_json() {
+            anyhow::bail!("{} entries could not be resolved; fix the list or pass --yes to skip them", unresolved.len());
+        }
+    }
+
+    download_queue(queue, downloader, args.yes, output, cancel).await
+}
+
+/// Completes multi-disc sets and lists the queue, asks for confirmation (unless `yes` or JSON mode),
+/// then downloads it and removes the completed titles from the wishlist.
+async fn download_queue(
+    mut queue: DownloadQueue,
+    downloader: &Downloader,
+    yes: bool,
+    output: &Output,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    if queue.is_empty() {
+        output.message("Nothing to download");
+        return Ok(());
+    }
+    queue.complete_sets(downloader.disc_sets());
+
+    if !output.is_json() {
+        print_queue(&queue);
+        if !yes && !confirm("Start download? [Y/n]: ")? {
+            output.message("Download cancelled");
+            return Ok(());
+        }
+    }
+
+    let summary = queue.run(downloader, cancel).await?;
+    print_summary(&summary, output);
+    let wishlist_path = downloader.config().wishlist_path();
+    prune_wishlist(&mut Wishlist::load(&wishlist_path)?, &wishlist_path, &summary, output)?;
+    if !summary.failed.is_empty() {
+        anyhow::bail!("{} of {} downloads failed", summary.failed.len(), summary.failed.len() + summary.completed.len());
+    }
+    Ok(())
+}
+
+/// Prints why a list entry did not resolve, listing up to five candidates; `label` names the entry.
+fn print_unresolved(label: &str, entry: &UnresolvedEntry) {
+    if let Some(error) = &entry.error {
+        println!("{} could not be looked up on Redump: {}", label, error);
+    } else if entry.candidates.is_empty() {
+        println!("{} not found", label);
+    } else {
+        println!("{} is ambiguous ({} matches):", label, entry.candidates.len());
+        for candidate in entry.candidates.iter().take(5) {
+            println!("    {}", candidate);
+        }
+        if entry.candidates.len() > 5 {
+            println!("    ...");
+        }
+    }
+}
+
+/// Adds, removes or lists wishlist titles.
+async fn run_wishlist(
+    command: WishlistCommand,
+    scraper: &Scraper,
+    games: &[Game],
+    library: &SharedLibrary,
+    config: &Config,
+    output: &Output,
+) -> Result<()> {
+    let path = config.wishlist_path();
+    let mut wishlist = Wishlist::load(&path)?;
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut problems = 0;
+
+    let action = match command {
+        WishlistCommand::List => {
+            print_wishlist(&wishlist, games, &library.lock().unwrap(), output);
+            return Ok(());
+        }
+        WishlistCommand::Add { entries } => {
+            // Arguments resolve like the lines of a list file
+            let lines = entries.into_iter().enumerate().map(|(index, entry)| (index + 1, entry)).collect();
+            for entry in batch::resolve(scraper, games, lines).await {
+                match &entry.resolution {
+                    Resolution::Found(game) => {
+                        let title = game.clean_title();
+                        if wishlist.add(&title) {
+                            added.push(title);
+                        } else {
+                            output.message(&format!("'{}' is already on the wishlist", title));
+                        }
+                    }
+                    _ => {
+                        problems += 1;
+                        if let Some(unresolved) = entry.unresolved() {
+                            print_unresolved(&format!("'{}'", entry.text), &unresolved);
+                        }
+                    }
+                }
+            }
+            "added"
+        }
+        WishlistCommand::Remove { entries } => {
+            // Numbers refer to the list before anything is removed
+            let titles: Vec<String> = wishlist.entries().iter().map(|entry| entry.title.clone()).collect();
+            for entry in entries {
+                let title = match entry.parse::<usize>() {
+                    Ok(number) if (1..=titles.len()).contains(&number) => titles[number - 1].clone(),
+                    _ => entry,
+                };
+                if wishlist.remove(&title) {
+                    removed.push(title);
+                } else if !removed.iter().any(|done| done.eq_ignore_ascii_case(&title)) {
+                    problems += 1;
+                    output.message(&format!("'{}' is not on the wishlist", title));
+                }
+            }
+            "removed"
+        }
+    };
+
+    if !added.is_empty() || !removed.is_empty() {
+        wishlist.save(&path)?;
+    }
+    report_wishlist_change(&wishlist, added, removed, output);
+    if problems > 0 {
+        anyhow::bail!("{} entries could not be {}", problems, action);
+    }
+    Ok(())
+}
+
+/// Lists the wishlist with a number per title (one JSON line per title in JSON mode).
+fn print_wishlist(wishlist: &Wishlist, games: &[Game], library: &Library, output: &Output) {
+    if wishlist.entries().is_empty() && !output.is_json() {
+        output.message("The wishlist is empty");
+        return;
+    }
+    let (_, missing) = wishlist.resolve(games);
+    for (index, entry) in wishlist.entries().iter().enumerate() {
+        let owned = library.owns_title(&entry.title);
+        let in_catalog = !missing.contains(&entry.title);
+        if output.is_json() {
+            let line = serde_json::json!({
+                "title": entry.title,
+                "added": entry.added,
+                "owned": owned,
+                "in_catalog": in_catalog,
+            });
+            println!("{}", line);
+            continue;
+        }
+        let marker = match (owned, in_catalog) {
+            (true, _) => " [owned]",
+            (false, false) => " [not in catalog]",
+            (false, true) => "",
+        };
+        println!("{}. {}{}", index + 1, entry.title, marker);
+    }
+}
+
+/// Reports titles added to or removed from the wishlist.
+fn report_wishlist_change(wishlist: &Wishlist, added: Vec<String>, removed: Vec<String>, output: &Output) {
+    if added.is_empty() && removed.is_empty() {
+        return;
+    }
+    for title in &added {
+        output.message(&format!("Added '{}' to the wishlist", title));
+    }
+    for title in &removed {
+        output.message(&format!("Removed '{}' from the wishlist", title));
+    }
+    output.emit(&Event::WishlistChanged {
+        added,
+        removed,
+        titles: wishlist.entries().len(),
+    });
+}
+
+/// Removes the titles the queue completed from the wishlist.
+fn prune_wishlist(wishlist: &mut Wishlist, path: &Path, summary: &QueueSummary, output: &Output) -> Result<()> {
+    let removed: Vec<String> = summary
+        .completed
+        .iter()
+        .filter(|title| wishlist.remove(title))
+        .cloned()
+        .collect();
+    if !removed.is_empty() {
+        wishlist.save(path)?;
+        report_wishlist_change(wishlist, Vec::new(), removed, output);
+    }
+    Ok(())
+}
+
+/// Manages the saved searches, or refreshes the catalog and reports (and optionally downloads) new matches.
+async fn run_watch(
+    args: WatchArgs,
+    scraper: &Scraper,
+    downloader: &Downloader,
+    mut games: Vec<Game>,
+    library: &SharedLibrary,
+    output: &Output,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let path = downloader.config().saved_searches_path();
+    let mut searches = SavedSearches::load(&path)?;
+
+    match args.command {
+        Some(WatchCommand::List) => {
+            for (index, search) in searches.searches().iter().enumerate() {
+                if output.is_json() {
+                    if let Ok(line) = serde_json::to_string(search) {
+                        println!("{}", line);
+                    }
+                } else {
+                    println!("{}. {}", index + 1, search.query);
+                }
+            }
+            if searches.searches().is_empty() {
+                output.message("No saved searches; add one with 'watch add QUERY'");
+            }
+            return Ok(());
+        }
+        Some(WatchCommand::Add { queries }) => {
+            for query in queries {
+                if searches.add(&query, &games) {
+                    let matches = filter_games(&games, &query).len();
+                    output.message(&format!("Saved '{}' ({} titles match now)", query.trim(), matches));
+                } else {
+                    output.message(&format!("'{}' is empty or already saved", query.trim()));
+                }
+            }
+            searches.save(&path)?;
+            return Ok(());
+        }
+        Some(WatchCommand::Remove { queries }) => {
+            // Numbers refer to the list before anything is removed
+            let saved: Vec<String> = searches.searches().iter().map(|search| search.query.clone()).collect();
+            let mut missing = 0;
+            for query in queries {
+                let query = match query.parse::<usize>() {
+                    Ok(number) if (1..=saved.len()).contains(&number) => saved[number - 1].clone(),
+                    _ => query,
+                };
+                if searches.remove(&query) {
+                    output.message(&format!("Removed '{}'", query));
+                } else {
+                    missing += 1;
+                    output.message(&format!("'{}' is not a saved search", query));
+                }
+            }
+            searches.save(&path)?;
+            if missing > 0 {
+                anyhow::bail!("{} searches could not be removed", missing);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if searches.searches().is_empty() {
+        output.message("No saved searches; add one with 'watch add QUERY'");
+        return Ok(());
+    }
+    if !args.cached {
+        games = tokio::select! {
+            games = scraper.refresh_ps2_list() => games?,
+            _ = cancel.cancelled() => return Err(Error::Cancelled.into()),
+        };
+    }
+
+    // Each match is reported once: the snapshot is updated whether or not it gets downloaded
+    let reports = searches.check(&games);
+    searches.save(&path)?;
+    if reports.is_empty() {
+        output.message(&format!("No new dumps for {} saved searches", searches.searches().len()));
+        return Ok(());
+    }
+
+    let mut queue = DownloadQueue::new().with_concurrency(downloader.config().download_concurrency);
+    for report in &reports {
+        output.emit(&Event::WatchMatches {
+            query: report.query.clone(),
+            new: report.new.iter().map(Game::clean_title).collect(),
+            changed: report.changed.iter().map(|(game, _)| game.clean_title()).collect(),
+        });
+        output.message(&format!("'{}': {} new, {} changed", report.query, report.new.len(), report.changed.len()));
+        for game in &report.new {
+            output.message(&format!("  + {} ({})", game.clean_title(), game.size));
+        }
+        for (game, previous) in &report.changed {
+            output.message(&format!("  ~ {} ({}, was {})", game.clean_title(), game.size, previous));
+        }
+        let context = HookContext {
+            query: Some(report.query.clone()),
+            matches: Some(report.titles()),
+            ..HookContext::default()
+        };
+        downloader.run_hook(HookEvent::WatchMatch, context).await;
+
+        if args.download {
+            let library = library.lock().unwrap();
+            queue.extend(report.new.iter().filter(|game| !library.owns(game)).cloned());
+        }
+    }
+
+    if !args.download {
+        return Ok(());
+    }
+    download_queue(queue, downloader, args.yes, output, cancel).await
+}
+
+/// Picks one version of every game (1G1R), lists the picks and optionally saves or downloads them.
+async fn run_one_game(
+    args: OneGameArgs,
+    downloader: &Downloader,
+    games: &[Game],
+    library: &SharedLibrary,
+    output: &Output,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let candidates: Vec<Game> = match &args.query {
+        Some(query) => filter_games(games, query).into_iter().cloned().collect(),
+        None => games.to_vec(),
+    };
+    let picks = one_game::select(&candidates, &downloader.config().preferences());
+
+    let mut queue = DownloadQueue::new().with_concurrency(downloader.config().download_concurrency);
+    let mut owned_games = 0;
+    {
+        let library = library.lock().unwrap();
+        for pick in &picks {
+            let owned = pick.games.iter().all(|game| library.owns(game));
+            if owned {
+                owned_games += 1;
+            } else {
+                queue.extend(pick.games.iter().filter(|game| !library.owns(game)).map(|&game| game.clone()));
+            }
+            if output.is_json() {
+                let line = serde_json::json!({
+                    "base_title": pick.base_title,
+                    "titles": pick.games.iter().map(|game| game.clean_title()).collect::<Vec<_>>(),
+                    "owned": owned,
+                    "skipped": pick.skipped,
+                });
+                println!("{}", line);
+                continue;
+            }
+            let marker = if owned { " [owned]" } else { "" };
+            for game in &pick.games {
+                println!("{} ({}){}", game.clean_title(), game.size, marker);
+            }
+            if args.verbose {
+                for title in &pick.skipped {
+                    println!("    - {}", title);
+                }
+            }
+        }
+    }
+    let titles: usize = picks.iter().map(|pick| pick.games.len()).sum();
+    let (total, _) = queue.total_size();
+    output.message(&format!(
+        "\n{} games ({} titles) picked from {} titles; {} already in the library, {} to download",
+        picks.len(),
+        titles,
+        candidates.len(),
+        owned_games,
+        format_size(total)
+    ));
+
+    if let Some(path) = &args.output {
+        let list: String = picks
+            .iter()
+            .flat_map(|pick| pick.games.iter().map(|game| game.clean_title() + "\n"))
+            .collect();
+        std::fs::write(path, list)?;
+        output.message(&format!("Wrote {} titles to {}", titles, path.display()));
+    }
+
+    if !args.download {
+        return Ok(());
+    }
+    download_queue(queue, downloader, args.yes, output, cancel).await
+}
+
+/// Asks a yes/no question on stdin; an empty answer means yes.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{}", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "" | "y" | "yes"))
+}
+
 /// Main interactive loop for searching and downloading PS2 games.
 /// Uses async-compatible input/output for better performance.
 async fn run_main_loop(
     downloader: &Downloader,
     games: Vec<Game>,
+    library: &SharedLibrary,
+    wishlist: &mut Wishlist,
+    output: &Output,
+    controls: bool,
+    cancel: &CancellationToken,
 ) -> Result<()> {
+    let wishlist_path = downloader.config().wishlist_path();
     let stdin = io::stdin();
     let mut reader = BufReader::new(stdin);
     let mut input = String::new();
     loop {
-        print!("Find PS2 title to download (leave empty to exit): ");
-        std::io::stdout().flush()?;
-        input.clear();
-        reader.read_line(&mut input).await?;
-        let search_input = input.trim();
+        prompt(output, "Find PS2 title to download (leave empty to exit): ")?;
+        read_input(&mut reader, &mut input, cancel).await?;
+        let search_input = input.trim().to_string();
 
         if search_input.is_empty() {
-            println!("Exiting...");
+            output.message("Exiting...");
             break Ok(());
         }
 
-        let filtered_games = filter_games(&games, search_input);
+        let filtered_games = filter_games(&games, &search_input);
+        let owned: Vec<bool> = {
+            let library = library.lock().unwrap();
+            filtered_games.iter().map(|game| library.owns(game)).collect()
+        };
+        let wished: Vec<bool> = filtered_games.iter().map(|game| wishlist.contains(game)).collect();
+
+        output.emit(&Event::SearchResults {
+            query: search_input,
+            results: filtered_games
+                .iter()
+                .enumerate()
+                .map(|(index, game)| SearchResult {
+                    index: index + 1,
+                    title: game.title.clone(),
+                    size: game.size.clone(),
+                    owned: owned[index],
+                    wishlist: wished[index],
+                })
+                .collect(),
+        });
 
         if filtered_games.is_empty() {
-            println!("No elements found\n");
+            output.message("No elements found\n");
             continue;
         }
 
-        print_games(&filtered_games);
+        if !output.is_json() {
+            print_games(&filtered_games, &owned, &wished);
+        }
 
-        print!("Enter PS2 title number [1-{}]: ", filtered_games.len());
-        std::io::stdout().flush()?;
-        input.clear();
-        reader.read_line(&mut input).await?;
+        prompt(
+            output,
+            &format!(
+                "Enter PS2 title numbers, e.g. 1,3,5-8, all, !2 (+ or - first to add to or remove from the wishlist) [1-{}]: ",
+                filtered_games.len()
+            ),
+        )?;
+        read_input(&mut reader, &mut input, cancel).await?;
 
-        if let Ok(file_number) = input.trim().parse::<usize>() {
-            if file_number > 0 && file_number <= filtered_games.len() {
-                let selected_game = &filtered_games[file_number - 1];
-                downloader.download_ps2_element(selected_game).await?;
-            } else {
-                println!("Number not in valid range (1-{})\n", filtered_games.len());
+        // A leading '+' or '-' edits the wishlist instead of downloading
+        let trimmed = input.trim();
+        let (edit, numbers) = match trimmed.strip_prefix('+') {
+            Some(rest) => (Some(true), rest),
+            None => match trimmed.strip_prefix('-') {
+                Some(rest) => (Some(false), rest),
+                None => (None, trimmed),
+            },
+        };
+
+        let selection = match parse_selection(numbers, filtered_games.len()) {
+            Ok(selection) => selection,
+            Err(e) => {
+                output.message(&format!("{}\n", e));
                 tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                continue;
+            }
+        };
+
+        if let Some(add) = edit {
+            let mut added = Vec::new();
+            let mut removed = Vec::new();
+            for &i in &selection {
+                let title = filtered_games[i].clean_title();
+                if add && wishlist.add(&title) {
+                    added.push(title);
+                } else if !add && wishlist.remove(&title) {
+                    removed.push(title);
+                }
+            }
+            wishlist.save(&wishlist_path)?;
+            report_wishlist_change(wishlist, added, removed, output);
+            output.message("");
+            continue;
+        }
+
+        let mut queue = DownloadQueue::new().with_concurrency(downloader.config().download_concurrency);
+        queue.extend(selection.iter().map(|&i| filtered_games[i].clone()));
+        queue.complete_sets(downloader.disc_sets());
+
+        // Confirm before starting (JSON consumers select exactly what they want)
+        if !output.is_json() {
+            print_queue(&queue);
+            prompt(output, "Start download? [Y/n]: ")?;
+            read_input(&mut reader, &mut input, cancel).await?;
+            if !matches!(input.trim().to_lowercase().as_str(), "" | "y" | "yes") {
+                output.message("Download cancelled\n");
+                continue;
             }
         }
+
+        let summary = if controls {
+            run_queue_with_controls(downloader, &mut queue, &mut reader, output, cancel).await?
+        } else {
+            queue.run(downloader, cancel).await?
+        };
+        if queue_needs_summary(&summary) {
+            print_summary(&summary, output);
+        }
+        prune_wishlist(wishlist, &wishlist_path, &summary, output)?;
     }
 }
 
-/// Filters games by search string using the precomputed lowercased_title for efficiency.
-fn filter_games<'a>(games: &'a [Game], search: &str) -> Vec<&'a Game> {
-    let search_lower = search.to_lowercase();
-    let searches: Vec<&str> = search_lower.split_whitespace().collect();
+/// Runs the queue while listening for pause ("p") and resume ("r") commands on stdin.
+async fn run_queue_with_controls(
+    downloader: &Downloader,
+    queue: &mut DownloadQueue,
+    reader: &mut BufReader<io::Stdin>,
+    output: &Output,
+    cancel: &CancellationToken,
+) -> Result<QueueSummary> {
+    output.message("Type 'p' + Enter to pause, 'r' + Enter to resume, Ctrl-C to cancel");
+    let download = queue.run(downloader, cancel);
+    tokio::pin!(download);
 
-    games
-        .iter()
-        .filter(|game| {
-            searches.iter().all(|search| game.lowercased_title.contains(search))
-        })
-        .collect()
+    let mut command = String::new();
+    loop {
+        command.clear();
+        tokio::select! {
+            result = &mut download => return Ok(result?),
+            read = reader.read_line(&mut command) => {
+                if read? == 0 {
+                    // stdin closed: just wait for the queue to finish
+                    return Ok(download.await?);
+                }
+                match command.trim() {
+                    "p" => downloader.pause(),
+                    "r" => downloader.resume(),
+                    _ => {}
+                }
+            }
+        }
+    }
 }
 
-/// Displays the list of filtered games with their titles and sizes.
-fn print_games(games: &[&Game]) {
+/// Reports how many queued titles completed and which ones failed.
+fn print_summary(summary: &QueueSummary, output: &Output) {
+    output.message(&format!(
+        "\nCompleted {} of {} titles",
+        summary.completed.len(),
+        summary.completed.len() + summary.failed.len()
+    ));
+    for (title, error) in &summary.failed {
+        output.message(&format!(" - {} failed: {}", title, error));
+    }
+    output.message("");
+}
+
+/// Returns true when the queue had more than one title or something failed.
+fn queue_needs_summary(summary: &QueueSummary) -> bool {
+    summary.completed.len() + summary.failed.len() > 1 || !summary.failed.is_empty()
+}
+
+/// Reads a line of input into `input`, giving up when the operation is cancelled.
+async fn read_input(
+    reader: &mut BufReader<io::Stdin>,
+    input: &mut String,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    input.clear();
+    tokio::select! {
+        read = reader.read_line(input) => {
+            read?;
+        }
+        _ = cancel.cancelled() => return Err(Error::Cancelled.into()),
+    }
+    Ok(())
+}
+
+/// Prints a prompt without a trailing newline (to stderr in JSON mode).
+fn prompt(output: &Output, text: &str) -> Result<()> {
+    if output.is_json() {
+        eprint!("{}", text);
+        std::io::stderr().flush()?;
+    } else {
+        print!("{}", text);
+        std::io::stdout().flush()?;
+    }
+    Ok(())
+}
+
+/// Displays the list of filtered games with their titles and sizes, marking owned and wishlist ones.
+fn print_games(games: &[&Game], owned: &[bool], wished: &[bool]) {
     for (index, game) in games.iter().enumerate() {
-        println!("{}. {} ({})", index + 1, game.title, game.size);
+        let owned = if owned[index] { " [owned]" } else { "" };
+        let wished = if wished[index] { " [wishlist]" } else { "" };
+        println!("{}. {} ({}){}{}", index + 1, game.title, game.size, owned, wished);
     }
     println!();
 }
+
+/// Displays the queued games and their total download size.
+fn print_queue(queue: &DownloadQueue) {
+    println!("Selected {} titles:", queue.len());
+    for game in queue.games() {
+        println!(" - {} ({})", game.clean_title(), game.size);
+    }
+    let (total, unknown) = queue.total_size();
+    if unknown > 0 {
+        println!("Total download size: {} (+{} of unknown size)", format_size(total), unknown);
+    } else {
+        println!("Total download size: {}", format_size(total));
+    }
+}
@@ -20,6 +20,65 @@ impl Game {
         self.title.replace(".zip", "")
     }
 
+    /// Returns the title without extension and parenthesized tags (e.g. 'Final Fantasy X').
+    pub fn base_title(&self) -> String {
+        let title = self.clean_title();
+        match title.find(" (") {
+            Some(end) => title[..end].trim().to_string(),
+            None => title.trim().to_string(),
+        }
+    }
+
+    /// Returns the disc number from a '(Disc N)' tag, for titles spanning several discs.
+    pub fn disc(&self) -> Option<u32> {
+        let title = self.clean_title();
+        let start = title.find("(Disc ")? + "(Disc ".len();
+        let end = title[start..].find(')')? + start;
+        title[start..end].trim().parse().ok()
+    }
+
+    /// Returns the clean title without its '(Disc N)' tag, shared by every disc of a set
+    /// (e.g. 'Xenosaga Episode II (USA)').
+    pub fn set_title(&self) -> String {
+        let title = self.clean_title();
+        match self.disc() {
+            Some(disc) => title.replace(&format!(" (Disc {})", disc), ""),
+            None => title,
+        }
+    }
+
+    /// Returns the region from the first parenthesized group of the title (e.g. 'USA', 'Europe, Australia').
+    pub fn region(&self) -> Option<String> {
+        let title = self.clean_title();
+        let start = title.find('(')?;
+        let end = title[start..].find(')')? + start;
+        Some(title[start + 1..end].to_string())
+    }
+
+    /// Parses the displayed size (e.g. '2.1 GiB', '700 MB') into bytes.
+    /// Returns None if the size is missing or not understood.
+    pub fn size_bytes(&self) -> Option<u64> {
+        let size = self.size.trim();
+        let split = size
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(size.len());
+        let (number, unit) = size.split_at(split);
+        let number: f64 = number.parse().ok()?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            "t" | "tib" => 1 << 40,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            "tb" => 1_000_000_000_000,
+            _ => return None,
+        };
+        Some((number * multiplier as f64) as u64)
+    }
+
     /// Creates a new Game with lowercased_title initialized
     pub fn with_lowercased(mut self) -> Self {
         self.lowercased_title = self.title.to_lowercase();
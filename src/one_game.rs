@@ -0,0 +1,188 @@
+use crate::models::Game;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Preferences for 1G1R (one game, one ROM) selection, from the `[1g1r]` section.
+#[derive(Debug, Clone, Default)]
+pub struct Preferences {
+    /// Regions in order of preference, e.g. 'USA', 'Europe'
+    pub regions: Vec<String>,
+    /// Language codes in order of preference, e.g. 'En', 'Fr'
+    pub languages: Vec<String>,
+    /// Tags that rule a version out, e.g. 'Demo' (also matches 'Kiosk Demo')
+    pub exclude: Vec<String>,
+}
+
+/// Parenthesized tags of a Redump title, e.g. 'Gran Turismo 4 (Europe) (En,Fr,De) (Rev 1)'.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tags {
+    /// Regions from the first tag, e.g. ['Europe', 'Australia']
+    pub regions: Vec<String>,
+    /// Language codes, when the title lists them
+    pub languages: Vec<String>,
+    /// Revision number from a '(Rev N)' tag (0 for the original release)
+    pub revision: u32,
+    /// Disc number from a '(Disc N)' tag
+    pub disc: Option<u32>,
+    /// Every other tag, e.g. 'Demo', 'Greatest Hits'
+    pub other: Vec<String>,
+}
+
+impl Tags {
+    /// Parses the tags of a clean title.
+    pub fn parse(title: &str) -> Self {
+        let mut tags = Tags::default();
+        let mut rest = title;
+        let mut first = true;
+        while let Some(start) = rest.find('(') {
+            let Some(length) = rest[start..].find(')') else { break };
+            let tag = rest[start + 1..start + length].trim();
+            rest = &rest[start + length + 1..];
+
+            if first {
+                first = false;
+                tags.regions = tag.split(',').map(|region| region.trim().to_string()).collect();
+            } else if let Some(revision) = tag.strip_prefix("Rev ").and_then(|n| n.trim().parse().ok()) {
+                tags.revision = revision;
+            } else if let Some(disc) = tag.strip_prefix("Disc ").and_then(|n| n.trim().parse().ok()) {
+                tags.disc = Some(disc);
+            } else if tag.split(',').all(is_language_code) {
+                tags.languages = tag.split(',').map(|code| code.trim().to_string()).collect();
+            } else {
+                tags.other.push(tag.to_string());
+            }
+        }
+        tags
+    }
+
+    /// Languages listed in the title, or those implied by its regions when none are listed.
+    pub fn effective_languages(&self) -> Vec<String> {
+        if !self.languages.is_empty() {
+            return self.languages.clone();
+        }
+        let mut languages: Vec<String> = Vec::new();
+        for region in &self.regions {
+            if let Some(language) = implied_language(region) {
+                if !languages.iter().any(|known| known == language) {
+                    languages.push(language.to_string());
+                }
+            }
+        }
+        languages
+    }
+
+    /// Returns true if one of the other tags contains an excluded word (case-insensitive).
+    pub fn is_excluded(&self, exclude: &[String]) -> bool {
+        self.other.iter().any(|tag| {
+            tag.split_whitespace()
+                .any(|word| exclude.iter().any(|excluded| excluded.eq_ignore_ascii_case(word)))
+        })
+    }
+}
+
+/// The version picked for one game.
+#[derive(Debug, Clone)]
+pub struct Pick<'a> {
+    /// Title without tags shared by every version, e.g. 'Final Fantasy X'
+    pub base_title: String,
+    /// Discs of the picked version (a single game unless the title spans several discs)
+    pub games: Vec<&'a Game>,
+    /// Clean titles of the versions passed over, excluded ones included
+    pub skipped: Vec<String>,
+}
+
+/// A version of a game: its title without the disc tag and its discs.
+type Version<'a> = (String, Vec<&'a Game>);
+
+/// Groups `games` by base title and picks the best version of each, in catalog order.
+/// Versions are ranked by region, then language, then latest revision; excluded versions are never picked,
+/// so games with only excluded versions are left out. Every disc of the picked version is kept.
+pub fn select<'a>(games: &'a [Game], preferences: &Preferences) -> Vec<Pick<'a>> {
+    // Base title -> versions (title without the disc tag) -> discs
+    let mut groups: Vec<(String, Vec<Version<'a>>)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for game in games {
+        let base_title = game.base_title();
+        let position = *positions.entry(base_title.to_lowercase()).or_insert_with(|| {
+            groups.push((base_title, Vec::new()));
+            groups.len() - 1
+        });
+        let version = game.set_title();
+        let versions = &mut groups[position].1;
+        match versions.iter_mut().find(|(title, _)| *title == version) {
+            Some((_, discs)) => discs.push(game),
+            None => versions.push((version, vec![game])),
+        }
+    }
+
+    let mut picks = Vec::new();
+    for (base_title, versions) in groups {
+        let best = versions
+            .iter()
+            .enumerate()
+            .map(|(index, (title, _))| (index, Tags::parse(title)))
+            .filter(|(_, tags)| !tags.is_excluded(&preferences.exclude))
+            .min_by_key(|(index, tags)| {
+                (
+                    rank(&tags.regions, &preferences.regions),
+                    rank(&tags.effective_languages(), &preferences.languages),
+                    Reverse(tags.revision),
+                    tags.other.len(),
+                    *index,
+                )
+            })
+            .map(|(index, _)| index);
+        let Some(best) = best else { continue };
+
+        let mut skipped = Vec::new();
+        let mut picked = Vec::new();
+        for (index, (_, mut discs)) in versions.into_iter().enumerate() {
+            if index == best {
+                discs.sort_by_key(|game| game.disc());
+                picked = discs;
+            } else {
+                skipped.extend(discs.iter().map(|game| game.clean_title()));
+            }
+        }
+        picks.push(Pick { base_title, games: picked, skipped });
+    }
+    picks
+}
+
+/// Position of the most preferred entry of `values` in `preferences`; unlisted values rank last.
+fn rank(values: &[String], preferences: &[String]) -> usize {
+    values
+        .iter()
+        .filter_map(|value| preferences.iter().position(|preferred| preferred.eq_ignore_ascii_case(value)))
+        .min()
+        .unwrap_or(preferences.len())
+}
+
+/// Two-letter language codes as Redump writes them, e.g. 'En' or 'Pt'.
+fn is_language_code(code: &str) -> bool {
+    let mut chars = code.trim().chars();
+    matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(first), Some(second), None) if first.is_ascii_uppercase() && second.is_ascii_lowercase()
+    )
+}
+
+/// Language of a region's releases when the title does not list any.
+fn implied_language(region: &str) -> Option<&'static str> {
+    match region {
+        "USA" | "Europe" | "World" | "UK" | "Australia" | "Canada" | "Asia" => Some("En"),
+        "Japan" => Some("Ja"),
+        "Korea" => Some("Ko"),
+        "China" | "Taiwan" | "Hong Kong" => Some("Zh"),
+        "Germany" | "Austria" => Some("De"),
+        "France" => Some("Fr"),
+        "Spain" => Some("Es"),
+        "Italy" => Some("It"),
+        "Netherlands" => Some("Nl"),
+        "Sweden" => Some("Sv"),
+        "Brazil" | "Portugal" => Some("Pt"),
+        "Russia" => Some("Ru"),
+        "Poland" => Some("Pl"),
+        _ => None,
+    }
+}
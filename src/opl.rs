@@ -0,0 +1,148 @@
+use crate::error::{Error, Result};
+use crate::iso9660::DiscInfo;
+use crate::library::ImageFormat;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Longest game title OPL displays and accepts in file names.
+pub const MAX_TITLE_LENGTH: usize = 32;
+/// Largest image that still fits on a CD (360000 sectors of 2048 bytes).
+const MAX_CD_SIZE: u64 = 360_000 * 2048;
+
+/// Folder of the OPL tree an image goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Media {
+    /// `CD/`
+    Cd,
+    /// `DVD/`
+    Dvd,
+}
+
+impl Media {
+    /// Picks the media type of an image. Redump dumps PS2 DVDs as a single ISO and CDs as BIN/CUE,
+    /// so the format decides; the size only decides for images of another format.
+    pub fn of_image(path: &Path, size: u64) -> Self {
+        match ImageFormat::from_path(path) {
+            Some(ImageFormat::Iso) => Media::Dvd,
+            Some(ImageFormat::Bin | ImageFormat::Cue) => Media::Cd,
+            _ => Media::from_size(size),
+        }
+    }
+
+    /// Picks the media type from the image size.
+    fn from_size(size: u64) -> Self {
+        if size <= MAX_CD_SIZE {
+            Media::Cd
+        } else {
+            Media::Dvd
+        }
+    }
+
+    /// Name of the folder in the OPL tree.
+    pub fn folder(self) -> &'static str {
+        match self {
+            Media::Cd => "CD",
+            Media::Dvd => "DVD",
+        }
+    }
+}
+
+/// How images are placed into the OPL tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceMode {
+    /// Copy the image
+    Copy,
+    /// Hardlink the image, falling back to a copy across filesystems
+    Hardlink,
+}
+
+/// An image placed into the OPL tree.
+#[derive(Debug, Clone)]
+pub struct Exported {
+    /// Path of the image inside the OPL tree
+    pub path: PathBuf,
+    /// Folder the image was placed in
+    pub media: Media,
+    /// Whether the title was shortened to fit OPL's limit
+    pub truncated: bool,
+    /// Whether an identical file was already there
+    pub already_present: bool,
+}
+
+/// Places ISOs into an Open PS2 Loader directory tree (`CD/` and `DVD/`, `SLUS_123.45.Title.iso`).
+#[derive(Debug, Clone)]
+pub struct OplExport {
+    root: PathBuf,
+    mode: PlaceMode,
+}
+
+impl OplExport {
+    /// Creates an exporter for the OPL tree at `root`.
+    pub fn new(root: PathBuf, mode: PlaceMode) -> Self {
+        Self { root, mode }
+    }
+
+    /// Root of the OPL tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Places `iso` into the tree, named after its serial and `title`.
+    /// An existing file with the same name and size is left untouched.
+    pub fn export(&self, iso: &Path, disc: &DiscInfo, title: &str) -> Result<Exported> {
+        let serial = disc
+            .serial
+            .as_ref()
+            .ok_or_else(|| Error::ExportFailed(format!("no serial found in {}", iso.display())))?;
+        let size = fs::metadata(iso)?.len();
+        let media = Media::of_image(iso, size);
+        let (title, truncated) = opl_title(title);
+
+        let folder = self.root.join(media.folder());
+        fs::create_dir_all(&folder)?;
+        let target = folder.join(format!("{}.{}.iso", serial, title));
+
+        if fs::metadata(&target).is_ok_and(|metadata| metadata.len() == size) {
+            return Ok(Exported {
+                path: target,
+                media,
+                truncated,
+                already_present: true,
+            });
+        }
+        if target.exists() {
+            fs::remove_file(&target)?;
+        }
+
+        match self.mode {
+            PlaceMode::Hardlink if fs::hard_link(iso, &target).is_ok() => {}
+            _ => {
+                fs::copy(iso, &target)?;
+            }
+        }
+
+        Ok(Exported {
+            path: target,
+            media,
+            truncated,
+            already_present: false,
+        })
+    }
+}
+
+/// Makes a title usable by OPL: characters FAT32 rejects are removed
+/// and the title is cut to `MAX_TITLE_LENGTH` characters. Returns the title and whether it was cut.
+pub fn opl_title(title: &str) -> (String, bool) {
+    let cleaned: String = title
+        .chars()
+        .filter(|c| !matches!(c, '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|') && !c.is_control())
+        .collect();
+    let cleaned = cleaned.trim();
+    if cleaned.chars().count() <= MAX_TITLE_LENGTH {
+        return (cleaned.to_string(), false);
+    }
+    let cut: String = cleaned.chars().take(MAX_TITLE_LENGTH).collect();
+    (cut.trim_end().to_string(), true)
+}
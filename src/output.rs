@@ -0,0 +1,452 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use crate::hooks::HookEvent;
+use crate::opl::Media;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::LazyLock;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Progress bars of the downloads running at the same time, drawn below the messages.
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Selects how the application reports progress and results.
+#[derive(Debug, Clone, Default)]
+pub enum Output {
+    /// Human-readable text with progress bars
+    #[default]
+    Text,
+    /// One JSON object per line on stdout (see `Event`)
+    Json,
+    /// Events (including messages) are sent to a channel and nothing is printed
+    Channel(UnboundedSender<Event>),
+}
+
+/// Machine-readable events emitted in JSON mode, one per line.
+/// Every line carries an `event` field naming the variant in snake_case.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The game catalog was loaded
+    Catalog {
+        /// Where the catalog came from
+        source: CatalogSource,
+        /// Path of the cached JSON list
+        path: String,
+        /// Number of titles in the catalog
+        titles: usize,
+    },
+    /// Results of a search query
+    SearchResults {
+        /// The query as entered by the user
+        query: String,
+        /// Matching games, numbered from 1
+        results: Vec<SearchResult>,
+    },
+    /// Lines of a list file matched against the catalog (`download --from-file`)
+    BatchResolved {
+        /// Number of lines that matched exactly one game
+        resolved: usize,
+        /// Lines that matched no game or several games, or whose serial lookup failed
+        unresolved: Vec<UnresolvedEntry>,
+    },
+    /// Titles were added to or removed from the wishlist
+    WishlistChanged {
+        /// Clean titles added
+        added: Vec<String>,
+        /// Clean titles removed
+        removed: Vec<String>,
+        /// Number of titles on the wishlist afterwards
+        titles: usize,
+    },
+    /// A saved search matched new or changed dumps (`watch`)
+    WatchMatches {
+        /// The saved search
+        query: String,
+        /// Clean titles not matched by the previous check
+        new: Vec<String>,
+        /// Clean titles whose size changed since the previous check
+        changed: Vec<String>,
+    },
+    /// Progress of a download or extraction
+    Progress {
+        /// Clean title of the game
+        title: String,
+        /// Current stage
+        stage: Stage,
+        /// Bytes processed so far
+        bytes: u64,
+        /// Total bytes, if known
+        total: Option<u64>,
+    },
+    /// Final result of a download
+    DownloadResult {
+        /// Clean title of the game
+        title: String,
+        /// Outcome of the download
+        status: DownloadStatus,
+        /// Path of the extracted ISO, if any
+        path: Option<String>,
+        /// CRC32 of the extracted ISO (hex), when it was extracted in this run
+        crc32: Option<String>,
+        /// Boot file serial read from the ISO, e.g. 'SLUS_209.46'
+        serial: Option<String>,
+        /// Volume label of the ISO
+        volume_label: Option<String>,
+        /// Error message when the download failed
+        error: Option<String>,
+    },
+    /// An ISO was placed into the OPL folder
+    OplExport {
+        /// Clean title of the game
+        title: String,
+        /// Path of the image inside the OPL folder
+        path: String,
+        /// OPL folder the image went to
+        media: Media,
+        /// Whether the title was shortened to OPL's 32-character limit
+        truncated: bool,
+    },
+    /// A patch was applied to a downloaded ISO
+    Patched {
+        /// Clean title of the game
+        title: String,
+        /// Patch file that was applied
+        patch: String,
+        /// Path of the patched image
+        path: String,
+        /// Size of the patched image in bytes
+        size: u64,
+    },
+    /// A hook command finished, failed or timed out
+    Hook {
+        /// Hook that ran
+        hook: HookEvent,
+        /// Command line that was run
+        command: String,
+        /// Exit code, or null if the command was killed or could not be started
+        exit_code: Option<i32>,
+        /// Whether the command was killed for exceeding the timeout
+        timed_out: bool,
+        /// Captured standard output
+        stdout: String,
+        /// Captured standard error
+        stderr: String,
+    },
+    /// A human-readable status message (only sent in channel mode)
+    Message {
+        /// Message text
+        text: String,
+    },
+    /// A fatal error that ended the current operation
+    Error {
+        /// Error message
+        message: String,
+    },
+}
+
+/// Where the catalog was loaded from.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogSource {
+    /// Loaded from the cached JSON list
+    Cache,
+    /// Scraped from the configured URL
+    Remote,
+}
+
+/// A single entry of a search result.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    /// 1-based position, as used when selecting a title
+    pub index: usize,
+    /// Title of the game
+    pub title: String,
+    /// Size as displayed by the source
+    pub size: String,
+    /// Whether the game is already in the library
+    pub owned: bool,
+    /// Whether the game is on the wishlist
+    pub wishlist: bool,
+}
+
+/// A list file line that did not match exactly one game.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnresolvedEntry {
+    /// 1-based line number in the list file
+    pub line: usize,
+    /// The line as written
+    pub text: String,
+    /// Candidate titles (empty when nothing matched)
+    pub candidates: Vec<String>,
+    /// Why a serial could not be looked up on Redump, when that failed
+    pub error: Option<String>,
+}
+
+/// Stage reported by progress events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Downloading the ZIP archive
+    Download,
+    /// Extracting the ZIP archive
+    Extract,
+    /// Compressing the ISO to CSO/ZSO
+    Compress,
+    /// Checking the compressed image against the ISO
+    Verify,
+    /// Applying a patch to the ISO
+    Patch,
+}
+
+/// Outcome of a download.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadStatus {
+    /// The game was downloaded and extracted
+    Downloaded,
+    /// The ISO was already present and nothing was downloaded
+    AlreadyPresent,
+    /// The download failed
+    Failed,
+    /// The download was cancelled; partial files are kept for resuming
+    Cancelled,
+}
+
+impl Output {
+    /// Returns true when emitting JSON lines.
+    pub fn is_json(&self) -> bool {
+        matches!(self, Output::Json)
+    }
+
+    /// Returns true when the user is reading plain text on a terminal.
+    pub fn is_text(&self) -> bool {
+        matches!(self, Output::Text)
+    }
+
+    /// Prints a human-readable message. In JSON mode it goes to stderr so stdout stays parseable.
+    pub fn message(&self, text: &str) {
+        match self {
+            Output::Text => BARS.suspend(|| println!("{}", text)),
+            Output::Json => eprintln!("{}", text),
+            Output::Channel(sender) => {
+                let text = text.trim();
+                if !text.is_empty() {
+                    let _ = sender.send(Event::Message { text: text.to_string() });
+                }
+            }
+        }
+    }
+
+    /// Emits an event as a JSON line or on the channel. Does nothing in text mode.
+    pub fn emit(&self, event: &Event) {
+        match self {
+            Output::Text => {}
+            Output::Json => {
+                if let Ok(line) = serde_json::to_string(event) {
+                    let mut stdout = std::io::stdout().lock();
+                    let _ = writeln!(stdout, "{}", line);
+                    let _ = stdout.flush();
+                }
+            }
+            Output::Channel(sender) => {
+                let _ = sender.send(event.clone());
+            }
+        }
+    }
+
+    /// Creates a progress reporter: a progress bar in text mode, throttled progress events otherwise.
+    /// `template` is the indicatif bar template used in text mode.
+    pub fn progress(&self, title: &str, stage: Stage, total: Option<u64>, template: &str) -> Progress {
+        match self {
+            Output::Text => {
+                let bar = match total {
+                    Some(total) => {
+                        let pb = ProgressBar::new(total);
+                        pb.set_style(
+                            ProgressStyle::default_bar()
+                                .template(template)
+                                .unwrap()
+                                .progress_chars("#>-"),
+                        );
+                        pb
+                    }
+                    None => {
+                        let spinner = ProgressBar::new_spinner();
+                        spinner.set_style(
+                            ProgressStyle::default_spinner()
+                                .template("{spinner:.green} {bytes} {elapsed_precise}")
+                                .unwrap()
+                                .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏"),
+                        );
+                        spinner
+                    }
+                };
+                Progress {
+                    output: self.clone(),
+                    title: title.to_string(),
+                    stage,
+                    total,
+                    position: 0,
+                    last_emitted: None,
+                    bar: Some(BARS.add(bar)),
+                }
+            }
+            Output::Json | Output::Channel(_) => Progress {
+                output: self.clone(),
+                title: title.to_string(),
+                stage,
+                total,
+                position: 0,
+                last_emitted: None,
+                bar: None,
+            },
+        }
+    }
+}
+
+/// Bytes between progress events when the total size is unknown.
+const JSON_PROGRESS_STEP: u64 = 8 * 1024 * 1024;
+
+/// Reports progress of a single stage either as a progress bar or as JSON events.
+pub struct Progress {
+    output: Output,
+    title: String,
+    stage: Stage,
+    total: Option<u64>,
+    position: u64,
+    last_emitted: Option<u64>,
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// Sets the current position.
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+        if let Some(bar) = &self.bar {
+            bar.set_position(position);
+        }
+        self.maybe_emit();
+    }
+
+    /// Advances the current position.
+    pub fn inc(&mut self, delta: u64) {
+        self.set_position(self.position + delta);
+    }
+
+    /// Returns the current position.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Finishes the progress bar with a message and emits a final progress event.
+    pub fn finish(&mut self, message: &str) {
+        if let Some(bar) = &self.bar {
+            bar.finish_with_message(message.to_string());
+        }
+        if self.last_emitted != Some(self.position) {
+            self.emit();
+        }
+    }
+
+    /// Emits a progress event when at least one percent (or a fixed step) has passed.
+    fn maybe_emit(&mut self) {
+        if self.output.is_text() {
+            return;
+        }
+        let step = match self.total {
+            Some(total) => (total / 100).max(1),
+            None => JSON_PROGRESS_STEP,
+        };
+        let due = match self.last_emitted {
+            Some(last) => self.position >= last + step,
+            None => true,
+        };
+        if due {
+            self.emit();
+        }
+    }
+
+    fn emit(&mut self) {
+        self.last_emitted = Some(self.position);
+        self.output.emit(&Event::Progress {
+            title: self.title.clone(),
+            stage: self.stage,
+            bytes: self.position,
+            total: self.total,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn events_are_tagged_in_snake_case() {
+        let event = Event::DownloadResult {
+            title: "Alpha Game (USA)".to_string(),
+            status: DownloadStatus::AlreadyPresent,
+            path: None,
+            crc32: Some("0badf00d".to_string()),
+            serial: None,
+            volume_label: None,
+            error: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "event": "download_result",
+                "title": "Alpha Game (USA)",
+                "status": "already_present",
+                "path": null,
+                "crc32": "0badf00d",
+                "serial": null,
+                "volume_label": null,
+                "error": null,
+            })
+        );
+
+        let event = Event::Progress { title: "Alpha".to_string(), stage: Stage::Extract, bytes: 3, total: None };
+        let line = serde_json::to_string(&event).unwrap();
+        assert!(!line.contains('\n'), "one event per line");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+            json!({"event": "progress", "title": "Alpha", "stage": "extract", "bytes": 3, "total": null})
+        );
+    }
+
+    #[test]
+    fn channel_messages_are_trimmed_and_blank_ones_dropped() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let output = Output::Channel(sender);
+        output.message("\n");
+        output.message("\nSelected Alpha\n");
+        match receiver.try_recv().unwrap() {
+            Event::Message { text } => assert_eq!(text, "Selected Alpha"),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn progress_events_are_throttled_to_one_percent() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let output = Output::Channel(sender);
+        let mut progress = output.progress("Alpha", Stage::Download, Some(1000), "");
+        for _ in 0..100 {
+            progress.inc(5);
+        }
+        progress.finish("done");
+
+        let mut positions = Vec::new();
+        while let Ok(Event::Progress { bytes, total, .. }) = receiver.try_recv() {
+            assert_eq!(total, Some(1000));
+            positions.push(bytes);
+        }
+        // The first update, then every 10 bytes (5, 15 .. 495), then the final position on finish
+        assert_eq!(positions.first(), Some(&5));
+        assert_eq!(positions.len(), 51);
+        assert_eq!(positions.last(), Some(&500));
+    }
+}
@@ -0,0 +1,702 @@
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+/// Name of the manifest in the patch folder.
+pub const MANIFEST_NAME: &str = "patches.json";
+/// Extensions of patch files picked up next to the manifest.
+const PATCH_EXTENSIONS: [&str; 5] = ["ips", "ppf", "xdelta", "xdelta3", "vcdiff"];
+/// Offset of the block PPF files use to check they match the image (BIN images).
+const PPF_BLOCK_OFFSET_BIN: u64 = 0x9320;
+/// Offset of the check block for GI images (PPF 3.0 only).
+const PPF_BLOCK_OFFSET_GI: u64 = 0x80A0;
+/// Size of the PPF check block.
+const PPF_BLOCK_SIZE: usize = 1024;
+/// Bytes copied per chunk when duplicating the source.
+const COPY_CHUNK: usize = 1 << 20;
+
+/// Supported patch formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    /// International Patching System
+    Ips,
+    /// PlayStation Patch Format 1.0, 2.0 or 3.0
+    Ppf,
+    /// VCDIFF (RFC 3284), as written by xdelta3
+    Vcdiff,
+}
+
+impl PatchFormat {
+    /// Detects the format from the first bytes of a patch.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"PATCH") {
+            Some(PatchFormat::Ips)
+        } else if data.starts_with(b"PPF") {
+            Some(PatchFormat::Ppf)
+        } else if data.starts_with(&[0xD6, 0xC3, 0xC4, 0x00]) {
+            Some(PatchFormat::Vcdiff)
+        } else {
+            None
+        }
+    }
+
+    /// Short name of the format.
+    pub fn name(self) -> &'static str {
+        match self {
+            PatchFormat::Ips => "IPS",
+            PatchFormat::Ppf => "PPF",
+            PatchFormat::Vcdiff => "VCDIFF",
+        }
+    }
+}
+
+/// A patch mapped to a title.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchEntry {
+    /// Clean title of the game, e.g. 'Final Fantasy XII (USA)'
+    pub title: String,
+    /// Patch file, relative to the patch folder
+    pub patch: PathBuf,
+    /// CRC32 (hex) the ISO must have before patching, if known
+    #[serde(default)]
+    pub source_crc32: Option<String>,
+    /// Label added to the output file name; defaults to the patch file name
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl PatchEntry {
+    /// Label used in the output file name.
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.patch
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Path of the patched image written next to `iso`: '<title> [<label>].iso'.
+    pub fn output_path(&self, iso: &Path) -> PathBuf {
+        let stem = iso.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        iso.with_file_name(format!("{} [{}].iso", stem, self.label()))
+    }
+
+    /// Parses the expected source checksum.
+    pub fn expected_crc32(&self) -> Result<Option<u32>> {
+        self.source_crc32
+            .as_deref()
+            .map(|crc| {
+                u32::from_str_radix(crc.trim(), 16)
+                    .map_err(|_| Error::PatchFailed(format!("invalid source_crc32 '{}' for {}", crc, self.title)))
+            })
+            .transpose()
+    }
+}
+
+/// Lists the patches for `title` in the patch folder: entries of `patches.json`, plus
+/// sidecar patch files named after the title (e.g. 'Final Fantasy XII (USA).xdelta').
+pub fn patches_for(folder: &Path, title: &str) -> Result<Vec<PatchEntry>> {
+    let manifest = folder.join(MANIFEST_NAME);
+    let mut entries: Vec<PatchEntry> = if manifest.exists() {
+        let content = fs::read_to_string(&manifest)?;
+        serde_json::from_str::<Vec<PatchEntry>>(&content)?
+            .into_iter()
+            .filter(|entry| entry.title.eq_ignore_ascii_case(title))
+            .map(|entry| PatchEntry {
+                patch: folder.join(&entry.patch),
+                ..entry
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    for extension in PATCH_EXTENSIONS {
+        let sidecar = folder.join(format!("{}.{}", title, extension));
+        if sidecar.exists() && !entries.iter().any(|entry| entry.patch == sidecar) {
+            entries.push(PatchEntry {
+                title: title.to_string(),
+                patch: sidecar,
+                source_crc32: None,
+                name: Some("patched".to_string()),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// A patched image.
+#[derive(Debug, Clone)]
+pub struct Patched {
+    /// Format of the patch that was applied
+    pub format: PatchFormat,
+    /// Size of the patched image in bytes
+    pub size: u64,
+}
+
+/// Applies `patch` to `source`, writing the result to `output`; the source is left untouched.
+/// When `expected_crc32` is given, the source must have that CRC32.
+/// `on_progress` receives the number of bytes written so far. A failed or cancelled patch removes the output.
+pub fn apply(
+    source: &Path,
+    patch: &Path,
+    output: &Path,
+    expected_crc32: Option<u32>,
+    cancel: &CancellationToken,
+    on_progress: impl FnMut(u64),
+) -> Result<Patched> {
+    let data = fs::read(patch)?;
+    let format = PatchFormat::detect(&data)
+        .ok_or_else(|| Error::PatchFailed(format!("{} is not an IPS, PPF or VCDIFF patch", patch.display())))?;
+
+    if let Some(expected) = expected_crc32 {
+        let actual = crate::utils::crc32_file(source)?;
+        if actual != expected {
+            return Err(Error::ChecksumMismatch {
+                file: source.display().to_string(),
+                expected: format!("{:08x}", expected),
+                actual: format!("{:08x}", actual),
+            });
+        }
+    }
+
+    let result = match format {
+        PatchFormat::Ips => copy_and_patch(source, output, cancel, on_progress, |file| apply_ips(&data, file)),
+        PatchFormat::Ppf => {
+            check_ppf(&data, source)?;
+            copy_and_patch(source, output, cancel, on_progress, |file| apply_ppf(&data, file))
+        }
+        PatchFormat::Vcdiff => apply_vcdiff(&data, source, output, cancel, on_progress),
+    };
+    match result {
+        Ok(()) => Ok(Patched {
+            format,
+            size: fs::metadata(output)?.len(),
+        }),
+        Err(e) => {
+            let _ = fs::remove_file(output);
+            Err(e)
+        }
+    }
+}
+
+/// Copies the source to the output, then patches the copy in place.
+fn copy_and_patch(
+    source: &Path,
+    output: &Path,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(u64),
+    patch: impl FnOnce(&mut File) -> Result<()>,
+) -> Result<()> {
+    let mut input = File::open(source)?;
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(output)?;
+    let mut buffer = vec![0u8; COPY_CHUNK];
+    let mut copied = 0u64;
+    loop {
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read])?;
+        copied += read as u64;
+        on_progress(copied);
+    }
+    patch(&mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Cursor over patch data that reports truncation as a patch error.
+struct PatchReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self.position.checked_add(count).filter(|&end| end <= self.data.len());
+        let end = end.ok_or_else(|| Error::PatchFailed("patch data is truncated".to_string()))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Big-endian integer of `count` bytes.
+    fn be(&mut self, count: usize) -> Result<u64> {
+        Ok(self.bytes(count)?.iter().fold(0, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    /// Little-endian integer of `count` bytes.
+    fn le(&mut self, count: usize) -> Result<u64> {
+        Ok(self.bytes(count)?.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    /// VCDIFF variable-length integer: big-endian base 128, high bit set on all but the last byte.
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for _ in 0..10 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::PatchFailed("invalid VCDIFF integer".to_string()))
+    }
+}
+
+/// IPS: 'PATCH', records of a 3-byte offset and 2-byte size (0 means an RLE run), 'EOF',
+/// and an optional 3-byte size the image is truncated to.
+fn apply_ips(data: &[u8], file: &mut File) -> Result<()> {
+    let mut patch = PatchReader::new(data, 5);
+    loop {
+        let offset = patch.be(3)?;
+        if offset == 0x454F46 {
+            // 'EOF'
+            break;
+        }
+        let size = patch.be(2)? as usize;
+        file.seek(SeekFrom::Start(offset))?;
+        if size == 0 {
+            let count = patch.be(2)? as usize;
+            let value = patch.byte()?;
+            file.write_all(&vec![value; count])?;
+        } else {
+            file.write_all(patch.bytes(size)?)?;
+        }
+    }
+    if let Ok(length) = patch.be(3) {
+        file.set_len(length)?;
+    }
+    Ok(())
+}
+
+/// PPF version (1, 2 or 3) from the 'PPFx0' signature.
+fn ppf_version(data: &[u8]) -> Result<u8> {
+    match data.get(..5) {
+        Some(b"PPF10") => Ok(1),
+        Some(b"PPF20") => Ok(2),
+        Some(b"PPF30") => Ok(3),
+        _ => Err(Error::PatchFailed("unknown PPF version".to_string())),
+    }
+}
+
+/// Checks the image against the size and the 1024-byte block stored in PPF 2.0/3.0 patches.
+fn check_ppf(data: &[u8], source: &Path) -> Result<()> {
+    let mut patch = PatchReader::new(data, 56);
+    let (size, offset) = match ppf_version(data)? {
+        1 => return Ok(()),
+        2 => (Some(patch.le(4)?), PPF_BLOCK_OFFSET_BIN),
+        _ => {
+            let image_type = patch.byte()?;
+            let block_check = patch.byte()?;
+            if block_check == 0 {
+                return Ok(());
+            }
+            let offset = if image_type == 1 { PPF_BLOCK_OFFSET_GI } else { PPF_BLOCK_OFFSET_BIN };
+            patch.bytes(2)?;
+            (None, offset)
+        }
+    };
+    let block = patch.bytes(PPF_BLOCK_SIZE)?;
+
+    let mut image = File::open(source)?;
+    if let Some(size) = size {
+        let actual = image.metadata()?.len();
+        if actual != size {
+            return Err(Error::PatchFailed(format!(
+                "the patch expects an image of {} bytes, this one has {}",
+                size, actual
+            )));
+        }
+    }
+    let mut actual = vec![0u8; PPF_BLOCK_SIZE];
+    image.seek(SeekFrom::Start(offset))?;
+    image.read_exact(&mut actual)?;
+    if actual != block {
+        return Err(Error::PatchFailed("the image does not match the patch's check block".to_string()));
+    }
+    Ok(())
+}
+
+/// PPF: a header, then records of an offset (4 bytes, 8 in 3.0), a length and the data.
+/// PPF 3.0 records may carry undo data, and 2.0/3.0 files may end with a FILE_ID.DIZ.
+fn apply_ppf(data: &[u8], file: &mut File) -> Result<()> {
+    let version = ppf_version(data)?;
+    let (start, offset_size, undo, diz_length_size) = match version {
+        1 => (56, 4, false, 0),
+        2 => (56 + 4 + PPF_BLOCK_SIZE, 4, false, 4),
+        _ => {
+            let block_check = data.get(57).copied().unwrap_or(0) != 0;
+            let undo = data.get(58).copied().unwrap_or(0) != 0;
+            (60 + if block_check { PPF_BLOCK_SIZE } else { 0 }, 8, undo, 2)
+        }
+    };
+
+    // The optional description block: '@BEGIN_FILE_ID.DIZ', text, '@END_FILE_ID.DIZ', length
+    let mut end = data.len();
+    if diz_length_size > 0 && data.len() >= diz_length_size + 4 {
+        let marker = data.len() - diz_length_size - 4;
+        if &data[marker..marker + 4] == b".DIZ" {
+            let length = PatchReader::new(data, marker + 4).le(diz_length_size)? as usize;
+            end = data
+                .len()
+                .checked_sub(length + 18 + 16 + diz_length_size)
+                .ok_or_else(|| Error::PatchFailed("invalid FILE_ID.DIZ block".to_string()))?;
+        }
+    }
+
+    let mut patch = PatchReader::new(&data[..end], start);
+    while !patch.is_empty() {
+        let offset = patch.le(offset_size)?;
+        let length = patch.byte()? as usize;
+        let bytes = patch.bytes(length)?;
+        if undo {
+            patch.bytes(length)?;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(bytes)?;
+    }
+    Ok(())
+}
+
+/// Instruction types of the VCDIFF code table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instruction {
+    Noop,
+    Add,
+    Run,
+    Copy(u8),
+}
+
+/// The default VCDIFF code table (RFC 3284 section 5.6): each entry is two (instruction, size) pairs.
+fn default_code_table() -> Vec<[(Instruction, u8); 2]> {
+    use Instruction::*;
+    let mut table = Vec::with_capacity(256);
+    table.push([(Run, 0), (Noop, 0)]);
+    for size in 0..=17 {
+        table.push([(Add, size), (Noop, 0)]);
+    }
+    for mode in 0..9 {
+        table.push([(Copy(mode), 0), (Noop, 0)]);
+        for size in 4..=18 {
+            table.push([(Copy(mode), size), (Noop, 0)]);
+        }
+    }
+    for mode in 0..6 {
+        for add in 1..=4 {
+            for copy in 4..=6 {
+                table.push([(Add, add), (Copy(mode), copy)]);
+            }
+        }
+    }
+    for mode in 6..9 {
+        for add in 1..=4 {
+            table.push([(Add, add), (Copy(mode), 4)]);
+        }
+    }
+    for mode in 0..9 {
+        table.push([(Copy(mode), 4), (Add, 1)]);
+    }
+    table
+}
+
+/// The VCDIFF address cache (4 near slots, 3 same blocks).
+struct AddressCache {
+    near: [u64; 4],
+    next_slot: usize,
+    same: Vec<u64>,
+}
+
+impl AddressCache {
+    fn new() -> Self {
+        Self {
+            near: [0; 4],
+            next_slot: 0,
+            same: vec![0; 3 * 256],
+        }
+    }
+
+    fn decode(&mut self, here: u64, mode: u8, addresses: &mut PatchReader) -> Result<u64> {
+        let address = match mode {
+            0 => addresses.varint()?,
+            1 => here
+                .checked_sub(addresses.varint()?)
+                .ok_or_else(|| Error::PatchFailed("invalid VCDIFF address".to_string()))?,
+            2..=5 => self.near[mode as usize - 2] + addresses.varint()?,
+            _ => self.same[(mode as usize - 6) * 256 + addresses.byte()? as usize],
+        };
+        self.near[self.next_slot] = address;
+        self.next_slot = (self.next_slot + 1) % self.near.len();
+        let same_size = self.same.len() as u64;
+        self.same[(address % same_size) as usize] = address;
+        Ok(address)
+    }
+}
+
+/// VCDIFF (RFC 3284) with the xdelta3 extensions: application header and per-window Adler-32.
+/// Secondary compression and custom code tables are not supported.
+fn apply_vcdiff(
+    data: &[u8],
+    source: &Path,
+    output: &Path,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(u64),
+) -> Result<()> {
+    let unsupported = |what: &str| Error::PatchFailed(format!("VCDIFF patches with {} are not supported", what));
+    let mut patch = PatchReader::new(data, 4);
+    let header = patch.byte()?;
+    if header & 0x01 != 0 {
+        return Err(unsupported("secondary compression (create them with 'xdelta3 -S none')"));
+    }
+    if header & 0x02 != 0 {
+        return Err(unsupported("a custom code table"));
+    }
+    if header & 0x04 != 0 {
+        let length = patch.varint()? as usize;
+        patch.bytes(length)?;
+    }
+
+    let table = default_code_table();
+    let mut input = BufReader::new(File::open(source)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+    // Windows copying from earlier target data need what has been written so far
+    let mut target_file: Option<File> = None;
+    let mut written = 0u64;
+
+    while !patch.is_empty() {
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let indicator = patch.byte()?;
+        let segment = if indicator & 0x03 != 0 {
+            let length = patch.varint()? as usize;
+            let position = patch.varint()?;
+            let mut segment = vec![0u8; length];
+            if indicator & 0x01 != 0 {
+                input.seek(SeekFrom::Start(position))?;
+                input.read_exact(&mut segment)?;
+            } else {
+                writer.flush()?;
+                let target = match &mut target_file {
+                    Some(file) => file,
+                    None => target_file.insert(File::open(output)?),
+                };
+                target.seek(SeekFrom::Start(position))?;
+                target.read_exact(&mut segment)?;
+            }
+            segment
+        } else {
+            Vec::new()
+        };
+
+        patch.varint()?; // length of the delta encoding
+        let window_length = patch.varint()? as usize;
+        if patch.byte()? != 0 {
+            return Err(unsupported("compressed sections"));
+        }
+        let data_length = patch.varint()? as usize;
+        let instructions_length = patch.varint()? as usize;
+        let addresses_length = patch.varint()? as usize;
+        let checksum = match indicator & 0x04 != 0 {
+            true => Some(patch.be(4)? as u32),
+            false => None,
+        };
+        let mut adds = PatchReader::new(patch.bytes(data_length)?, 0);
+        let mut instructions = PatchReader::new(patch.bytes(instructions_length)?, 0);
+        let mut addresses = PatchReader::new(patch.bytes(addresses_length)?, 0);
+
+        let mut window = Vec::with_capacity(window_length);
+        let mut cache = AddressCache::new();
+        while !instructions.is_empty() {
+            let code = instructions.byte()?;
+            for (instruction, size) in table[code as usize] {
+                if instruction == Instruction::Noop {
+                    continue;
+                }
+                let size = match size {
+                    0 => instructions.varint()? as usize,
+                    size => size as usize,
+                };
+                match instruction {
+                    Instruction::Noop => {}
+                    Instruction::Add => window.extend_from_slice(adds.bytes(size)?),
+                    Instruction::Run => {
+                        let byte = adds.byte()?;
+                        window.resize(window.len() + size, byte);
+                    }
+                    Instruction::Copy(mode) => {
+                        let here = (segment.len() + window.len()) as u64;
+                        let address = cache.decode(here, mode, &mut addresses)? as usize;
+                        if address >= here as usize {
+                            return Err(Error::PatchFailed("invalid VCDIFF copy address".to_string()));
+                        }
+                        // Copies may overlap the bytes they produce, so go byte by byte
+                        for index in address..address + size {
+                            let byte = match index < segment.len() {
+                                true => segment[index],
+                                false => window[index - segment.len()],
+                            };
+                            window.push(byte);
+                        }
+                    }
+                }
+            }
+        }
+        if window.len() != window_length {
+            return Err(Error::PatchFailed("VCDIFF window has the wrong length".to_string()));
+        }
+        if checksum.is_some_and(|checksum| checksum != adler32(&window)) {
+            return Err(Error::PatchFailed("VCDIFF window checksum mismatch".to_string()));
+        }
+        writer.write_all(&window)?;
+        written += window.len() as u64;
+        on_progress(written);
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Adler-32, used by xdelta3 for window checksums.
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder under the system temp folder, emptied for the test.
+    fn scratch(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("ps2rd-patch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// Applies `patch` to `source` and returns the patched bytes, or the error with the output checked gone.
+    fn patch(name: &str, source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+        let folder = scratch(name);
+        let (input, file, output) = (folder.join("game.iso"), folder.join("game.patch"), folder.join("patched.iso"));
+        fs::write(&input, source).unwrap();
+        fs::write(&file, patch).unwrap();
+        let result = apply(&input, &file, &output, None, &CancellationToken::new(), |_| {});
+        let patched = result.map(|_| fs::read(&output).unwrap());
+        assert!(patched.is_ok() || !output.exists(), "a failed patch leaves no output");
+        assert_eq!(fs::read(&input).unwrap(), source, "the source is left untouched");
+        fs::remove_dir_all(folder).unwrap();
+        patched
+    }
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(PatchFormat::detect(b"PATCHEOF"), Some(PatchFormat::Ips));
+        assert_eq!(PatchFormat::detect(b"PPF30\x02"), Some(PatchFormat::Ppf));
+        assert_eq!(PatchFormat::detect(&[0xD6, 0xC3, 0xC4, 0x00, 0x00]), Some(PatchFormat::Vcdiff));
+        assert_eq!(PatchFormat::detect(b"PK\x03\x04"), None);
+    }
+
+    #[test]
+    fn adler32_known_vector() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn ips_records_rle_and_truncation() {
+        let mut ips = b"PATCH".to_vec();
+        ips.extend([0x00, 0x00, 0x02, 0x00, 0x03]);
+        ips.extend(b"abc");
+        // RLE record: size 0, then a 2-byte count and the value
+        ips.extend([0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x04, 0x55]);
+        ips.extend(b"EOF");
+        ips.extend([0x00, 0x00, 0x0E]);
+
+        let patched = patch("ips", &[0u8; 16], &ips).unwrap();
+        assert_eq!(patched, [0, 0, b'a', b'b', b'c', 0, 0, 0, 0x55, 0x55, 0x55, 0x55, 0, 0]);
+
+        // Without the 'EOF' marker the patch is truncated
+        let error = patch("ips-truncated", &[0u8; 16], &ips[..ips.len() - 6]).unwrap_err();
+        assert!(matches!(error, Error::PatchFailed(_)));
+    }
+
+    #[test]
+    fn ppf2_checks_the_image_block() {
+        let mut source = vec![0u8; PPF_BLOCK_OFFSET_BIN as usize + PPF_BLOCK_SIZE];
+        for (index, byte) in source.iter_mut().enumerate() {
+            *byte = (index % 251) as u8;
+        }
+        let mut ppf = b"PPF20\x01".to_vec();
+        ppf.extend([b' '; 50]);
+        ppf.extend((source.len() as u32).to_le_bytes());
+        ppf.extend(&source[PPF_BLOCK_OFFSET_BIN as usize..]);
+        ppf.extend(0x10u32.to_le_bytes());
+        ppf.push(2);
+        ppf.extend(b"hi");
+
+        let patched = patch("ppf", &source, &ppf).unwrap();
+        assert_eq!(&patched[0x10..0x12], b"hi");
+        assert_eq!(patched[..0x10], source[..0x10]);
+        assert_eq!(patched[0x12..], source[0x12..]);
+
+        source[PPF_BLOCK_OFFSET_BIN as usize] ^= 0xFF;
+        let error = patch("ppf-mismatch", &source, &ppf).unwrap_err();
+        assert!(matches!(error, Error::PatchFailed(message) if message.contains("check block")));
+    }
+
+    #[test]
+    fn vcdiff_copy_add_and_run() {
+        let source = b"ABCDEFGHIJKLMNOP";
+        let target = b"ABCDEFGHxyzxyzxyz!!!!";
+        // Default code table: 24 = COPY mode 0 size 8, 4 = ADD size 3, 22 = COPY mode 0 size 6, 0 = RUN with its size next
+        let adds = b"xyz!";
+        let instructions = [24, 4, 22, 0, 4];
+        // The second copy reads the window's own output: 16 source bytes + 8 = 'xyz', overlapping what it writes
+        let addresses = [0, 24];
+
+        let mut delta = vec![target.len() as u8, 0, adds.len() as u8, instructions.len() as u8, addresses.len() as u8];
+        delta.extend(adler32(target).to_be_bytes());
+        delta.extend(adds);
+        delta.extend(instructions);
+        delta.extend(addresses);
+        let mut vcdiff = vec![0xD6, 0xC3, 0xC4, 0x00, 0x00];
+        // Window: source segment with an Adler-32, 16 bytes at 0
+        vcdiff.extend([0x01 | 0x04, source.len() as u8, 0, delta.len() as u8]);
+        vcdiff.extend(&delta);
+
+        assert_eq!(patch("vcdiff", source, &vcdiff).unwrap(), target);
+
+        let last = vcdiff.len() - 1;
+        vcdiff[last] = 40;
+        let error = patch("vcdiff-address", source, &vcdiff).unwrap_err();
+        assert!(matches!(error, Error::PatchFailed(message) if message.contains("copy address")));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::error::{Error, Result};
+use crate::hooks::{HookContext, HookEvent};
+use crate::discs::DiscSets;
+use crate::{downloader::Downloader, models::Game};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::collections::VecDeque;
+use tokio_util::sync::CancellationToken;
+
+/// A queue of games downloaded one after another, or a few at a time.
+#[derive(Debug)]
+pub struct DownloadQueue {
+    pending: VecDeque<Game>,
+    concurrency: usize,
+}
+
+impl Default for DownloadQueue {
+    fn default() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            concurrency: 1,
+        }
+    }
+}
+
+/// Outcome of running the queue.
+#[derive(Debug, Default)]
+pub struct QueueSummary {
+    /// Titles downloaded (or already present)
+    pub completed: Vec<String>,
+    /// Titles that failed, with the error message
+    pub failed: Vec<(String, String)>,
+}
+
+impl DownloadQueue {
+    /// Creates an empty queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Downloads up to `concurrency` games at the same time (at least one).
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Adds a game at the end of the queue, ignoring duplicates.
+    pub fn push(&mut self, game: Game) {
+        if !self.pending.iter().any(|queued| queued.title == game.title) {
+            self.pending.push_back(game);
+        }
+    }
+
+    /// Adds several games at the end of the queue.
+    pub fn extend<I: IntoIterator<Item = Game>>(&mut self, games: I) {
+        for game in games {
+            self.push(game);
+        }
+    }
+
+    /// Queues the other discs of every multi-disc title, next to the first disc queued.
+    pub fn complete_sets(&mut self, sets: &DiscSets) {
+        self.pending = sets.complete(self.pending.drain(..)).into();
+    }
+
+    /// Number of queued games.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns true if nothing is queued.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Iterates over the queued games in download order.
+    pub fn games(&self) -> impl Iterator<Item = &Game> {
+        self.pending.iter()
+    }
+
+    /// Total size of the queued games in bytes, and how many games have an unknown size.
+    pub fn total_size(&self) -> (u64, usize) {
+        self.pending
+            .iter()
+            .fold((0, 0), |(total, unknown), game| match game.size_bytes() {
+                Some(size) => (total + size, unknown),
+                None => (total, unknown + 1),
+            })
+    }
+
+    /// Downloads every queued game in order, starting the next one whenever fewer than `concurrency`
+    /// are running. A failed game is recorded and the queue moves on; cancellation stops the queue
+    /// and leaves the interrupted games and the rest queued.
+    /// The queue-empty hook runs once every game has been tried, unless the run was cancelled.
+    pub async fn run(&mut self, downloader: &Downloader, cancel: &CancellationToken) -> Result<QueueSummary> {
+        let mut summary = QueueSummary::default();
+        let mut running = FuturesUnordered::new();
+        let mut interrupted = Vec::new();
+
+        loop {
+            while running.len() < self.concurrency && !cancel.is_cancelled() {
+                let Some(game) = self.pending.pop_front() else {
+                    break;
+                };
+                running.push(async move {
+                    let result = downloader.download_ps2_element(&game, cancel).await;
+                    (game, result)
+                });
+            }
+            let Some((game, result)) = running.next().await else {
+                break;
+            };
+            match result {
+                Ok(_) => summary.completed.push(game.clean_title()),
+                Err(Error::Cancelled) => interrupted.push(game),
+                Err(e) => summary.failed.push((game.clean_title(), e.to_string())),
+            }
+        }
+        for game in interrupted.into_iter().rev() {
+            self.pending.push_front(game);
+        }
+        // Even when nothing is left queued, a cancelled run did not empty the queue
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        let context = HookContext {
+            completed: Some(summary.completed.len()),
+            failed: Some(summary.failed.len()),
+            ..HookContext::default()
+        };
+        downloader.run_hook(HookEvent::QueueEmpty, context).await;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Event, Output};
+
+    /// Runs an empty queue with a queue-empty hook and returns the result and whether the hook ran.
+    async fn run_empty(cancel: &CancellationToken) -> (Result<QueueSummary>, bool) {
+        let mut config = crate::config::Settings::defaults().config().unwrap();
+        config.hook_on_queue_empty = "echo done".to_string();
+        let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let downloader = Downloader::new(&config).with_output(Output::Channel(sender));
+        let result = DownloadQueue::new().run(&downloader, cancel).await;
+        let mut hook_ran = false;
+        while let Ok(event) = events.try_recv() {
+            hook_ran |= matches!(event, Event::Hook { hook: HookEvent::QueueEmpty, .. });
+        }
+        (result, hook_ran)
+    }
+
+    #[tokio::test]
+    async fn queue_empty_hook_runs_after_the_last_game() {
+        let (result, hook_ran) = run_empty(&CancellationToken::new()).await;
+        assert!(result.is_ok());
+        assert!(hook_ran);
+    }
+
+    #[tokio::test]
+    async fn cancelled_run_skips_the_queue_empty_hook() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let (result, hook_ran) = run_empty(&cancel).await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(!hook_ran);
+    }
+}
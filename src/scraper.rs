@@ -1,14 +1,19 @@
+use crate::output::{CatalogSource, Event, Output};
+use crate::error::{Error, Result};
 use crate::{config::Config, models::Game};
-use anyhow::Result;
 use reqwest::Client;
 use scraper::{Html, Selector};
 use serde_json;
 use std::fs;
 
+/// Redump quick search, used to look up disc serials.
+const REDUMP_QUICKSEARCH_URL: &str = "http://redump.org/discs/quicksearch/";
+
 /// Scraper handles downloading and parsing the PS2 games list from the configured source.
 pub struct Scraper {
     config: Config,
     client: Client,
+    output: Output,
 }
 
 impl Scraper {
@@ -22,35 +27,58 @@ impl Scraper {
         Self {
             config: config.clone(),
             client,
+            output: Output::default(),
         }
     }
 
+    /// Sets the output mode used for messages and catalog events.
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
     /// Gets the PS2 games list, using a cached JSON file if available, otherwise scrapes and saves it.
     pub async fn get_ps2_list(&self) -> Result<Vec<Game>> {
         // Check if JSON file exists
         let json_path = self.config.list_json_path();
         if json_path.exists() {
-            println!("{} exists...", self.config.list_files_json_name);
+            self.output.message(&format!("{} exists...", self.config.list_files_json_name));
             let content = fs::read_to_string(&json_path)?;
             let mut games: Vec<Game> = serde_json::from_str(&content)?;
             // Initialize lowercased_title for each game
             for game in &mut games {
                 game.lowercased_title = game.title.to_lowercase();
             }
-            println!(
+            self.output.message(&format!(
                 "{} has {} titles",
                 self.config.list_files_json_name,
                 games.len()
-            );
+            ));
+            self.output.emit(&Event::Catalog {
+                source: CatalogSource::Cache,
+                path: json_path.display().to_string(),
+                titles: games.len(),
+            });
             return Ok(games);
         }
 
+        self.refresh_ps2_list().await
+    }
+
+    /// Downloads and parses the PS2 games list, replacing the cached JSON file.
+    pub async fn refresh_ps2_list(&self) -> Result<Vec<Game>> {
         // Download and parse PS2 list
-        println!("Downloading PS2 list...");
+        self.output.message("Downloading PS2 list...");
         let response = self.client.get(&self.config.iso_url).send().await?;
+        if !response.status().is_success() {
+            return Err(Error::HttpStatus {
+                status: response.status().as_u16(),
+                url: self.config.iso_url.clone(),
+            });
+        }
         let html = response.text().await?;
 
-        println!("Converting data...");
+        self.output.message("Converting data...");
         let document = Html::parse_document(&html);
 
         // Selectors for parsing the table rows, links, and sizes
@@ -76,13 +104,54 @@ impl Scraper {
             }
         }
 
-        println!("Downloaded {} titles", games.len());
+        self.output.message(&format!("Downloaded {} titles", games.len()));
 
         // Save to JSON file
+        let json_path = self.config.list_json_path();
         let json_content = serde_json::to_string_pretty(&games)?;
         fs::write(&json_path, json_content)?;
-        println!("Saved in {}", json_path.display());
+        self.output.message(&format!("Saved in {}", json_path.display()));
+        self.output.emit(&Event::Catalog {
+            source: CatalogSource::Remote,
+            path: json_path.display().to_string(),
+            titles: games.len(),
+        });
 
         Ok(games)
     }
+
+    /// Looks up the titles Redump lists for a disc serial (e.g. 'SLUS-20946').
+    /// The titles are Redump display names, which use ':' where file names use ' -'.
+    pub async fn lookup_serial(&self, serial: &str) -> Result<Vec<String>> {
+        let url = format!("{}{}/", REDUMP_QUICKSEARCH_URL, serial);
+        let response = self.client.get(&url).send().await?;
+        if !response.status().is_success() {
+            return Err(Error::HttpStatus {
+                status: response.status().as_u16(),
+                url,
+            });
+        }
+
+        // A single match redirects to the disc page, several matches show a results table
+        let single_disc = response.url().path().starts_with("/disc/");
+        let html = response.text().await?;
+        let document = Html::parse_document(&html);
+
+        let titles = if single_disc {
+            let heading_selector = Selector::parse("h1").unwrap();
+            document
+                .select(&heading_selector)
+                .map(|heading| heading.text().collect::<String>().trim().to_string())
+                .take(1)
+                .collect()
+        } else {
+            let link_selector = Selector::parse("table a[href^='/disc/']").unwrap();
+            document
+                .select(&link_selector)
+                .map(|link| link.text().collect::<String>().trim().to_string())
+                .collect()
+        };
+
+        Ok(titles)
+    }
 }
@@ -0,0 +1,25 @@
+use crate::models::Game;
+
+/// Filters games by search string using the precomputed lowercased_title for efficiency.
+/// Every whitespace-separated word must appear in the title.
+pub fn filter_games<'a>(games: &'a [Game], search: &str) -> Vec<&'a Game> {
+    filter_indices(games, search)
+        .into_iter()
+        .map(|i| &games[i])
+        .collect()
+}
+
+/// Like `filter_games`, but returns the positions of the matching games.
+pub fn filter_indices(games: &[Game], search: &str) -> Vec<usize> {
+    let search_lower = search.to_lowercase();
+    let searches: Vec<&str> = search_lower.split_whitespace().collect();
+
+    games
+        .iter()
+        .enumerate()
+        .filter(|(_, game)| {
+            searches.iter().all(|search| game.lowercased_title.contains(search))
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
@@ -0,0 +1,117 @@
+/// Parses a selection of 1-based item numbers into sorted, deduplicated 0-based indices.
+///
+/// Accepted forms, separated by commas or spaces:
+/// - `3` a single item
+/// - `5-8` an inclusive range
+/// - `all` every item
+/// - `!2` or `!4-6` excludes items; a selection made only of exclusions starts from `all`
+pub fn parse_selection(input: &str, len: usize) -> Result<Vec<usize>, String> {
+    let mut included = vec![false; len];
+    let mut excluded = vec![false; len];
+    let mut any_include = false;
+
+    let tokens = input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty());
+
+    for token in tokens {
+        let (negated, token) = match token.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+
+        let (start, end) = if token.eq_ignore_ascii_case("all") {
+            (1, len)
+        } else {
+            parse_range(token, len)?
+        };
+
+        let target = if negated {
+            &mut excluded
+        } else {
+            any_include = true;
+            &mut included
+        };
+        for flag in &mut target[start - 1..end] {
+            *flag = true;
+        }
+    }
+
+    if !any_include {
+        if !excluded.contains(&true) {
+            return Err("Nothing selected".to_string());
+        }
+        included.iter_mut().for_each(|flag| *flag = true);
+    }
+
+    Ok((0..len).filter(|&i| included[i] && !excluded[i]).collect())
+}
+
+/// Parses `N` or `A-B` into an inclusive 1-based range within `1..=len`.
+fn parse_range(token: &str, len: usize) -> Result<(usize, usize), String> {
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .map_err(|_| format!("'{}' is not a number", s))
+    };
+
+    let (start, end) = match token.split_once('-') {
+        Some((a, b)) => (parse(a)?, parse(b)?),
+        None => {
+            let n = parse(token)?;
+            (n, n)
+        }
+    };
+
+    if start == 0 || end > len || start > end {
+        return Err(format!("'{}' is not in valid range (1-{})", token, len));
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_selections() {
+        let cases: &[(&str, &[usize])] = &[
+            ("3", &[2]),
+            ("1,3,5-8", &[0, 2, 4, 5, 6, 7]),
+            ("8-8 1", &[0, 7]),
+            ("all", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            ("ALL !2", &[0, 2, 3, 4, 5, 6, 7, 8, 9]),
+            ("5-8,6,5", &[4, 5, 6, 7]),
+            ("1-4 !2-3", &[0, 3]),
+            // Exclusions only start from every item
+            ("!2", &[0, 2, 3, 4, 5, 6, 7, 8, 9]),
+            ("!1-9", &[9]),
+            ("!1-10", &[]),
+            // Empty tokens between separators are skipped
+            ("1,,3", &[0, 2]),
+            (" , 2 ,", &[1]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_selection(input, 10).as_deref(), Ok(*expected), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_selections() {
+        let cases = [
+            ("", "Nothing selected"),
+            (" ,, ", "Nothing selected"),
+            ("8-5", "'8-5' is not in valid range (1-10)"),
+            ("0", "'0' is not in valid range (1-10)"),
+            ("11", "'11' is not in valid range (1-10)"),
+            ("3-11", "'3-11' is not in valid range (1-10)"),
+            ("!12", "'12' is not in valid range (1-10)"),
+            ("two", "'two' is not a number"),
+            ("1-", "'' is not a number"),
+            ("!", "'' is not a number"),
+        ];
+        for (input, message) in cases {
+            assert_eq!(parse_selection(input, 10), Err(message.to_string()), "{:?}", input);
+        }
+    }
+}
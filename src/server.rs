@@ -0,0 +1,555 @@
+use crate::error::{Error, Result};
+use crate::hooks::{HookContext, HookEvent};
+use crate::library::{LibraryEntry, SharedLibrary};
+use crate::output::{DownloadStatus, Event, Output, Stage};
+use crate::search::filter_games;
+use crate::{config::Config, downloader::Downloader, models::Game};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::uri::Authority;
+use axum::http::{header, StatusCode, Uri};
+use axum::middleware::{self, Next};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use futures_util::{future, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+/// Address the server listens on when `[server] LISTEN` is not set.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8420";
+/// Search results returned when a request sets no limit.
+const DEFAULT_SEARCH_LIMIT: usize = 50;
+/// Updates buffered per event stream; slower clients skip the oldest ones.
+const UPDATE_BUFFER: usize = 256;
+
+/// Web UI assets, compiled into the binary.
+const INDEX_HTML: &str = include_str!("../web/index.html");
+const APP_JS: &str = include_str!("../web/app.js");
+const STYLE_CSS: &str = include_str!("../web/style.css");
+
+/// State of a download job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for earlier jobs to finish
+    Queued,
+    /// Downloading, extracting or post-processing
+    Running,
+    /// Downloaded and extracted
+    Downloaded,
+    /// The image was already on disk or in the library
+    AlreadyPresent,
+    /// The download failed
+    Failed,
+    /// Cancelled through the API or by shutting down the server
+    Cancelled,
+}
+
+/// A game queued through the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    /// Job number, starting at 1
+    pub id: u64,
+    /// Clean title of the game
+    pub title: String,
+    /// Current state
+    pub status: JobStatus,
+    /// Stage of a running job
+    pub stage: Option<Stage>,
+    /// Bytes processed in the current stage of a running job
+    pub bytes: u64,
+    /// Total bytes of the current stage, if known
+    pub total: Option<u64>,
+    /// Path of the image, once downloaded
+    pub path: Option<String>,
+    /// Boot file serial read from the image
+    pub serial: Option<String>,
+    /// Error message of a failed job
+    pub error: Option<String>,
+    #[serde(skip)]
+    game: Game,
+    #[serde(skip)]
+    cancel: Option<CancellationToken>,
+}
+
+impl Job {
+    /// Returns true if the job has not finished yet.
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+/// Something sent to event stream clients.
+#[derive(Debug, Clone)]
+enum Update {
+    /// A job changed state
+    Job(Job),
+    /// An event reported by the downloader
+    Event(Event),
+}
+
+/// State shared by the request handlers, the job worker and the event relay.
+struct Server {
+    games: Vec<Game>,
+    library: SharedLibrary,
+    downloader: Arc<Downloader>,
+    token: Option<String>,
+    jobs: Mutex<Vec<Job>>,
+    queue: UnboundedSender<u64>,
+    updates: broadcast::Sender<Update>,
+    shutdown: CancellationToken,
+}
+
+impl Server {
+    /// Returns a copy of the job with the given id.
+    fn job(&self, id: u64) -> Option<Job> {
+        self.jobs.lock().unwrap().iter().find(|job| job.id == id).cloned()
+    }
+
+    /// Applies `update` to a job and sends the new state to event stream clients.
+    fn update_job<T>(&self, id: u64, update: impl FnOnce(&mut Job) -> T) -> Option<T> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.iter_mut().find(|job| job.id == id)?;
+        let result = update(job);
+        let _ = self.updates.send(Update::Job(job.clone()));
+        Some(result)
+    }
+}
+
+/// Runs the HTTP API on `listen` until `cancel` fires. Downloads run one at a time in the background,
+/// like the terminal UI's queue; `output` only receives the server's own messages.
+pub async fn serve(
+    config: &Config,
+    games: Vec<Game>,
+    library: SharedLibrary,
+    listen: &str,
+    output: &Output,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let address: SocketAddr = listen.trim().parse().map_err(|_| {
+        Error::ConfigInvalid(format!("server LISTEN must be an address like '{}', got '{}'", DEFAULT_LISTEN, listen))
+    })?;
+    let token = Some(config.server_token.clone()).filter(|token| !token.is_empty());
+    if token.is_none() && !address.ip().is_loopback() {
+        return Err(Error::ConfigInvalid(format!(
+            "server TOKEN must be set to listen on {}",
+            address
+        )));
+    }
+    if config.external_iso_download {
+        return Err(Error::ConfigInvalid("EXTERNAL_ISO downloads cannot be used by the server".to_string()));
+    }
+
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+    let mut downloader = Downloader::new(config)
+        .with_output(Output::Channel(events_tx))
+        .with_library(library.clone())
+        .with_catalog(&games);
+    if let Some(opl) = config.opl_export() {
+        downloader = downloader.with_opl(opl);
+    }
+    let server = Arc::new(Server {
+        games,
+        library,
+        downloader: Arc::new(downloader),
+        token,
+        jobs: Mutex::new(Vec::new()),
+        queue: queue_tx,
+        updates: broadcast::channel(UPDATE_BUFFER).0,
+        shutdown: cancel.clone(),
+    });
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    output.message(&format!("Listening on http://{}", listener.local_addr()?));
+    let relay = tokio::spawn(relay_events(server.clone(), events_rx));
+    let worker = tokio::spawn(process_jobs(server.clone(), queue_rx, cancel.clone()));
+
+    let result = axum::serve(listener, router(server))
+        .with_graceful_shutdown(cancel.clone().cancelled_owned())
+        .await;
+    // Stop the active download cleanly so its partial file can be resumed
+    cancel.cancel();
+    let _ = worker.await;
+    relay.abort();
+    output.message("Server stopped");
+    Ok(result?)
+}
+
+fn router(server: Arc<Server>) -> Router {
+    let api = Router::new()
+        .route("/api/catalog", get(search_catalog))
+        .route("/api/library", get(list_library))
+        .route("/api/jobs", get(list_jobs).post(enqueue))
+        .route("/api/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/api/events", get(stream_events))
+        .layer(middleware::from_fn_with_state(server.clone(), authorize))
+        .with_state(server);
+    // The web UI holds no data and asks for the token itself, so its assets need none
+    Router::new()
+        .route("/", get(|| async { ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], INDEX_HTML) }))
+        .route("/app.js", get(|| async { ([(header::CONTENT_TYPE, "text/javascript; charset=utf-8")], APP_JS) }))
+        .route("/style.css", get(|| async { ([(header::CONTENT_TYPE, "text/css; charset=utf-8")], STYLE_CSS) }))
+        .merge(api)
+}
+
+/// Downloads queued jobs one at a time. Cancelling `cancel` stops the active download and the worker.
+async fn process_jobs(server: Arc<Server>, mut queue: UnboundedReceiver<u64>, cancel: CancellationToken) {
+    // Outcomes since the queue was last empty, for the queue-empty hook
+    let (mut completed, mut failed) = (0, 0);
+    loop {
+        let id = tokio::select! {
+            id = queue.recv() => match id {
+                Some(id) => id,
+                None => return,
+            },
+            _ = cancel.cancelled() => return,
+        };
+
+        // Jobs cancelled while queued are skipped
+        if server.job(id).is_none_or(|job| job.status != JobStatus::Queued) {
+            continue;
+        }
+        // Each job gets its own token so it can be cancelled without stopping the queue
+        let token = cancel.child_token();
+        let game = server.update_job(id, |job| {
+            (job.status == JobStatus::Queued).then(|| {
+                job.status = JobStatus::Running;
+                job.cancel = Some(token.clone());
+                job.game.clone()
+            })
+        });
+        let Some(Some(game)) = game else {
+            continue;
+        };
+
+        let result = server.downloader.download_ps2_element(&game, &token).await;
+        match &result {
+            Ok(_) => completed += 1,
+            Err(Error::Cancelled) => {}
+            Err(_) => failed += 1,
+        }
+        server.update_job(id, |job| {
+            job.cancel = None;
+            job.stage = None;
+            job.bytes = 0;
+            job.total = None;
+            match result {
+                Ok(result) => {
+                    job.status = match result.status {
+                        DownloadStatus::AlreadyPresent => JobStatus::AlreadyPresent,
+                        _ => JobStatus::Downloaded,
+                    };
+                    job.path = result.path.map(|path| path.display().to_string());
+                    job.serial = result.disc.and_then(|disc| disc.serial);
+                }
+                Err(Error::Cancelled) => job.status = JobStatus::Cancelled,
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        });
+        if cancel.is_cancelled() {
+            return;
+        }
+        if queue.is_empty() && completed + failed > 0 {
+            let context = HookContext {
+                completed: Some(completed),
+                failed: Some(failed),
+                ..HookContext::default()
+            };
+            server.downloader.run_hook(HookEvent::QueueEmpty, context).await;
+            (completed, failed) = (0, 0);
+        }
+    }
+}
+
+/// Records download progress in the running job and forwards downloader events to event stream clients.
+async fn relay_events(server: Arc<Server>, mut events: UnboundedReceiver<Event>) {
+    while let Some(event) = events.recv().await {
+        if let Event::Progress { title, stage, bytes, total } = &event {
+            let mut jobs = server.jobs.lock().unwrap();
+            let running = jobs
+                .iter_mut()
+                .find(|job| job.status == JobStatus::Running && &job.title == title);
+            if let Some(job) = running {
+                job.stage = Some(*stage);
+                job.bytes = *bytes;
+                job.total = *total;
+            }
+        }
+        let _ = server.updates.send(Update::Event(event));
+    }
+}
+
+/// Rejects requests without the configured token, given as a bearer token or a `token` query parameter
+/// (browsers cannot set headers on event streams). Without a token, only requests addressed to this machine
+/// from this machine are served, so web pages cannot reach the API through DNS rebinding.
+async fn authorize(State(server): State<Arc<Server>>, request: Request, next: Next) -> Response {
+    if server.token.is_none() && !is_local_request(&request) {
+        return ApiError(
+            StatusCode::FORBIDDEN,
+            "without a server TOKEN, Host and Origin must be localhost or a loopback address".to_string(),
+        )
+        .into_response();
+    }
+    if let Some(token) = &server.token {
+        let bearer = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let query = Query::<TokenQuery>::try_from_uri(request.uri())
+            .ok()
+            .and_then(|Query(query)| query.token);
+        if !bearer.or(query.as_deref()).is_some_and(|given| tokens_match(given, token)) {
+            return ApiError(StatusCode::UNAUTHORIZED, "missing or invalid token".to_string()).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Returns true if the request's `Host` and, when a browser sent one, its `Origin` name a loopback host.
+fn is_local_request(request: &Request) -> bool {
+    let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+    let host = header(header::HOST).and_then(|host| host.parse::<Authority>().ok());
+    let origin_is_local = match header(header::ORIGIN) {
+        Some(origin) => origin.parse::<Uri>().ok().and_then(|uri| uri.host().map(is_loopback_host)).unwrap_or(false),
+        None => true,
+    };
+    host.is_some_and(|host| is_loopback_host(host.host())) && origin_is_local
+}
+
+/// Returns true for 'localhost' and loopback addresses ('127.0.0.1', '[::1]').
+fn is_loopback_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Compares tokens without stopping at the first differing byte.
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// An error answered as `{"error": "..."}` with the given status.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    /// Only titles whose region list contains this region, e.g. 'Europe'
+    region: Option<String>,
+    /// Only owned (true) or missing (false) titles
+    owned: Option<bool>,
+    limit: Option<usize>,
+}
+
+/// A catalog entry returned by a search.
+#[derive(Serialize)]
+struct CatalogEntry {
+    title: String,
+    region: Option<String>,
+    size: String,
+    owned: bool,
+}
+
+#[derive(Serialize)]
+struct CatalogResults {
+    total: usize,
+    results: Vec<CatalogEntry>,
+}
+
+async fn search_catalog(State(server): State<Arc<Server>>, Query(query): Query<SearchQuery>) -> Json<CatalogResults> {
+    let region = query.region.as_deref().map(str::trim).filter(|region| !region.is_empty());
+    let library = server.library.lock().unwrap();
+    let games: Vec<_> = filter_games(&server.games, &query.q)
+        .into_iter()
+        .map(|game| (game, game.region(), library.owns(game)))
+        .filter(|(_, regions, owned)| {
+            let in_region = region.is_none_or(|region| {
+                regions
+                    .as_deref()
+                    .is_some_and(|regions| regions.split(',').any(|r| r.trim().eq_ignore_ascii_case(region)))
+            });
+            in_region && query.owned.is_none_or(|wanted| wanted == *owned)
+        })
+        .collect();
+    let results = games
+        .iter()
+        .take(query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT))
+        .map(|(game, region, owned)| CatalogEntry {
+            title: game.clean_title(),
+            region: region.clone(),
+            size: game.size.clone(),
+            owned: *owned,
+        })
+        .collect();
+    Json(CatalogResults {
+        total: games.len(),
+        results,
+    })
+}
+
+async fn list_library(State(server): State<Arc<Server>>) -> Json<Vec<LibraryEntry>> {
+    Json(server.library.lock().unwrap().entries().to_vec())
+}
+
+async fn list_jobs(State(server): State<Arc<Server>>) -> Json<Vec<Job>> {
+    Json(server.jobs.lock().unwrap().clone())
+}
+
+#[derive(Deserialize)]
+struct EnqueueRequest {
+    titles: Vec<String>,
+}
+
+/// Queues catalog titles (with or without '.zip'). Nothing is queued if a title is unknown;
+/// titles that are already queued or running return their existing job.
+async fn enqueue(
+    State(server): State<Arc<Server>>,
+    Json(request): Json<EnqueueRequest>,
+) -> std::result::Result<(StatusCode, Json<Vec<Job>>), ApiError> {
+    let mut games = Vec::with_capacity(request.titles.len());
+    let mut unknown = Vec::new();
+    for title in &request.titles {
+        let title = title.trim();
+        match server.games.iter().find(|game| game.title == title || game.clean_title() == title) {
+            Some(game) => games.push(game.clone()),
+            None => unknown.push(title.to_string()),
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(ApiError(StatusCode::NOT_FOUND, format!("unknown titles: {}", unknown.join(", "))));
+    }
+    // The other discs of a multi-disc title are queued with it
+    let games = server.downloader.disc_sets().complete(games);
+
+    let mut queued = Vec::with_capacity(games.len());
+    let mut jobs = server.jobs.lock().unwrap();
+    for game in games {
+        let title = game.clean_title();
+        if let Some(job) = jobs.iter().find(|job| job.is_pending() && job.title == title) {
+            queued.push(job.clone());
+            continue;
+        }
+        let job = Job {
+            id: jobs.len() as u64 + 1,
+            title,
+            status: JobStatus::Queued,
+            stage: None,
+            bytes: 0,
+            total: None,
+            path: None,
+            serial: None,
+            error: None,
+            game,
+            cancel: None,
+        };
+        let _ = server.updates.send(Update::Job(job.clone()));
+        let _ = server.queue.send(job.id);
+        queued.push(job.clone());
+        jobs.push(job);
+    }
+    Ok((StatusCode::CREATED, Json(queued)))
+}
+
+async fn get_job(State(server): State<Arc<Server>>, Path(id): Path<u64>) -> std::result::Result<Json<Job>, ApiError> {
+    server
+        .job(id)
+        .map(Json)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("no job {}", id)))
+}
+
+/// Cancels a queued or running job. A running download keeps its partial file for resuming.
+async fn cancel_job(State(server): State<Arc<Server>>, Path(id): Path<u64>) -> std::result::Result<Json<Job>, ApiError> {
+    let job = server
+        .update_job(id, |job| {
+            match (job.status, &job.cancel) {
+                (JobStatus::Queued, _) => job.status = JobStatus::Cancelled,
+                (JobStatus::Running, Some(token)) => token.cancel(),
+                _ => return Err(ApiError(StatusCode::CONFLICT, format!("job {} has already finished", id))),
+            }
+            Ok(job.clone())
+        })
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("no job {}", id)))??;
+    Ok(Json(job))
+}
+
+#[derive(Deserialize)]
+struct EventFilter {
+    job: Option<u64>,
+}
+
+/// Streams job changes (`job` events) and downloader events (unnamed, the `--json` objects) as
+/// server-sent events, optionally limited to one job.
+async fn stream_events(
+    State(server): State<Arc<Server>>,
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = std::result::Result<sse::Event, Infallible>>> {
+    let updates = futures_util::stream::unfold(server.updates.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(update) => return Some((update, receiver)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let shutdown = server.shutdown.clone();
+    let stream = updates
+        .filter_map(move |update| {
+            let event = match update {
+                Update::Job(job) if filter.job.is_none_or(|id| id == job.id) => {
+                    sse::Event::default().event("job").json_data(&job).ok()
+                }
+                // The job may be created after the stream was opened, so its title is looked up per event
+                Update::Event(event)
+                    if filter.job.is_none_or(|id| {
+                        server.job(id).is_some_and(|job| event_title(&event) == Some(job.title.as_str()))
+                    }) =>
+                {
+                    sse::Event::default().json_data(&event).ok()
+                }
+                _ => None,
+            };
+            future::ready(event.map(Ok))
+        })
+        // End open streams on shutdown so the server can stop
+        .take_until(shutdown.cancelled_owned());
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Title of the game a downloader event is about.
+fn event_title(event: &Event) -> Option<&str> {
+    match event {
+        Event::Progress { title, .. }
+        | Event::DownloadResult { title, .. }
+        | Event::OplExport { title, .. }
+        | Event::Patched { title, .. } => Some(title),
+        _ => None,
+    }
+}
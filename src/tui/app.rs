@@ -0,0 +1,237 @@
+use crate::library::SharedLibrary;
+use crate::models::Game;
+use crate::output::{DownloadStatus, Event, Stage};
+use crate::search::filter_indices;
+use ratatui::widgets::ListState;
+use std::path::PathBuf;
+
+/// Panel that receives keyboard input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    /// The search box
+    Search,
+    /// The results list
+    Results,
+    /// The download queue
+    Queue,
+}
+
+/// State of a queued download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    /// Waiting for earlier downloads to finish
+    Queued,
+    /// Downloading or extracting
+    Running(Stage),
+    /// Downloaded and extracted
+    Done,
+    /// The ISO was already on disk
+    AlreadyPresent,
+    /// The download failed
+    Failed(String),
+    /// Cancelled by the user
+    Cancelled,
+    /// Checksum verification in progress
+    Verifying,
+    /// The ISO matches the CRC32 recorded at extraction
+    Verified,
+    /// The ISO does not match the recorded CRC32
+    VerifyFailed(String),
+}
+
+/// A game in the download queue.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// The game being downloaded
+    pub game: Game,
+    /// Current state
+    pub state: JobState,
+    /// Bytes processed in the current stage
+    pub bytes: u64,
+    /// Total bytes of the current stage, if known
+    pub total: Option<u64>,
+    /// Extracted ISO path, once done
+    pub path: Option<PathBuf>,
+    /// CRC32 recorded at extraction
+    pub crc32: Option<u32>,
+    /// Serial read from the extracted ISO
+    pub serial: Option<String>,
+}
+
+impl Job {
+    /// Returns true if the job has not finished yet.
+    pub fn is_pending(&self) -> bool {
+        matches!(self.state, JobState::Queued | JobState::Running(_))
+    }
+}
+
+/// State of the terminal UI.
+pub struct App {
+    /// Full catalog
+    pub games: Vec<Game>,
+    /// Current search text
+    pub query: String,
+    /// Indices into `games` matching `query`
+    pub filtered: Vec<usize>,
+    /// Selection in the results list
+    pub results_state: ListState,
+    /// Selection in the queue panel
+    pub queue_state: ListState,
+    /// Panel receiving keyboard input
+    pub focus: Focus,
+    /// Queued, running and finished downloads
+    pub jobs: Vec<Job>,
+    /// Last status message
+    pub status: String,
+    /// Whether downloads are paused
+    pub paused: bool,
+    /// Set when the user asked to quit
+    pub should_quit: bool,
+    /// Index of the images the user already has
+    pub library: SharedLibrary,
+}
+
+impl App {
+    /// Creates the UI state for a catalog, initially showing every title.
+    pub fn new(games: Vec<Game>, library: SharedLibrary) -> Self {
+        let mut app = Self {
+            games,
+            query: String::new(),
+            filtered: Vec::new(),
+            results_state: ListState::default(),
+            queue_state: ListState::default(),
+            focus: Focus::Search,
+            jobs: Vec::new(),
+            status: String::new(),
+            paused: false,
+            should_quit: false,
+            library,
+        };
+        app.refilter();
+        app
+    }
+
+    /// Recomputes the results after the query changed.
+    pub fn refilter(&mut self) {
+        self.filtered = filter_indices(&self.games, &self.query);
+        self.results_state
+            .select(if self.filtered.is_empty() { None } else { Some(0) });
+    }
+
+    /// The game highlighted in the results list.
+    pub fn selected_game(&self) -> Option<&Game> {
+        let index = self.results_state.selected()?;
+        self.filtered.get(index).map(|&i| &self.games[i])
+    }
+
+    /// The job highlighted in the queue panel.
+    pub fn selected_job(&self) -> Option<&Job> {
+        self.jobs.get(self.queue_state.selected()?)
+    }
+
+    /// Moves the selection of the focused list by `delta` rows.
+    pub fn move_selection(&mut self, delta: isize) {
+        let (state, len) = match self.focus {
+            Focus::Queue => (&mut self.queue_state, self.jobs.len()),
+            _ => (&mut self.results_state, self.filtered.len()),
+        };
+        if len == 0 {
+            state.select(None);
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        let next = (current + delta).clamp(0, len as isize - 1);
+        state.select(Some(next as usize));
+    }
+
+    /// Adds a job for `game` unless it is already pending. Returns true if it was added.
+    pub fn enqueue(&mut self, game: Game) -> bool {
+        if self
+            .jobs
+            .iter()
+            .any(|job| job.game.title == game.title && job.is_pending())
+        {
+            return false;
+        }
+        self.jobs.push(Job {
+            game,
+            state: JobState::Queued,
+            bytes: 0,
+            total: None,
+            path: None,
+            crc32: None,
+            serial: None,
+        });
+        if self.queue_state.selected().is_none() {
+            self.queue_state.select(Some(0));
+        }
+        true
+    }
+
+    /// Finds the most recent job for a clean title.
+    fn job_mut(&mut self, title: &str) -> Option<&mut Job> {
+        self.jobs
+            .iter_mut()
+            .rev()
+            .find(|job| job.game.clean_title() == title)
+    }
+
+    /// Applies a downloader event to the queue.
+    pub fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Progress {
+                title,
+                stage,
+                bytes,
+                total,
+            } => {
+                if let Some(job) = self.job_mut(&title) {
+                    job.state = JobState::Running(stage);
+                    job.bytes = bytes;
+                    job.total = total;
+                }
+            }
+            Event::DownloadResult {
+                title,
+                status,
+                path,
+                crc32,
+                serial,
+                error,
+                ..
+            } => {
+                if let Some(job) = self.job_mut(&title) {
+                    job.state = match status {
+                        DownloadStatus::Downloaded => JobState::Done,
+                        DownloadStatus::AlreadyPresent => JobState::AlreadyPresent,
+                        DownloadStatus::Cancelled => JobState::Cancelled,
+                        DownloadStatus::Failed => JobState::Failed(error.unwrap_or_default()),
+                    };
+                    job.path = path.map(PathBuf::from);
+                    job.crc32 = crc32.and_then(|crc| u32::from_str_radix(&crc, 16).ok());
+                    job.serial = serial;
+                }
+            }
+            Event::OplExport { path, .. } => self.status = format!("Exported to OPL: {}", path),
+            Event::Patched { path, .. } => self.status = format!("Patched: {}", path),
+            Event::Hook { .. } => {}
+            Event::Message { text } => self.status = text,
+            Event::Error { message } => self.status = message,
+            Event::Catalog { .. }
+            | Event::SearchResults { .. }
+            | Event::BatchResolved { .. }
+            | Event::WishlistChanged { .. }
+            | Event::WatchMatches { .. } => {}
+        }
+    }
+
+    /// Records the outcome of a verification.
+    pub fn handle_verified(&mut self, title: &str, result: Result<(), String>) {
+        if let Some(job) = self.job_mut(title) {
+            job.state = match result {
+                Ok(()) => JobState::Verified,
+                Err(e) => JobState::VerifyFailed(e),
+            };
+        }
+    }
+}
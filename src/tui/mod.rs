@@ -0,0 +1,274 @@
+mod app;
+mod ui;
+
+use crate::error::{Error, Result};
+use crate::hooks::{HookContext, HookEvent};
+use crate::library::SharedLibrary;
+use crate::output::Output;
+use crate::{config::Config, downloader::Downloader, models::Game};
+use app::{App, Focus, JobState};
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+/// Outcome of a checksum verification, sent back to the UI: (clean title, result).
+type Verified = (String, std::result::Result<(), String>);
+
+/// How long to wait for a key press before redrawing.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Runs the full-screen terminal UI until the user quits or `cancel` fires.
+pub async fn run(config: &Config, games: Vec<Game>, library: SharedLibrary, cancel: &CancellationToken) -> Result<()> {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let (verify_tx, mut verify_rx) = mpsc::unbounded_channel::<Verified>();
+    let mut downloader = Downloader::new(config)
+        .with_output(Output::Channel(events_tx))
+        .with_library(library.clone())
+        .with_catalog(&games);
+    if let Some(opl) = config.opl_export() {
+        downloader = downloader.with_opl(opl);
+    }
+    let downloader = Arc::new(downloader);
+    let worker = Worker::spawn(downloader.clone(), cancel.clone());
+
+    let mut app = App::new(games, library);
+    let mut terminal = ratatui::init();
+
+    let result = loop {
+        while let Ok(event) = events_rx.try_recv() {
+            app.handle_event(event);
+        }
+        while let Ok((title, outcome)) = verify_rx.try_recv() {
+            app.handle_verified(&title, outcome);
+        }
+
+        if let Err(e) = terminal.draw(|frame| ui::draw(frame, &mut app)) {
+            break Err(e.into());
+        }
+        if app.should_quit || cancel.is_cancelled() {
+            break Ok(());
+        }
+
+        // Polling blocks this worker thread briefly; downloads run on other tasks
+        match tokio::task::block_in_place(|| event::poll(TICK)) {
+            Ok(true) => match event::read() {
+                Ok(TermEvent::Key(key)) if key.kind == KeyEventKind::Press => {
+                    handle_key(&mut app, key, &worker, &downloader, &verify_tx);
+                }
+                Ok(_) => {}
+                Err(e) => break Err(e.into()),
+            },
+            Ok(false) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+
+    ratatui::restore();
+    // Stop the active download cleanly so its partial file can be resumed
+    cancel.cancel();
+    worker.finish().await;
+    result
+}
+
+/// Handles a key press according to the focused panel.
+fn handle_key(
+    app: &mut App,
+    key: KeyEvent,
+    worker: &Worker,
+    downloader: &Arc<Downloader>,
+    verify_tx: &UnboundedSender<Verified>,
+) {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        app.should_quit = true;
+        return;
+    }
+
+    match (app.focus, key.code) {
+        (_, KeyCode::Tab) => {
+            app.focus = match app.focus {
+                Focus::Search => Focus::Results,
+                Focus::Results => Focus::Queue,
+                Focus::Queue => Focus::Search,
+            }
+        }
+        (Focus::Search, KeyCode::Esc) => app.should_quit = true,
+        (Focus::Search, KeyCode::Char(c)) => {
+            app.query.push(c);
+            app.refilter();
+        }
+        (Focus::Search, KeyCode::Backspace) => {
+            app.query.pop();
+            app.refilter();
+        }
+        (Focus::Search, KeyCode::Enter | KeyCode::Down) => app.focus = Focus::Results,
+
+        (_, KeyCode::Char('q') | KeyCode::Esc) => app.should_quit = true,
+        (_, KeyCode::Up) => app.move_selection(-1),
+        (_, KeyCode::Down) => app.move_selection(1),
+        (_, KeyCode::PageUp) => app.move_selection(-10),
+        (_, KeyCode::PageDown) => app.move_selection(10),
+        (_, KeyCode::Home) => app.move_selection(isize::MIN / 2),
+        (_, KeyCode::End) => app.move_selection(isize::MAX / 2),
+        (_, KeyCode::Char('p')) => {
+            app.paused = !app.paused;
+            if app.paused {
+                downloader.pause();
+                app.status = "Downloads paused".to_string();
+            } else {
+                downloader.resume();
+                app.status = "Downloads resumed".to_string();
+            }
+        }
+
+        (Focus::Results, KeyCode::Char('/')) => app.focus = Focus::Search,
+        (Focus::Results, KeyCode::Enter | KeyCode::Char('a')) => {
+            if let Some(game) = app.selected_game().cloned() {
+                // The other discs of a multi-disc title are queued with it
+                let (title, set_title) = (game.clean_title(), game.set_title());
+                let mut queued = 0;
+                for disc in downloader.disc_sets().complete([game]) {
+                    if app.enqueue(disc.clone()) {
+                        queued += 1;
+                        worker.enqueue(disc);
+                    }
+                }
+                match queued {
+                    0 => {}
+                    1 => app.status = format!("Queued {}", title),
+                    discs => app.status = format!("Queued {} ({} discs)", set_title, discs),
+                }
+            }
+        }
+
+        (Focus::Queue, KeyCode::Char('c')) => {
+            if let Some(job) = app.selected_job() {
+                let title = job.game.title.clone();
+                match job.state {
+                    JobState::Queued => {
+                        worker.skip(&title);
+                        if let Some(index) = app.queue_state.selected() {
+                            app.jobs[index].state = JobState::Cancelled;
+                        }
+                    }
+                    JobState::Running(_) => worker.cancel_current(),
+                    _ => {}
+                }
+            }
+        }
+        (Focus::Queue, KeyCode::Char('v')) => {
+            let Some(index) = app.queue_state.selected() else {
+                return;
+            };
+            let job = &mut app.jobs[index];
+            match (job.path.clone(), job.crc32) {
+                (Some(path), Some(crc32)) => {
+                    job.state = JobState::Verifying;
+                    let title = job.game.clean_title();
+                    let downloader = downloader.clone();
+                    let verify_tx = verify_tx.clone();
+                    tokio::spawn(async move {
+                        let outcome = downloader.verify(&path, crc32).await.map_err(|e| e.to_string());
+                        let _ = verify_tx.send((title, outcome));
+                    });
+                }
+                _ => app.status = "Only titles extracted in this session can be verified".to_string(),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Background task downloading queued games one at a time.
+struct Worker {
+    games: UnboundedSender<Game>,
+    skipped: Arc<Mutex<HashSet<String>>>,
+    current: Arc<Mutex<Option<CancellationToken>>>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl Worker {
+    fn spawn(downloader: Arc<Downloader>, cancel: CancellationToken) -> Self {
+        let (games, rx) = mpsc::unbounded_channel();
+        let skipped = Arc::new(Mutex::new(HashSet::new()));
+        let current = Arc::new(Mutex::new(None));
+        let handle = tokio::spawn(Self::process(
+            downloader,
+            rx,
+            skipped.clone(),
+            current.clone(),
+            cancel,
+        ));
+        Self {
+            games,
+            skipped,
+            current,
+            handle,
+        }
+    }
+
+    async fn process(
+        downloader: Arc<Downloader>,
+        mut games: UnboundedReceiver<Game>,
+        skipped: Arc<Mutex<HashSet<String>>>,
+        current: Arc<Mutex<Option<CancellationToken>>>,
+        cancel: CancellationToken,
+    ) {
+        // Outcomes since the queue was last empty, for the queue-empty hook
+        let (mut completed, mut failed) = (0, 0);
+        loop {
+            let game = tokio::select! {
+                game = games.recv() => match game {
+                    Some(game) => game,
+                    None => return,
+                },
+                _ = cancel.cancelled() => return,
+            };
+            if skipped.lock().unwrap().remove(&game.title) {
+                continue;
+            }
+
+            // Each job gets its own token so it can be cancelled without stopping the queue
+            let token = cancel.child_token();
+            *current.lock().unwrap() = Some(token.clone());
+            // Failures are reported to the UI through download result events
+            match downloader.download_ps2_element(&game, &token).await {
+                Ok(_) => completed += 1,
+                Err(Error::Cancelled) if cancel.is_cancelled() => return,
+                Err(Error::Cancelled) => {}
+                Err(_) => failed += 1,
+            }
+            *current.lock().unwrap() = None;
+            if games.is_empty() && completed + failed > 0 {
+                let context = HookContext {
+                    completed: Some(completed),
+                    failed: Some(failed),
+                    ..HookContext::default()
+                };
+                downloader.run_hook(HookEvent::QueueEmpty, context).await;
+                (completed, failed) = (0, 0);
+            }
+        }
+    }
+
+    fn enqueue(&self, game: Game) {
+        let _ = self.games.send(game);
+    }
+
+    fn skip(&self, title: &str) {
+        self.skipped.lock().unwrap().insert(title.to_string());
+    }
+
+    fn cancel_current(&self) {
+        if let Some(token) = self.current.lock().unwrap().as_ref() {
+            token.cancel();
+        }
+    }
+
+    /// Waits for the worker to stop after the root token was cancelled.
+    async fn finish(self) {
+        let _ = self.handle.await;
+    }
+}
@@ -0,0 +1,184 @@
+use super::app::{App, Focus, Job, JobState};
+use crate::output::Stage;
+use crate::utils::format_size;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Wrap};
+use ratatui::Frame;
+
+/// Width of the text progress bar in the queue panel.
+const BAR_WIDTH: usize = 20;
+
+/// Draws the whole screen.
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [search_area, main_area, queue_area, help_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(8),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [results_area, details_area] =
+        Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(main_area);
+
+    draw_search(frame, app, search_area);
+    draw_results(frame, app, results_area);
+    draw_details(frame, app, details_area);
+    draw_queue(frame, app, queue_area);
+    draw_help(frame, app, help_area);
+}
+
+/// Block with a title, highlighted when its panel has focus.
+fn panel(title: String, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(title);
+    if focused {
+        block.border_style(Style::new().fg(Color::Cyan))
+    } else {
+        block
+    }
+}
+
+fn draw_search(frame: &mut Frame, app: &App, area: Rect) {
+    let focused = app.focus == Focus::Search;
+    let cursor = if focused { "_" } else { "" };
+    let search = Paragraph::new(format!("{}{}", app.query, cursor))
+        .block(panel("Search".to_string(), focused));
+    frame.render_widget(search, area);
+}
+
+fn draw_results(frame: &mut Frame, app: &mut App, area: Rect) {
+    let library = app.library.lock().unwrap();
+    let items: Vec<ListItem> = app
+        .filtered
+        .iter()
+        .map(|&i| {
+            let game = &app.games[i];
+            let owned = if library.owns(game) { "✓ " } else { "  " };
+            ListItem::new(Line::from(vec![
+                Span::styled(owned, Style::new().fg(Color::Green)),
+                Span::raw(game.clean_title()),
+                Span::raw("  "),
+                Span::styled(game.size.clone(), Style::new().fg(Color::DarkGray)),
+            ]))
+        })
+        .collect();
+    drop(library);
+    let title = format!("Results ({}/{})", app.filtered.len(), app.games.len());
+    let list = List::new(items)
+        .block(panel(title, app.focus == Focus::Results))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list, area, &mut app.results_state);
+}
+
+fn draw_details(frame: &mut Frame, app: &App, area: Rect) {
+    let lines = match app.selected_game() {
+        Some(game) => {
+            let size = match game.size_bytes() {
+                Some(bytes) => format!("{} ({} bytes)", game.size, bytes),
+                None => game.size.clone(),
+            };
+            let mut lines = vec![
+                Line::from(game.clean_title().bold()),
+                Line::default(),
+                Line::from(vec!["Region: ".dark_gray(), Span::raw(game.region().unwrap_or_default())]),
+                Line::from(vec!["Size:   ".dark_gray(), Span::raw(size)]),
+                Line::from(vec!["File:   ".dark_gray(), Span::raw(game.title.clone())]),
+            ];
+            for entry in app.library.lock().unwrap().entries_for(game) {
+                lines.push(Line::from(vec![
+                    "Owned:  ".dark_gray(),
+                    Span::styled(entry.path.display().to_string(), Style::new().fg(Color::Green)),
+                ]));
+                if let Some(serial) = &entry.serial {
+                    lines.push(Line::from(vec!["Serial: ".dark_gray(), Span::raw(serial.clone())]));
+                }
+            }
+            lines
+        }
+        None => vec![Line::from("No title selected".dark_gray())],
+    };
+    let details = Paragraph::new(lines)
+        .block(panel("Details".to_string(), false))
+        .wrap(Wrap { trim: false });
+    frame.render_widget(details, area);
+}
+
+fn draw_queue(frame: &mut Frame, app: &mut App, area: Rect) {
+    let items: Vec<ListItem> = app.jobs.iter().map(job_line).map(ListItem::new).collect();
+    let pending = app.jobs.iter().filter(|job| job.is_pending()).count();
+    let mut title = format!("Queue ({} pending)", pending);
+    if app.paused {
+        title.push_str(" [PAUSED]");
+    }
+    let list = List::new(items)
+        .block(panel(title, app.focus == Focus::Queue))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list, area, &mut app.queue_state);
+}
+
+/// One row of the queue panel: state, progress bar and title.
+fn job_line(job: &Job) -> Line<'static> {
+    let (state, color) = match &job.state {
+        JobState::Queued => ("queued".to_string(), Color::DarkGray),
+        JobState::Running(Stage::Download) => ("downloading".to_string(), Color::Yellow),
+        JobState::Running(Stage::Extract) => ("extracting".to_string(), Color::Yellow),
+        JobState::Running(Stage::Compress) => ("compressing".to_string(), Color::Yellow),
+        JobState::Running(Stage::Verify) => ("checking".to_string(), Color::Yellow),
+        JobState::Running(Stage::Patch) => ("patching".to_string(), Color::Yellow),
+        JobState::Done => ("done".to_string(), Color::Green),
+        JobState::AlreadyPresent => ("already present".to_string(), Color::Green),
+        JobState::Failed(e) => (format!("failed: {}", e), Color::Red),
+        JobState::Cancelled => ("cancelled".to_string(), Color::DarkGray),
+        JobState::Verifying => ("verifying".to_string(), Color::Yellow),
+        JobState::Verified => ("verified".to_string(), Color::Green),
+        JobState::VerifyFailed(e) => (format!("verify failed: {}", e), Color::Red),
+    };
+
+    let progress = match (&job.state, job.total) {
+        (JobState::Running(_), Some(total)) if total > 0 => {
+            let ratio = (job.bytes as f64 / total as f64).min(1.0);
+            let filled = (ratio * BAR_WIDTH as f64) as usize;
+            format!(
+                "[{}{}] {:>3}% {}/{} ",
+                "#".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled),
+                (ratio * 100.0) as u32,
+                format_size(job.bytes),
+                format_size(total)
+            )
+        }
+        (JobState::Running(_), _) => format!("{} ", format_size(job.bytes)),
+        _ => String::new(),
+    };
+
+    Line::from(vec![
+        Span::styled(format!("{:<12} ", state.split(':').next().unwrap_or("")), Style::new().fg(color)),
+        Span::raw(progress),
+        Span::raw(job.game.clean_title()),
+        Span::styled(
+            job.serial.as_ref().map(|serial| format!("  [{}]", serial)).unwrap_or_default(),
+            Style::new().fg(Color::DarkGray),
+        ),
+        Span::styled(
+            state.split_once(": ").map(|(_, e)| format!("  ({})", e)).unwrap_or_default(),
+            Style::new().fg(color),
+        ),
+    ])
+}
+
+fn draw_help(frame: &mut Frame, app: &App, area: Rect) {
+    let keys = match app.focus {
+        Focus::Search => "type to filter | Enter/Down: results | Tab: next panel | Esc: quit",
+        Focus::Results => "Enter/a: queue | /: search | p: pause/resume | Tab: next panel | q: quit",
+        Focus::Queue => "c: cancel | v: verify | p: pause/resume | Tab: next panel | q: quit",
+    };
+    let line = if app.status.is_empty() {
+        Line::from(keys.dark_gray())
+    } else {
+        Line::from(vec![Span::raw(app.status.clone()), "  |  ".dark_gray(), keys.dark_gray()])
+    };
+    frame.render_widget(Paragraph::new(line), area);
+}
@@ -0,0 +1,394 @@
+use crate::error::{Error, Result};
+use crate::iso9660::{read_disc_info, DiscInfo};
+use crate::opl::{opl_title, Media};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of every part but the last (1 GiB, as written by USBUtil and OPL).
+pub const PART_SIZE: u64 = 1 << 30;
+/// Name of the game list in the OPL root.
+pub const CFG_NAME: &str = "ul.cfg";
+/// Size of a ul.cfg record.
+const RECORD_SIZE: usize = 64;
+/// Bytes available for the game name in a record.
+const NAME_SIZE: usize = 32;
+/// Bytes available for the 'ul.' + serial image name in a record.
+const IMAGE_SIZE: usize = 15;
+
+/// A game listed in ul.cfg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UlEntry {
+    /// Name shown by OPL (at most 32 ASCII characters)
+    pub name: String,
+    /// Boot file serial, e.g. 'SLUS_209.46'
+    pub serial: String,
+    /// Number of parts
+    pub parts: u8,
+    /// CD or DVD
+    pub media: Media,
+}
+
+impl UlEntry {
+    /// Encodes the entry as a 64-byte ul.cfg record.
+    fn to_record(&self) -> [u8; RECORD_SIZE] {
+        let mut record = [0u8; RECORD_SIZE];
+        let name = self.name.as_bytes();
+        record[..name.len().min(NAME_SIZE)].copy_from_slice(&name[..name.len().min(NAME_SIZE)]);
+        // The image name keeps a terminating zero
+        let image = format!("ul.{}", self.serial);
+        let image = image.as_bytes();
+        let image_len = image.len().min(IMAGE_SIZE - 1);
+        record[NAME_SIZE..NAME_SIZE + image_len].copy_from_slice(&image[..image_len]);
+        record[47] = self.parts;
+        record[48] = match self.media {
+            Media::Cd => 0x12,
+            Media::Dvd => 0x14,
+        };
+        // Always 0x08 in files written by USBUtil; OPL writes it for compatibility too
+        record[53] = 0x08;
+        record
+    }
+
+    /// Decodes a 64-byte ul.cfg record.
+    fn from_record(record: &[u8]) -> Option<Self> {
+        let text = |bytes: &[u8]| {
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).to_string()
+        };
+        let image = text(&record[NAME_SIZE..NAME_SIZE + IMAGE_SIZE]);
+        Some(Self {
+            name: text(&record[..NAME_SIZE]),
+            serial: image.strip_prefix("ul.")?.to_string(),
+            parts: record[47],
+            media: if record[48] == 0x12 { Media::Cd } else { Media::Dvd },
+        })
+    }
+
+    /// Paths of the parts in the OPL root.
+    pub fn part_paths(&self, root: &Path) -> Vec<PathBuf> {
+        (0..self.parts)
+            .map(|part| root.join(part_name(&self.name, &self.serial, part)))
+            .collect()
+    }
+}
+
+/// Makes a title usable as a ul.cfg name: OPL's title rules, ASCII only.
+/// Returns the name and whether it was shortened.
+pub fn ul_name(title: &str) -> (String, bool) {
+    let ascii: String = title.chars().filter(char::is_ascii).collect();
+    opl_title(&ascii)
+}
+
+/// File name of a part: 'ul.<name crc>.<serial>.<part>'.
+pub fn part_name(name: &str, serial: &str, part: u8) -> String {
+    format!("ul.{:08X}.{}.{:02x}", usba_crc32(name), serial, part)
+}
+
+/// The checksum OPL uses in part names. It is not a standard CRC32: the table is built
+/// with the polynomial applied on the opposite branch and stored reversed, and the
+/// terminating zero byte of the name is included.
+pub fn usba_crc32(name: &str) -> u32 {
+    let mut table = [0i32; 256];
+    let mut crc: i32 = 0;
+    for index in 0..256 {
+        crc = (index as i32) << 24;
+        for _ in 0..8 {
+            crc = if crc < 0 {
+                crc.wrapping_shl(1)
+            } else {
+                crc.wrapping_shl(1) ^ 0x04C1_1DB7
+            };
+        }
+        table[255 - index] = crc;
+    }
+    // The last table value is the initial register, as in OPL
+    for &byte in name.as_bytes().iter().chain(std::iter::once(&0)) {
+        crc = table[(byte as i32 ^ ((crc >> 24) & 0xFF)) as usize] ^ ((crc << 8) & 0xFFFF_FF00u32 as i32);
+    }
+    crc as u32
+}
+
+/// Reads the games listed in ul.cfg; a missing file is an empty list.
+pub fn read_cfg(root: &Path) -> Result<Vec<UlEntry>> {
+    let path = root.join(CFG_NAME);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = fs::read(path)?;
+    Ok(data.chunks_exact(RECORD_SIZE).filter_map(UlEntry::from_record).collect())
+}
+
+/// Adds a game to ul.cfg, replacing an entry with the same serial.
+pub fn write_entry(root: &Path, entry: &UlEntry) -> Result<()> {
+    let mut entries = read_cfg(root)?;
+    entries.retain(|existing| existing.serial != entry.serial);
+    entries.push(entry.clone());
+    let data: Vec<u8> = entries.iter().flat_map(UlEntry::to_record).collect();
+    fs::write(root.join(CFG_NAME), data)?;
+    Ok(())
+}
+
+/// Writes an image as 1 GiB parts named after the game, without the serial yet.
+/// `install` renames them once the serial has been read from the first part.
+pub struct PartWriter {
+    root: PathBuf,
+    name: String,
+    parts: Vec<PathBuf>,
+    file: Option<File>,
+    written: u64,
+}
+
+impl PartWriter {
+    /// Starts writing parts for `name` into the OPL root.
+    pub fn new(root: &Path, name: &str) -> Result<Self> {
+        fs::create_dir_all(root)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            name: name.to_string(),
+            parts: Vec::new(),
+            file: None,
+            written: 0,
+        })
+    }
+
+    /// Flushes the last part and returns the paths of all parts.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        Ok(std::mem::take(&mut self.parts))
+    }
+
+    /// Removes the parts written so far.
+    pub fn discard(mut self) {
+        self.file = None;
+        for part in self.parts.drain(..) {
+            let _ = fs::remove_file(part);
+        }
+    }
+
+    /// Opens the next part.
+    fn next_part(&mut self) -> io::Result<()> {
+        let path = self.root.join(format!(
+            "ul.{:08X}.{:02x}.part",
+            usba_crc32(&self.name),
+            self.parts.len()
+        ));
+        self.file = Some(File::create(&path)?);
+        self.parts.push(path);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for PartWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() || self.written == PART_SIZE {
+            self.next_part()?;
+        }
+        let room = (PART_SIZE - self.written) as usize;
+        let count = buf.len().min(room);
+        let written = self.file.as_mut().unwrap().write(&buf[..count])?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads parts as one continuous image.
+pub struct SplitReader {
+    parts: Vec<(BufReader<File>, u64)>,
+    position: u64,
+    length: u64,
+}
+
+impl SplitReader {
+    /// Opens the parts in order.
+    pub fn open(paths: &[PathBuf]) -> Result<Self> {
+        let mut parts = Vec::with_capacity(paths.len());
+        let mut length = 0;
+        for path in paths {
+            let file = File::open(path)?;
+            let size = file.metadata()?.len();
+            length += size;
+            parts.push((BufReader::new(file), size));
+        }
+        Ok(Self {
+            parts,
+            position: 0,
+            length,
+        })
+    }
+}
+
+impl Read for SplitReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut offset = self.position;
+        for (file, size) in &mut self.parts {
+            if offset < *size {
+                file.seek(SeekFrom::Start(offset))?;
+                let count = buf.len().min((*size - offset) as usize);
+                let read = file.read(&mut buf[..count])?;
+                self.position += read as u64;
+                return Ok(read);
+            }
+            offset -= *size;
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for SplitReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.position)
+    }
+}
+
+/// Reads the serial from freshly written parts, renames them to their final names
+/// and lists the game in ul.cfg. Returns the entry and what was read from the image.
+/// The parts are removed if no serial is found.
+pub fn install(root: &Path, name: &str, parts: Vec<PathBuf>, media: Media) -> Result<(UlEntry, DiscInfo)> {
+    let disc = match read_disc_info(SplitReader::open(&parts)?) {
+        Ok(disc) => disc,
+        Err(e) => {
+            remove_parts(&parts);
+            return Err(e);
+        }
+    };
+    let Some(serial) = disc.serial.clone() else {
+        remove_parts(&parts);
+        return Err(Error::ExportFailed("no serial found in SYSTEM.CNF".to_string()));
+    };
+    let count = u8::try_from(parts.len())
+        .map_err(|_| Error::ExportFailed(format!("image needs {} parts, more than ul.cfg allows", parts.len())))?;
+
+    let entry = UlEntry {
+        name: name.to_string(),
+        serial,
+        parts: count,
+        media,
+    };
+    for (part, target) in parts.iter().zip(entry.part_paths(root)) {
+        fs::rename(part, target)?;
+    }
+    write_entry(root, &entry)?;
+    Ok((entry, disc))
+}
+
+/// Removes temporary parts after a failed install.
+fn remove_parts(parts: &[PathBuf]) {
+    for part in parts {
+        let _ = fs::remove_file(part);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder under the system temp folder, emptied for the test.
+    fn scratch(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("ps2rd-usbextreme-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn usba_crc32_matches_opl() {
+        // Values of OPL's USBA_crc32, which hashes the terminating zero too
+        assert_eq!(usba_crc32("Final Fantasy X"), 0x30C7_6171);
+        assert_eq!(part_name("Final Fantasy X", "SLUS_203.12", 1), "ul.30C76171.SLUS_203.12.01");
+    }
+
+    #[test]
+    fn record_layout() {
+        let entry = UlEntry {
+            name: "Final Fantasy X".to_string(),
+            serial: "SLUS_203.12".to_string(),
+            parts: 3,
+            media: Media::Dvd,
+        };
+        let record = entry.to_record();
+        assert_eq!(&record[..15], b"Final Fantasy X");
+        assert!(record[15..NAME_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(&record[NAME_SIZE..NAME_SIZE + 14], b"ul.SLUS_203.12");
+        assert_eq!(record[46], 0, "the image name keeps its terminating zero");
+        assert_eq!(record[47], 3);
+        assert_eq!(record[48], 0x14);
+        assert_eq!(record[53], 0x08);
+        assert_eq!(UlEntry::from_record(&record), Some(entry));
+
+        let cd = UlEntry { media: Media::Cd, ..UlEntry::from_record(&record).unwrap() };
+        assert_eq!(cd.to_record()[48], 0x12);
+        assert_eq!(UlEntry::from_record(&cd.to_record()).unwrap().media, Media::Cd);
+    }
+
+    #[test]
+    fn long_names_are_cut_to_the_record() {
+        let entry = UlEntry {
+            name: "A".repeat(40),
+            serial: "SLUS_203.12".to_string(),
+            parts: 1,
+            media: Media::Dvd,
+        };
+        assert_eq!(UlEntry::from_record(&entry.to_record()).unwrap().name, "A".repeat(NAME_SIZE));
+    }
+
+    #[test]
+    fn write_entry_replaces_the_same_serial() {
+        let root = scratch("cfg");
+        let entry = |name: &str, serial: &str| UlEntry {
+            name: name.to_string(),
+            serial: serial.to_string(),
+            parts: 1,
+            media: Media::Dvd,
+        };
+        write_entry(&root, &entry("First", "SLUS_203.12")).unwrap();
+        write_entry(&root, &entry("Second", "SLES_500.01")).unwrap();
+        write_entry(&root, &entry("Renamed", "SLUS_203.12")).unwrap();
+
+        assert_eq!(fs::metadata(root.join(CFG_NAME)).unwrap().len(), 2 * RECORD_SIZE as u64);
+        assert_eq!(read_cfg(&root).unwrap(), vec![entry("Second", "SLES_500.01"), entry("Renamed", "SLUS_203.12")]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn split_reader_reads_across_parts() {
+        let root = scratch("split");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let paths: Vec<PathBuf> = data
+            .chunks(4096)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let path = root.join(format!("part{}", i));
+                fs::write(&path, chunk).unwrap();
+                path
+            })
+            .collect();
+
+        let mut reader = SplitReader::open(&paths).unwrap();
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+
+        let mut window = [0u8; 100];
+        reader.seek(SeekFrom::Start(4050)).unwrap();
+        reader.read_exact(&mut window).unwrap();
+        assert_eq!(window[..], data[4050..4150]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config::Config;
-use anyhow::Result;
+use crate::error::Result;
 use std::fs;
+use std::io::{self, Read};
 use std::path::Path;
 
 /// Sets up the required folders for temporary files and ISO downloads.
@@ -15,13 +16,49 @@ fn check_folder(folder_path: &Path, folder_name: &str) -> Result<()> {
     if !folder_path.exists() {
         create_folder(folder_path, folder_name)?;
     } else if !folder_path.is_dir() {
-        anyhow::bail!("Please remove the file named as {}", folder_name);
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Please remove the file named as {}", folder_name),
+        )
+        .into());
     }
     Ok(())
 }
 
 /// Creates a folder and handles errors appropriately.
 fn create_folder(folder_path: &Path, folder_name: &str) -> Result<()> {
-    fs::create_dir_all(folder_path)
-        .map_err(|e| anyhow::anyhow!("Error creating '{}' folder: {}", folder_name, e))
+    fs::create_dir_all(folder_path).map_err(|e| {
+        io::Error::new(e.kind(), format!("Error creating '{}' folder: {}", folder_name, e)).into()
+    })
+}
+
+/// Formats a byte count for display (e.g. '2.10 GiB').
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+/// Computes the CRC32 of a file.
+pub fn crc32_file(path: &Path) -> Result<u32> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(hasher.finalize())
 }
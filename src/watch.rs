@@ -0,0 +1,153 @@
+use crate::error::Result;
+use crate::models::Game;
+use crate::search::filter_games;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A search query checked for new dumps by `watch`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSearch {
+    /// Query in the search syntax: every word must appear in the title
+    pub query: String,
+    /// When the search was saved (seconds since the Unix epoch)
+    pub added: u64,
+    /// When the search was last checked against a catalog (seconds since the Unix epoch)
+    pub checked: Option<u64>,
+    /// Clean titles matched by the last check, mapped to their displayed size
+    seen: BTreeMap<String, String>,
+}
+
+/// Games that appeared or changed for a saved search since its last check.
+#[derive(Debug, Clone)]
+pub struct WatchReport {
+    /// The saved search
+    pub query: String,
+    /// Matching games not matched by the previous check
+    pub new: Vec<Game>,
+    /// Matching games whose size changed since the previous check, with the previous size
+    pub changed: Vec<(Game, String)>,
+}
+
+impl WatchReport {
+    /// Clean titles of the new and changed games.
+    pub fn titles(&self) -> Vec<String> {
+        self.new
+            .iter()
+            .chain(self.changed.iter().map(|(game, _)| game))
+            .map(Game::clean_title)
+            .collect()
+    }
+}
+
+/// Persistent saved searches with the matches each one saw last.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SavedSearches {
+    /// Searches in the order they were saved
+    searches: Vec<SavedSearch>,
+}
+
+impl SavedSearches {
+    /// Loads the saved searches from `path`, or returns none if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+        } else {
+            Ok(SavedSearches::default())
+        }
+    }
+
+    /// Saves the searches and their snapshots to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Returns the saved searches in the order they were saved.
+    pub fn searches(&self) -> &[SavedSearch] {
+        &self.searches
+    }
+
+    /// Saves a query; its current matches in `games` become the snapshot, so only later dumps are reported.
+    /// Returns false if the query (compared case-insensitively, ignoring extra spaces) is already saved.
+    pub fn add(&mut self, query: &str, games: &[Game]) -> bool {
+        let query = normalize(query);
+        if query.is_empty() || self.position(&query).is_some() {
+            return false;
+        }
+        let now = unix_time();
+        self.searches.push(SavedSearch {
+            seen: snapshot(&query, games),
+            query,
+            added: now,
+            checked: Some(now),
+        });
+        true
+    }
+
+    /// Removes a saved query; returns false if it was not saved.
+    pub fn remove(&mut self, query: &str) -> bool {
+        match self.position(&normalize(query)) {
+            Some(index) => {
+                self.searches.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Compares every saved search with `games` and makes it the new snapshot.
+    /// Returns a report for each search with new or changed matches.
+    pub fn check(&mut self, games: &[Game]) -> Vec<WatchReport> {
+        let now = unix_time();
+        let mut reports = Vec::new();
+        for search in &mut self.searches {
+            let mut report = WatchReport {
+                query: search.query.clone(),
+                new: Vec::new(),
+                changed: Vec::new(),
+            };
+            for game in filter_games(games, &search.query) {
+                match search.seen.get(&game.clean_title()) {
+                    None => report.new.push(game.clone()),
+                    Some(size) if *size != game.size => report.changed.push((game.clone(), size.clone())),
+                    Some(_) => {}
+                }
+            }
+            search.seen = snapshot(&search.query, games);
+            search.checked = Some(now);
+            if !report.new.is_empty() || !report.changed.is_empty() {
+                reports.push(report);
+            }
+        }
+        reports
+    }
+
+    fn position(&self, query: &str) -> Option<usize> {
+        self.searches
+            .iter()
+            .position(|search| search.query.eq_ignore_ascii_case(query))
+    }
+}
+
+/// Current matches of `query`: clean title mapped to displayed size.
+fn snapshot(query: &str, games: &[Game]) -> BTreeMap<String, String> {
+    filter_games(games, query)
+        .into_iter()
+        .map(|game| (game.clean_title(), game.size.clone()))
+        .collect()
+}
+
+/// Collapses runs of whitespace so equivalent queries are saved once.
+fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,97 @@
+use crate::error::Result;
+use crate::models::Game;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A title the user wants to download later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WishlistEntry {
+    /// Clean Redump title, e.g. 'Final Fantasy X (USA)'
+    pub title: String,
+    /// When the title was added (seconds since the Unix epoch)
+    pub added: u64,
+}
+
+/// Persistent list of wanted titles, keyed by Redump title (compared case-insensitively).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Wishlist {
+    /// Wanted titles in the order they were added
+    entries: Vec<WishlistEntry>,
+}
+
+impl Wishlist {
+    /// Loads the wishlist from `path`, or returns an empty one if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+        } else {
+            Ok(Wishlist::default())
+        }
+    }
+
+    /// Saves the wishlist to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Returns the wanted titles in the order they were added.
+    pub fn entries(&self) -> &[WishlistEntry] {
+        &self.entries
+    }
+
+    /// Returns true if this clean title is on the wishlist.
+    pub fn contains_title(&self, title: &str) -> bool {
+        self.position(title).is_some()
+    }
+
+    /// Returns true if this game is on the wishlist.
+    pub fn contains(&self, game: &Game) -> bool {
+        self.contains_title(&game.clean_title())
+    }
+
+    /// Adds a clean title; returns false if it was already on the wishlist.
+    pub fn add(&mut self, title: &str) -> bool {
+        if self.contains_title(title) {
+            return false;
+        }
+        let added = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        self.entries.push(WishlistEntry { title: title.to_string(), added });
+        true
+    }
+
+    /// Removes a clean title; returns false if it was not on the wishlist.
+    pub fn remove(&mut self, title: &str) -> bool {
+        match self.position(title) {
+            Some(index) => {
+                self.entries.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the catalog games on the wishlist, in wishlist order, and the titles missing from the catalog.
+    pub fn resolve<'a>(&self, games: &'a [Game]) -> (Vec<&'a Game>, Vec<String>) {
+        let mut found = Vec::new();
+        let mut missing = Vec::new();
+        for entry in &self.entries {
+            let title = entry.title.to_lowercase();
+            match games.iter().find(|game| game.clean_title().to_lowercase() == title) {
+                Some(game) => found.push(game),
+                None => missing.push(entry.title.clone()),
+            }
+        }
+        (found, missing)
+    }
+
+    fn position(&self, title: &str) -> Option<usize> {
+        let title = title.to_lowercase();
+        self.entries.iter().position(|entry| entry.title.to_lowercase() == title)
+    }
+}
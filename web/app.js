@@ -0,0 +1,310 @@
+// Web UI for `ps2-redump-downloader serve`. Talks to the JSON API and follows /api/events for live progress.
+"use strict";
+
+const TOKEN_KEY = "ps2rd-token";
+const STAGES = {
+  download: "Downloading",
+  extract: "Extracting",
+  compress: "Compressing",
+  verify: "Verifying",
+  patch: "Patching",
+};
+const STATUSES = {
+  queued: "Queued",
+  running: "Running",
+  downloaded: "Downloaded",
+  already_present: "Already present",
+  failed: "Failed",
+  cancelled: "Cancelled",
+};
+
+const jobs = new Map();
+let token = localStorage.getItem(TOKEN_KEY) || "";
+let events = null;
+let searchTimer = null;
+let libraryEntries = [];
+
+const $ = (id) => document.getElementById(id);
+
+// Creates an element with text content and optional class.
+function element(tag, text, className) {
+  const node = document.createElement(tag);
+  if (text !== undefined && text !== null) node.textContent = text;
+  if (className) node.className = className;
+  return node;
+}
+
+function formatSize(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let size = bytes;
+  let unit = 0;
+  while (size >= 1024 && unit < units.length - 1) {
+    size /= 1024;
+    unit += 1;
+  }
+  return `${size.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+// Calls the API; a 401 answer shows the token form.
+async function api(path, options = {}) {
+  const headers = { ...(options.headers || {}) };
+  if (token) headers.Authorization = `Bearer ${token}`;
+  if (options.body) headers["Content-Type"] = "application/json";
+  const response = await fetch(path, { ...options, headers });
+  if (response.status === 401) {
+    showLogin("The token was not accepted.");
+    throw new Error("unauthorized");
+  }
+  const body = await response.json();
+  if (!response.ok) throw new Error(body.error || response.statusText);
+  return body;
+}
+
+function showLogin(message) {
+  $("login").hidden = false;
+  document.querySelector("main").hidden = true;
+  $("login-error").textContent = token ? message : "";
+  if (events) events.close();
+  setConnection(false);
+}
+
+function setConnection(live) {
+  $("connection").textContent = live ? "live" : "offline";
+  $("connection").classList.toggle("live", live);
+}
+
+function pendingJob(title) {
+  for (const job of jobs.values()) {
+    if (job.title === title && (job.status === "queued" || job.status === "running")) return job;
+  }
+  return null;
+}
+
+// Catalog
+
+async function search() {
+  const params = new URLSearchParams({ q: $("search").value, limit: "100" });
+  if ($("region").value) params.set("region", $("region").value);
+  if ($("owned").value) params.set("owned", $("owned").value);
+  try {
+    const catalog = await api(`/api/catalog?${params}`);
+    renderCatalog(catalog);
+  } catch (error) {
+    $("catalog-summary").textContent = error.message;
+  }
+}
+
+function renderCatalog(catalog) {
+  const shown = catalog.results.length;
+  $("catalog-summary").textContent =
+    shown < catalog.total ? `Showing ${shown} of ${catalog.total} titles` : `${catalog.total} titles`;
+  const rows = catalog.results.map((game) => {
+    const row = element("tr");
+    row.dataset.title = game.title;
+    row.append(element("td", game.title), element("td", game.region || ""), element("td", game.size));
+    const action = element("td", null, "action");
+    if (game.owned) action.append(element("span", "Owned", "badge owned"), " ");
+    const button = element("button", null, "primary");
+    button.addEventListener("click", () => enqueue(game.title));
+    action.append(button);
+    row.append(action);
+    updateQueueButton(row);
+    return row;
+  });
+  $("catalog-results").replaceChildren(...rows);
+}
+
+// Disables the queue button of a catalog row while the title is queued or running.
+function updateQueueButton(row) {
+  const button = row.querySelector("button");
+  const job = pendingJob(row.dataset.title);
+  button.disabled = job !== null;
+  button.textContent = job ? STATUSES[job.status] : "Queue";
+}
+
+function refreshCatalogButtons() {
+  document.querySelectorAll("#catalog-results tr").forEach(updateQueueButton);
+}
+
+async function enqueue(title) {
+  try {
+    const queued = await api("/api/jobs", { method: "POST", body: JSON.stringify({ titles: [title] }) });
+    queued.forEach(updateJob);
+  } catch (error) {
+    alert(`Could not queue ${title}: ${error.message}`);
+  }
+}
+
+// Queue
+
+function updateJob(job) {
+  jobs.set(job.id, { ...jobs.get(job.id), ...job });
+  renderJobs();
+  refreshCatalogButtons();
+  if (job.status === "downloaded") loadLibrary();
+}
+
+function renderJobs() {
+  const list = [...jobs.values()].sort((a, b) => b.id - a.id);
+  const pending = list.filter((job) => job.status === "queued" || job.status === "running").length;
+  $("queue-count").textContent = pending ? `(${pending})` : "";
+  $("queue-summary").textContent = list.length ? `${pending} of ${list.length} jobs pending` : "Nothing queued yet.";
+  $("jobs").replaceChildren(...list.map(renderJob));
+}
+
+function renderJob(job) {
+  const item = element("li");
+  const head = element("div", null, "job-head");
+  const label = job.status === "running" && job.stage ? STAGES[job.stage] : STATUSES[job.status];
+  head.append(element("span", job.title, "title"), element("span", label, `badge ${job.status}`));
+  if (job.status === "queued" || job.status === "running") {
+    const cancel = element("button", "Cancel");
+    cancel.addEventListener("click", () => cancelJob(job.id));
+    head.append(cancel);
+  }
+  item.append(head);
+
+  if (job.status === "running") {
+    const bar = element("progress");
+    if (job.total) {
+      bar.max = job.total;
+      bar.value = job.bytes;
+    }
+    const detail = job.total
+      ? `${formatSize(job.bytes)} of ${formatSize(job.total)} (${Math.floor((job.bytes / job.total) * 100)}%)`
+      : job.bytes ? formatSize(job.bytes) : "Starting...";
+    item.append(bar, element("div", detail, "job-detail"));
+  } else if (job.error) {
+    item.append(element("div", job.error, "job-detail error"));
+  } else if (job.path) {
+    const serial = job.serial ? ` [${job.serial}]` : "";
+    item.append(element("div", `${job.path}${serial}`, "job-detail"));
+  }
+  return item;
+}
+
+async function cancelJob(id) {
+  try {
+    updateJob(await api(`/api/jobs/${id}`, { method: "DELETE" }));
+  } catch (error) {
+    alert(error.message);
+  }
+}
+
+async function loadJobs() {
+  const list = await api("/api/jobs");
+  jobs.clear();
+  list.forEach((job) => jobs.set(job.id, job));
+  renderJobs();
+  refreshCatalogButtons();
+}
+
+// Library
+
+async function loadLibrary() {
+  try {
+    libraryEntries = await api("/api/library");
+    renderLibrary();
+  } catch (error) {
+    $("library-summary").textContent = error.message;
+  }
+}
+
+function renderLibrary() {
+  const words = $("library-search").value.toLowerCase().split(/\s+/).filter(Boolean);
+  const entries = libraryEntries
+    .filter((entry) => words.every((word) => entry.title.toLowerCase().includes(word)))
+    .sort((a, b) => a.title.localeCompare(b.title));
+  const total = entries.reduce((sum, entry) => sum + entry.size, 0);
+  $("library-summary").textContent = `${entries.length} images, ${formatSize(total)}`;
+  $("library-entries").replaceChildren(
+    ...entries.map((entry) => {
+      const row = element("tr");
+      row.append(
+        element("td", entry.title),
+        element("td", entry.serial || ""),
+        element("td", entry.format.toUpperCase()),
+        element("td", formatSize(entry.size)),
+        element("td", entry.path, "path"),
+      );
+      return row;
+    }),
+  );
+}
+
+// Live updates
+
+function connectEvents() {
+  if (events) events.close();
+  const query = token ? `?token=${encodeURIComponent(token)}` : "";
+  events = new EventSource(`/api/events${query}`);
+  events.onopen = () => {
+    setConnection(true);
+    // Catch up on anything missed while disconnected
+    loadJobs().catch(() => {});
+  };
+  events.onerror = () => setConnection(false);
+  events.addEventListener("job", (message) => updateJob(JSON.parse(message.data)));
+  events.onmessage = (message) => {
+    const event = JSON.parse(message.data);
+    if (event.event !== "progress") return;
+    for (const job of jobs.values()) {
+      if (job.status === "running" && job.title === event.title) {
+        Object.assign(job, { stage: event.stage, bytes: event.bytes, total: event.total });
+        renderJobs();
+        refreshCatalogButtons();
+      }
+    }
+  };
+}
+
+// Start-up
+
+async function start() {
+  try {
+    await loadJobs();
+  } catch (error) {
+    if (error.message !== "unauthorized") $("catalog-summary").textContent = error.message;
+    return;
+  }
+  $("login").hidden = true;
+  document.querySelector("main").hidden = false;
+  connectEvents();
+  search();
+  loadLibrary();
+}
+
+document.querySelectorAll("nav button").forEach((button) => {
+  button.addEventListener("click", () => {
+    document.querySelectorAll("nav button").forEach((other) => other.classList.toggle("active", other === button));
+    document.querySelectorAll("main section").forEach((section) => {
+      section.hidden = section.id !== button.dataset.tab;
+    });
+    if (button.dataset.tab === "library") loadLibrary();
+  });
+});
+
+$("search").addEventListener("input", () => {
+  clearTimeout(searchTimer);
+  searchTimer = setTimeout(search, 250);
+});
+$("region").addEventListener("change", search);
+$("owned").addEventListener("change", search);
+$("library-search").addEventListener("input", renderLibrary);
+
+$("login").addEventListener("submit", (submit) => {
+  submit.preventDefault();
+  token = $("token").value.trim();
+  localStorage.setItem(TOKEN_KEY, token);
+  start();
+});
+
+// A token in the page URL (e.g. a shared bookmark) is remembered and removed from the address bar
+const urlToken = new URLSearchParams(location.search).get("token");
+if (urlToken) {
+  token = urlToken;
+  localStorage.setItem(TOKEN_KEY, token);
+  history.replaceState(null, "", location.pathname);
+}
+
+start();